@@ -406,8 +406,8 @@ impl FRMPayload {
             MType::UnconfirmedDown => Self::UnconfirmedDown(Payload::read(reader)?),
             MType::ConfirmedUp => Self::ConfirmedUp(Payload::read(reader)?),
             MType::ConfirmedDown => Self::ConfirmedDown(Payload::read(reader)?),
-            MType::Invalid(v) => return Err(LoraWanError::InvalidPacketType(v)),
-            _ => unreachable!(),
+            // Join and proprietary frames do not carry an FRMPayload
+            other => return Err(LoraWanError::InvalidPacketType(other.into())),
         };
         Ok(res)
     }
@@ -530,4 +530,17 @@ mod test {
         payload_a.write(&mut data_b).unwrap();
         assert_eq!(data_a, data_b);
     }
+
+    #[test]
+    fn test_read_non_data_frame_payload() {
+        // A fport followed by payload bytes read as a join request frame
+        let data = [0u8; 12];
+        for mtype in [MType::JoinRequest, MType::JoinAccept, MType::Proprietary] {
+            assert!(matches!(
+                MACPayload::read(mtype, Direction::Uplink, &mut &data[..]),
+                Err(LoraWanError::InvalidPacketType(_))
+            ));
+            assert!(FRMPayload::read(mtype, &mut &data[..]).is_err());
+        }
+    }
 }
@@ -0,0 +1,148 @@
+use crate::{error::RegionError, Region, Result};
+use helium_proto::{DataRate, Region as ProtoRegion};
+use std::time::Duration;
+
+/// Preamble length in symbols used by all LoRaWAN regions
+const PREAMBLE_SYMBOLS: u64 = 8;
+/// Coding rate 4/5 used by all LoRaWAN regions, as the `CR` of 4/(4 + CR)
+const CODING_RATE: u64 = 1;
+/// Symbol time in microseconds at or above which low data rate optimization is
+/// enabled
+const LOW_DATA_RATE_SYMBOL_TIME: u64 = 16_000;
+
+/// Returns the time on air of a LoRa packet with the given data rate and
+/// physical payload length in the given region.
+///
+/// Uses the LoRaWAN parameters of an explicit header, a payload CRC and a 4/5
+/// coding rate. Downlinks are sent without a payload CRC, so this slightly
+/// overestimates their airtime. Data rates that the region does not use,
+/// including FSK and LR-FHSS, are rejected.
+pub fn airtime(data_rate: DataRate, payload_len: usize, region: &Region) -> Result<Duration> {
+    let (sf, bw_khz) = lora_params(data_rate, region)
+        .ok_or_else(|| RegionError::unsupported_data_rate(data_rate))?;
+    // Symbol time in microseconds, exact for all LoRaWAN bandwidths
+    let symbol_time = (1u64 << sf) * 1000 / bw_khz;
+    let low_data_rate = u64::from(symbol_time >= LOW_DATA_RATE_SYMBOL_TIME);
+
+    let payload_bits = (8 * payload_len as i64) - (4 * sf as i64) + 28 + 16;
+    let symbol_bits = 4 * (sf - 2 * low_data_rate) as i64;
+    let payload_symbols = if payload_bits > 0 {
+        // integer div/ceil of positive values
+        ((payload_bits + symbol_bits - 1) / symbol_bits) as u64 * (CODING_RATE + 4)
+    } else {
+        0
+    };
+    // A preamble of 4.25 symbols more than the programmed length, in quarter
+    // symbols to keep the arithmetic exact
+    let preamble_time = (4 * PREAMBLE_SYMBOLS + 17) * symbol_time / 4;
+    let payload_time = (8 + payload_symbols) * symbol_time;
+    Ok(Duration::from_micros(preamble_time + payload_time))
+}
+
+/// The spreading factor and bandwidth in kHz of a LoRa data rate, if the given
+/// region uses it. Only US915 and AU915 use 500 kHz channels, and only the
+/// other regions use 250 kHz channels.
+fn lora_params(data_rate: DataRate, region: &Region) -> Option<(u64, u64)> {
+    let (sf, bw_khz) = match data_rate {
+        DataRate::Sf12bw125 => (12, 125),
+        DataRate::Sf11bw125 => (11, 125),
+        DataRate::Sf10bw125 => (10, 125),
+        DataRate::Sf9bw125 => (9, 125),
+        DataRate::Sf8bw125 => (8, 125),
+        DataRate::Sf7bw125 => (7, 125),
+        DataRate::Sf12bw250 => (12, 250),
+        DataRate::Sf11bw250 => (11, 250),
+        DataRate::Sf10bw250 => (10, 250),
+        DataRate::Sf9bw250 => (9, 250),
+        DataRate::Sf8bw250 => (8, 250),
+        DataRate::Sf7bw250 => (7, 250),
+        DataRate::Sf12bw500 => (12, 500),
+        DataRate::Sf11bw500 => (11, 500),
+        DataRate::Sf10bw500 => (10, 500),
+        DataRate::Sf9bw500 => (9, 500),
+        DataRate::Sf8bw500 => (8, 500),
+        DataRate::Sf7bw500 => (7, 500),
+        _ => return None,
+    };
+    let wide_channels = matches!(
+        ProtoRegion::from(*region),
+        ProtoRegion::Us915 | ProtoRegion::Au915
+    );
+    match bw_khz {
+        500 if !wide_channels => None,
+        250 if wide_channels => None,
+        _ => Some((sf, bw_khz)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mk_region(region: ProtoRegion) -> Region {
+        Region::from_i32(region.into()).expect("region")
+    }
+
+    fn airtime_micros(data_rate: DataRate, payload_len: usize, region: ProtoRegion) -> u128 {
+        airtime(data_rate, payload_len, &mk_region(region))
+            .expect("airtime")
+            .as_micros()
+    }
+
+    #[test]
+    fn known_airtimes() {
+        // Airtimes per Semtech AN1200.13 of a 13 byte LoRaWAN frame without
+        // application payload and of a 51 byte frame
+        assert_eq!(
+            46_336,
+            airtime_micros(DataRate::Sf7bw125, 13, ProtoRegion::Eu868)
+        );
+        assert_eq!(
+            102_656,
+            airtime_micros(DataRate::Sf7bw125, 51, ProtoRegion::Eu868)
+        );
+        assert_eq!(
+            164_864,
+            airtime_micros(DataRate::Sf9bw125, 13, ProtoRegion::Eu868)
+        );
+        assert_eq!(
+            288_768,
+            airtime_micros(DataRate::Sf10bw125, 13, ProtoRegion::Us915)
+        );
+        // Low data rate optimization at SF11 and SF12 on 125 kHz
+        assert_eq!(
+            577_536,
+            airtime_micros(DataRate::Sf11bw125, 13, ProtoRegion::Eu868)
+        );
+        assert_eq!(
+            1_155_072,
+            airtime_micros(DataRate::Sf12bw125, 13, ProtoRegion::Eu868)
+        );
+        assert_eq!(
+            2_465_792,
+            airtime_micros(DataRate::Sf12bw125, 51, ProtoRegion::Eu868)
+        );
+        // Wider channels
+        assert_eq!(
+            23_168,
+            airtime_micros(DataRate::Sf7bw250, 13, ProtoRegion::Eu868)
+        );
+        assert_eq!(
+            20_608,
+            airtime_micros(DataRate::Sf8bw500, 13, ProtoRegion::Us915)
+        );
+        assert_eq!(
+            288_768,
+            airtime_micros(DataRate::Sf12bw500, 13, ProtoRegion::Au915)
+        );
+    }
+
+    #[test]
+    fn region_data_rates() {
+        let eu868 = mk_region(ProtoRegion::Eu868);
+        let us915 = mk_region(ProtoRegion::Us915);
+        assert!(airtime(DataRate::Sf8bw500, 13, &eu868).is_err());
+        assert!(airtime(DataRate::Sf7bw250, 13, &us915).is_err());
+        assert!(airtime(DataRate::Fsk50, 13, &eu868).is_err());
+    }
+}
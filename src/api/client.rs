@@ -1,6 +1,6 @@
 use super::{
     connect_uri, AddGatewayReq, ConfigReq, ConfigValue, GatewayStakingMode, HeightReq, HeightRes,
-    PubkeyReq, RegionReq, SignReq,
+    PubkeyReq, RegionReq, SignReq, CONFIG_TYPE_JSON,
 };
 use crate::{error::Error, settings::StakingMode, PublicKey, Region, Result, TxnEnvelope};
 use helium_proto::{services::local::Client, BlockchainTxnAddGatewayV1};
@@ -44,6 +44,18 @@ impl LocalClient {
         Ok(response.values)
     }
 
+    /// Fetches a config key answered by the local server itself, see
+    /// `LOCAL_CONFIG_PREFIX`
+    pub async fn local_config(&mut self, key: &str) -> Result<serde_json::Value> {
+        let value = self
+            .config(&[key])
+            .await?
+            .into_iter()
+            .find(|value| value.name == key && value.r#type == CONFIG_TYPE_JSON)
+            .ok_or_else(|| Error::custom(format!("no local config value: {key}")))?;
+        Ok(serde_json::from_slice(&value.value)?)
+    }
+
     pub async fn height(&mut self) -> Result<HeightRes> {
         let response = self.client.height(HeightReq {}).await?.into_inner();
         Ok(response)
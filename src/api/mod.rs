@@ -3,6 +3,19 @@ mod server;
 
 const LISTEN_ADDR: &str = "127.0.0.1";
 
+/// Config keys with this prefix are answered by the local server from the
+/// gateway's own state instead of being looked up as chain variables. Their
+/// values are JSON encoded.
+pub const LOCAL_CONFIG_PREFIX: &str = "gateway.";
+/// The channel plan of the active region params
+pub const CONFIG_CHANNEL_PLAN: &str = "gateway.channel_plan";
+/// The downlinks pending transmission, ordered by transmit time
+pub const CONFIG_DOWNLINK_SCHEDULE: &str = "gateway.downlink_schedule";
+/// Drops the packets queued for all routers when read
+pub const CONFIG_FLUSH_QUEUES: &str = "gateway.flush_queues";
+/// Config value type of the local config keys
+pub const CONFIG_TYPE_JSON: &str = "json";
+
 pub use client::LocalClient;
 pub use helium_proto::{
     services::local::{
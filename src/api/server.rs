@@ -1,10 +1,16 @@
 use super::{
     listen_addr, AddGatewayReq, AddGatewayRes, ConfigReq, ConfigRes, ConfigValue, EcdhReq, EcdhRes,
     HeightReq, HeightRes, PubkeyReq, PubkeyRes, RegionReq, RegionRes, SignReq, SignRes,
+    CONFIG_CHANNEL_PLAN, CONFIG_DOWNLINK_SCHEDULE, CONFIG_FLUSH_QUEUES, CONFIG_TYPE_JSON,
+    LOCAL_CONFIG_PREFIX,
 };
 use crate::{
-    router::dispatcher, settings::StakingMode, Error, Keypair, PublicKey, Result, Settings,
-    TxnEnvelope, TxnFee, TxnFeeConfig, CONFIG_FEE_KEYS,
+    gateway::DownlinkScheduleReceiver,
+    health::{self, HealthReceiver},
+    router::dispatcher,
+    settings::StakingMode,
+    Error, Keypair, PublicKey, Result, Settings, TxnEnvelope, TxnFee, TxnFeeConfig,
+    CONFIG_FEE_KEYS,
 };
 use futures::TryFutureExt;
 use helium_crypto::Sign;
@@ -13,19 +19,29 @@ use helium_proto::{BlockchainTxnAddGatewayV1, Message};
 use slog::{info, o, Logger};
 use std::sync::Arc;
 use tonic::{self, transport::Server as TransportServer, Request, Response, Status};
+use tonic_health::{server::HealthReporter, ServingStatus};
 
 pub type ApiResult<T> = std::result::Result<Response<T>, Status>;
 
 pub struct LocalServer {
     dispatcher: dispatcher::MessageSender,
+    health: HealthReceiver,
+    downlink_schedule: DownlinkScheduleReceiver,
     keypair: Arc<Keypair>,
     onboarding_key: PublicKey,
     listen_port: u16,
 }
 
 impl LocalServer {
-    pub fn new(dispatcher: dispatcher::MessageSender, settings: &Settings) -> Result<Self> {
+    pub fn new(
+        dispatcher: dispatcher::MessageSender,
+        health: HealthReceiver,
+        downlink_schedule: DownlinkScheduleReceiver,
+        settings: &Settings,
+    ) -> Result<Self> {
         Ok(Self {
+            health,
+            downlink_schedule,
             keypair: settings.keypair.clone(),
             onboarding_key: settings.onboarding_key(),
             listen_port: settings.api,
@@ -37,26 +53,99 @@ impl LocalServer {
         let addr = listen_addr(self.listen_port).parse().unwrap();
         let logger = logger.new(o!("module" => "api", "listen" => addr));
         info!(logger, "starting");
+        let (mut health_reporter, health_service) = tonic_health::server::health_reporter();
+        health_reporter
+            .set_service_status(health::LIVENESS_SERVICE, ServingStatus::Serving)
+            .await;
+        tokio::spawn(Self::report_readiness(
+            health_reporter,
+            self.health.clone(),
+            shutdown.clone(),
+        ));
         TransportServer::builder()
+            .add_service(health_service)
             .add_service(Server::new(self))
             .serve_with_shutdown(addr, shutdown)
             .map_err(Error::from)
             .await
     }
 
+    async fn report_readiness(
+        mut reporter: HealthReporter,
+        mut health: HealthReceiver,
+        shutdown: triggered::Listener,
+    ) {
+        loop {
+            let status = if health.borrow().is_ready() {
+                ServingStatus::Serving
+            } else {
+                ServingStatus::NotServing
+            };
+            reporter
+                .set_service_status(health::READINESS_SERVICE, status)
+                .await;
+            tokio::select! {
+                _ = shutdown.clone() => return,
+                changed = health.changed() => if changed.is_err() {
+                    return
+                }
+            }
+        }
+    }
+
     async fn _get_config<T>(&self, keys: &[T]) -> std::result::Result<Vec<ConfigValue>, Status>
     where
         T: ToString,
     {
-        let keys = keys.iter().map(|s| s.to_string()).collect::<Vec<String>>();
-        let reply = self
-            .dispatcher
-            .config(&keys)
-            .map_err(|err| Status::internal(format!("{err}")))
-            .await?;
-        let values = reply.into_iter().map(ConfigValue::from).collect();
+        let (local_keys, chain_keys): (Vec<String>, Vec<String>) = keys
+            .iter()
+            .map(|s| s.to_string())
+            .partition(|key| key.starts_with(LOCAL_CONFIG_PREFIX));
+        let mut values = Vec::with_capacity(local_keys.len() + chain_keys.len());
+        for key in local_keys {
+            values.push(self._get_local_config(key).await?);
+        }
+        if !chain_keys.is_empty() {
+            let reply = self
+                .dispatcher
+                .config(&chain_keys)
+                .map_err(|err| Status::internal(format!("{err}")))
+                .await?;
+            values.extend(reply.into_iter().map(ConfigValue::from));
+        }
         Ok(values)
     }
+
+    async fn _get_local_config(&self, key: String) -> std::result::Result<ConfigValue, Status> {
+        let value = match key.as_str() {
+            CONFIG_CHANNEL_PLAN => {
+                let plan = self
+                    .dispatcher
+                    .channel_plan()
+                    .map_err(|err| Status::internal(format!("{err}")))
+                    .await?;
+                serde_json::to_vec(&plan)
+            }
+            CONFIG_DOWNLINK_SCHEDULE => {
+                let schedule = self.downlink_schedule.borrow().clone();
+                serde_json::to_vec(&schedule)
+            }
+            CONFIG_FLUSH_QUEUES => {
+                self.dispatcher
+                    .flush_drop()
+                    .map_err(|err| Status::internal(format!("{err}")))
+                    .await?;
+                serde_json::to_vec(&true)
+            }
+            _ => return Err(Status::invalid_argument(format!("Unknown key: {key}"))),
+        }
+        .map_err(|_err| Status::internal("Failed to encode value"))?;
+        Ok(ConfigValue {
+            name: key,
+            r#type: CONFIG_TYPE_JSON.to_string(),
+            value,
+        })
+    }
 }
 
 #[tonic::async_trait]
@@ -161,3 +250,204 @@ impl Api for LocalServer {
         }))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{gateway::DownlinkSchedule, ChannelPlan, Region};
+    use std::time::Duration;
+    use tokio::sync::watch;
+    use tonic::transport::Channel;
+    use tonic_health::proto::{
+        health_check_response::ServingStatus as CheckStatus, health_client::HealthClient,
+        HealthCheckRequest,
+    };
+
+    /// Waits for the given health service to report the given status
+    async fn wait_for(client: &mut HealthClient<Channel>, service: &str, status: CheckStatus) {
+        let check = async {
+            loop {
+                let reported = client
+                    .check(HealthCheckRequest {
+                        service: service.to_string(),
+                    })
+                    .await
+                    .map(|response| response.into_inner().status);
+                if matches!(reported, Ok(reported) if reported == status as i32) {
+                    return;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(1), check)
+            .await
+            .unwrap_or_else(|_| panic!("{service} not {status:?}"));
+    }
+
+    fn mk_schedule() -> DownlinkScheduleReceiver {
+        watch::channel(DownlinkSchedule::default()).1
+    }
+
+    /// Answers the dispatcher queries of the local config with a fixed
+    /// channel plan and echoed chain variables
+    fn answer_config(mut messages: dispatcher::MessageReceiver, plan: ChannelPlan) {
+        let logger = Logger::root(slog::Discard, o!());
+        tokio::spawn(async move {
+            while let Some(message) = messages.recv().await {
+                match message {
+                    dispatcher::Message::ChannelPlan { response } => {
+                        response.send(Ok(plan.clone()), &logger)
+                    }
+                    dispatcher::Message::Config { keys, response } => {
+                        let vars = keys
+                            .into_iter()
+                            .map(|name| helium_proto::BlockchainVarV1 {
+                                name,
+                                r#type: "int".to_string(),
+                                value: b"1".to_vec(),
+                            })
+                            .collect();
+                        response.send(Ok(vars), &logger)
+                    }
+                    other => panic!("unexpected dispatcher message: {other:?}"),
+                }
+            }
+        });
+    }
+
+    #[tokio::test]
+    async fn local_config() {
+        let settings = crate::settings::mk_test_settings();
+        let (dispatcher, messages) = dispatcher::message_channel(1);
+        let (_health, health_rx) = health::health_channel();
+        let server = LocalServer::new(dispatcher, health_rx, mk_schedule(), &settings)
+            .expect("local server");
+        let region = Region::from_i32(helium_proto::Region::Eu868.into()).expect("region");
+        answer_config(
+            messages,
+            ChannelPlan {
+                region,
+                channels: vec![],
+            },
+        );
+
+        // Local keys are answered as json, chain variables are forwarded
+        let keys = vec![CONFIG_CHANNEL_PLAN.to_string(), "txn_fees".to_string()];
+        let values = server
+            .config(Request::new(ConfigReq { keys }))
+            .await
+            .expect("config")
+            .into_inner()
+            .values;
+        assert_eq!(
+            vec![CONFIG_CHANNEL_PLAN, "txn_fees"],
+            values
+                .iter()
+                .map(|value| value.name.as_str())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(CONFIG_TYPE_JSON, values[0].r#type);
+        let plan: serde_json::Value = serde_json::from_slice(&values[0].value).expect("json");
+        assert_eq!(
+            serde_json::json!({ "region": region.to_string(), "channels": [] }),
+            plan
+        );
+        assert_eq!("int", values[1].r#type);
+
+        let values = server
+            .config(Request::new(ConfigReq {
+                keys: vec![CONFIG_DOWNLINK_SCHEDULE.to_string()],
+            }))
+            .await
+            .expect("config")
+            .into_inner()
+            .values;
+        let schedule: serde_json::Value = serde_json::from_slice(&values[0].value).expect("json");
+        assert_eq!(serde_json::json!({ "pending": [] }), schedule);
+
+        let unknown = server
+            .config(Request::new(ConfigReq {
+                keys: vec![format!("{LOCAL_CONFIG_PREFIX}unknown")],
+            }))
+            .await
+            .expect_err("unknown key");
+        assert_eq!(tonic::Code::InvalidArgument, unknown.code());
+    }
+
+    #[tokio::test]
+    async fn local_flush() {
+        let settings = crate::settings::mk_test_settings();
+        let (dispatcher, mut messages) = dispatcher::message_channel(1);
+        let (_health, health_rx) = health::health_channel();
+        let server = LocalServer::new(dispatcher, health_rx, mk_schedule(), &settings)
+            .expect("local server");
+
+        let keys = vec![CONFIG_FLUSH_QUEUES.to_string()];
+        let (reply, message) = tokio::join!(
+            server.config(Request::new(ConfigReq { keys })),
+            messages.recv()
+        );
+        assert!(matches!(message, Some(dispatcher::Message::FlushDrop)));
+        let values = reply.expect("config").into_inner().values;
+        assert_eq!(b"true".to_vec(), values[0].value);
+    }
+
+    #[tokio::test]
+    async fn health_transitions() {
+        let mut settings = crate::settings::mk_test_settings();
+        settings.api = std::net::TcpListener::bind(listen_addr(0))
+            .and_then(|listener| listener.local_addr())
+            .expect("free api port")
+            .port();
+        let (dispatcher, _dispatcher_messages) = dispatcher::message_channel(1);
+        let (health, health_rx) = health::health_channel();
+        let server = LocalServer::new(dispatcher, health_rx, mk_schedule(), &settings)
+            .expect("local server");
+        let (trigger, shutdown) = triggered::trigger();
+        let logger = Logger::root(slog::Discard, o!());
+        let running = tokio::spawn(async move { server.run(shutdown, &logger).await });
+
+        let uri = crate::api::connect_uri(settings.api);
+        let connect = async {
+            loop {
+                match HealthClient::connect(uri.clone()).await {
+                    Ok(client) => return client,
+                    Err(_) => tokio::time::sleep(Duration::from_millis(10)).await,
+                }
+            }
+        };
+        let mut client = tokio::time::timeout(Duration::from_secs(1), connect)
+            .await
+            .expect("health client");
+
+        // Live while serving, but not ready before a gateway connection and
+        // region
+        wait_for(&mut client, health::LIVENESS_SERVICE, CheckStatus::Serving).await;
+        wait_for(
+            &mut client,
+            health::READINESS_SERVICE,
+            CheckStatus::NotServing,
+        )
+        .await;
+
+        health.set_connected(true);
+        health.set_region(true);
+        wait_for(&mut client, health::READINESS_SERVICE, CheckStatus::Serving).await;
+
+        // Reconnecting is not ready, yet still live
+        health.set_connected(false);
+        wait_for(
+            &mut client,
+            health::READINESS_SERVICE,
+            CheckStatus::NotServing,
+        )
+        .await;
+        wait_for(&mut client, health::LIVENESS_SERVICE, CheckStatus::Serving).await;
+
+        health.set_connected(true);
+        wait_for(&mut client, health::READINESS_SERVICE, CheckStatus::Serving).await;
+
+        trigger.trigger();
+        running.await.expect("api task").expect("api shutdown");
+    }
+}
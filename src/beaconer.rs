@@ -70,6 +70,8 @@ pub struct Beaconer {
     region_params: Option<RegionParams>,
     poc_ingest_uri: Uri,
     entropy_service: EntropyService,
+    /// Whether to redact payloads in logs
+    redact_payload: bool,
 }
 
 impl Beaconer {
@@ -97,6 +99,7 @@ impl Beaconer {
             region_params: None,
             poc_ingest_uri,
             entropy_service,
+            redact_payload: settings.log.redact_payload,
         }
     }
 
@@ -171,7 +174,11 @@ impl Beaconer {
     }
 
     async fn handle_received_beacon(&mut self, packet: Packet, logger: &Logger) {
-        info!(logger, "received possible PoC payload: {packet:?}");
+        info!(
+            logger,
+            "received possible PoC payload: {packet}, payload: {}",
+            packet.log_payload(self.redact_payload)
+        );
 
         if let Some(last_beacon) = &self.last_beacon {
             if packet.payload == last_beacon.data {
@@ -0,0 +1,13 @@
+use crate::{cmd::*, Result, Settings};
+use structopt::StructOpt;
+
+/// Print the resolved settings, with defaults and environment overrides
+/// applied. The keypair is shown as its public key.
+#[derive(Debug, StructOpt)]
+pub struct Cmd {}
+
+impl Cmd {
+    pub async fn run(&self, settings: Settings) -> Result {
+        print_json(&settings)
+    }
+}
@@ -1,9 +1,9 @@
 use crate::{
-    api::{HeightRes, LocalClient},
+    api::{self, HeightRes, LocalClient},
     cmd::*,
     keyed_uri::KeyedUri,
     service::gateway::GatewayVersion,
-    settings::{self, Settings},
+    settings::{self, Capabilities, Settings},
     Error, Region, Result,
 };
 use angry_purple_tiger::AnimalName;
@@ -22,6 +22,9 @@ pub enum InfoKey {
     Name,
     Gateway,
     Region,
+    Capabilities,
+    ChannelPlan,
+    DownlinkSchedule,
 }
 
 #[derive(Debug, Clone)]
@@ -43,7 +46,11 @@ pub struct Cmd {
 
 impl Cmd {
     pub async fn run(&self, settings: Settings) -> Result {
-        let mut info_cache = InfoCache::new(settings.update.platform.clone(), settings.api);
+        let mut info_cache = InfoCache::new(
+            settings.update.platform.clone(),
+            settings.api,
+            settings.capabilities(),
+        );
         let mut info: HashMap<String, serde_json::Value> = HashMap::new();
         for key in &self.keys.0 {
             info.insert(key.to_string(), key.to_status(&mut info_cache).await?);
@@ -58,6 +65,9 @@ const INFO_ONBOARDING_KEY: &str = "onboarding";
 const INFO_NAME: &str = "name";
 const INFO_GATEWAY: &str = "gateway";
 const INFO_REGION: &str = "region";
+const INFO_CAPABILITIES: &str = "capabilities";
+const INFO_CHANNEL_PLAN: &str = "channel_plan";
+const INFO_DOWNLINK_SCHEDULE: &str = "downlink_schedule";
 
 impl fmt::Display for InfoKey {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -68,6 +78,9 @@ impl fmt::Display for InfoKey {
             Self::Name => INFO_NAME,
             Self::Gateway => INFO_GATEWAY,
             Self::Region => INFO_REGION,
+            Self::Capabilities => INFO_CAPABILITIES,
+            Self::ChannelPlan => INFO_CHANNEL_PLAN,
+            Self::DownlinkSchedule => INFO_DOWNLINK_SCHEDULE,
         };
         f.write_str(s)
     }
@@ -92,6 +105,9 @@ impl FromStr for InfoKey {
             INFO_NAME => Ok(Self::Name),
             INFO_GATEWAY => Ok(Self::Gateway),
             INFO_REGION => Ok(Self::Region),
+            INFO_CAPABILITIES => Ok(Self::Capabilities),
+            INFO_CHANNEL_PLAN => Ok(Self::ChannelPlan),
+            INFO_DOWNLINK_SCHEDULE => Ok(Self::DownlinkSchedule),
             invalid => Err(InfoKeyParseError(invalid.to_string())),
         }
     }
@@ -116,13 +132,15 @@ struct InfoCache {
     public_keys: Option<(PublicKey, PublicKey)>,
     height: Option<HeightRes>,
     region: Option<Region>,
+    capabilities: Capabilities,
 }
 
 impl InfoCache {
-    fn new(platform: String, port: u16) -> Self {
+    fn new(platform: String, port: u16, capabilities: Capabilities) -> Self {
         Self {
             platform,
             port,
+            capabilities,
             public_keys: None,
             height: None,
             region: None,
@@ -191,6 +209,11 @@ impl InfoCache {
         self.region = Some(region);
         Ok(region)
     }
+
+    async fn local_config(&mut self, key: &str) -> Result<serde_json::Value> {
+        let mut client = LocalClient::new(self.port).await?;
+        client.local_config(key).await
+    }
 }
 
 impl InfoKey {
@@ -225,6 +248,9 @@ impl InfoKey {
             Self::Region => {
                 json!(cache.region().await?.to_string())
             }
+            Self::Capabilities => serde_json::to_value(&cache.capabilities)?,
+            Self::ChannelPlan => cache.local_config(api::CONFIG_CHANNEL_PLAN).await?,
+            Self::DownlinkSchedule => cache.local_config(api::CONFIG_DOWNLINK_SCHEDULE).await?,
         };
         Ok(v)
     }
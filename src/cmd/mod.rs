@@ -1,6 +1,9 @@
 pub mod add;
+pub mod config;
 pub mod info;
 pub mod key;
+pub mod router_flush;
+pub mod router_selftest;
 pub mod server;
 pub mod update;
 
@@ -0,0 +1,19 @@
+use crate::{
+    api::{self, LocalClient},
+    cmd::*,
+    Result, Settings,
+};
+use structopt::StructOpt;
+
+/// Drop the packets queued for all routers of the running service without
+/// sending them. The dropped packets are counted in the router client logs.
+#[derive(Debug, StructOpt)]
+pub struct Cmd {}
+
+impl Cmd {
+    pub async fn run(&self, settings: Settings) -> Result {
+        let mut client = LocalClient::new(settings.api).await?;
+        let flushed = client.local_config(api::CONFIG_FLUSH_QUEUES).await?;
+        print_json(&flushed)
+    }
+}
@@ -0,0 +1,37 @@
+use crate::{
+    cmd::*,
+    router::{run_router_selftest, LoopbackRouter},
+    KeyedUri, Result, Settings,
+};
+use slog::Logger;
+use std::{sync::Arc, time::Duration};
+use structopt::StructOpt;
+use tokio::net::TcpListener;
+
+/// Self-test routing. Routes a synthetic uplink through a dispatcher and its
+/// router client to a local loopback router and waits for the echoed
+/// downlink on the gateway side. Does not involve a running server or the
+/// packet forwarder, and does not need the gateway service.
+#[derive(Debug, StructOpt)]
+pub struct Cmd {
+    /// Seconds to wait for the echoed downlink
+    #[structopt(long, default_value = "5")]
+    pub timeout: u64,
+}
+
+impl Cmd {
+    pub async fn run(&self, settings: Settings, logger: &Logger) -> Result {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let router = KeyedUri {
+            uri: format!("http://{}", listener.local_addr()?).parse()?,
+            pubkey: Arc::new(settings.keypair.public_key().clone()),
+        };
+        let (trigger, shutdown) = triggered::trigger();
+        let server = tokio::spawn(LoopbackRouter.serve(listener, shutdown));
+        let report =
+            run_router_selftest(router, &settings, Duration::from_secs(self.timeout), logger).await;
+        trigger.trigger();
+        let _ = server.await;
+        print_json(&report?)
+    }
+}
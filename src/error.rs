@@ -27,6 +27,8 @@ pub enum Error {
     Gateway(#[from] crate::gateway::GatewayError),
     #[error("region error")]
     Region(#[from] RegionError),
+    #[error("store error: {0}")]
+    Store(#[from] StoreError),
     #[error("curl error")]
     Curl(#[from] crate::curl::Error),
     #[error("system time")]
@@ -37,6 +39,8 @@ pub enum Error {
 pub enum EncodeError {
     #[error("protobuf encode")]
     Prost(#[from] prost::EncodeError),
+    #[error("compress")]
+    Compress(std::io::Error),
 }
 
 #[derive(Error, Debug)]
@@ -63,6 +67,8 @@ pub enum DecodeError {
     InvalidCrc,
     #[error("unexpected transaction in envelope")]
     InvalidEnvelope,
+    #[error("decompress")]
+    Decompress(std::io::Error),
 }
 
 #[derive(Error, Debug)]
@@ -77,6 +83,10 @@ pub enum ServiceError {
     Channel,
     #[error("no service")]
     NoService,
+    #[error("router connection limit {0} reached")]
+    ConnectionLimit(usize),
+    #[error("no response within {0:?}")]
+    Timeout(std::time::Duration),
     #[error("block age {block_age}s > {max_age}s")]
     Check { block_age: u64, max_age: u64 },
     #[error("Unable to connect to local server. Check that `helium_gateway` is running.")]
@@ -89,6 +99,18 @@ pub enum RegionError {
     NoRegionParams,
     #[error("no region tx power defined in region params")]
     NoRegionTxPower,
+    #[error("data rate {0} not supported in region")]
+    UnsupportedDataRate(String),
+}
+
+#[derive(Debug, Error)]
+pub enum StoreError {
+    #[error("queue capacity {0} reached")]
+    Capacity(u16),
+    #[error("store rate limit {0}/s reached")]
+    RateLimited(u32),
+    #[error("packet already queued")]
+    Filtered,
 }
 
 macro_rules! from_err {
@@ -124,7 +146,17 @@ from_err!(DecodeError, lorawan::LoraWanError);
 from_err!(DecodeError, longfi::LfcError);
 from_err!(DecodeError, semtech_udp::data_rate::ParseError);
 
+impl EncodeError {
+    pub fn compress(err: std::io::Error) -> Error {
+        Error::Encode(EncodeError::Compress(err))
+    }
+}
+
 impl DecodeError {
+    pub fn decompress(err: std::io::Error) -> Error {
+        Error::Decode(DecodeError::Decompress(err))
+    }
+
     pub fn invalid_envelope() -> Error {
         Error::Decode(DecodeError::InvalidEnvelope)
     }
@@ -150,6 +182,24 @@ impl RegionError {
     pub fn no_region_tx_power() -> Error {
         Error::Region(RegionError::NoRegionTxPower)
     }
+
+    pub fn unsupported_data_rate(data_rate: helium_proto::DataRate) -> Error {
+        Error::Region(RegionError::UnsupportedDataRate(format!("{data_rate:?}")))
+    }
+}
+
+impl StoreError {
+    pub fn capacity(max_packets: u16) -> Error {
+        Error::Store(StoreError::Capacity(max_packets))
+    }
+
+    pub fn rate_limited(limit: u32) -> Error {
+        Error::Store(StoreError::RateLimited(limit))
+    }
+
+    pub fn filtered() -> Error {
+        Error::Store(StoreError::Filtered)
+    }
 }
 
 impl Error {
@@ -167,6 +217,14 @@ impl Error {
         Error::Service(ServiceError::NoService)
     }
 
+    pub fn connection_limit(limit: usize) -> Error {
+        Error::Service(ServiceError::ConnectionLimit(limit))
+    }
+
+    pub fn timeout(timeout: std::time::Duration) -> Error {
+        Error::Service(ServiceError::Timeout(timeout))
+    }
+
     pub fn local_client_connect(e: helium_proto::services::Error) -> Error {
         Error::Service(ServiceError::LocalClientConnect(e))
     }
@@ -174,4 +232,15 @@ impl Error {
     pub fn gateway_service_check(block_age: u64, max_age: u64) -> Error {
         Error::Service(ServiceError::Check { block_age, max_age })
     }
+
+    /// Whether a signing failure may be transient, for example a busy secure
+    /// element or a communication error with it, and is worth retrying
+    pub fn is_retryable_signing(&self) -> bool {
+        matches!(self, Error::CryptoError(_))
+    }
+
+    /// Whether the receiving end of a channel is gone
+    pub fn is_channel_closed(&self) -> bool {
+        matches!(self, Error::Service(ServiceError::Channel))
+    }
 }
@@ -1,25 +1,50 @@
 use crate::{
-    beaconer, error::RegionError, router::dispatcher, sync, Error, Packet, RegionParams, Result,
-    Settings,
+    beaconer,
+    error::RegionError,
+    metrics::{Counters, RateLimiter, METRICS_INTERVAL},
+    packet::LogPayload,
+    router::{dispatcher, DevAddrMap},
+    settings::{AntennaChain, RegionOverride},
+    sync, Error, LocationSettings, Packet, Region, RegionParams, Result, RetryPolicy, Settings,
+    TimestampSource, UplinkSource, UplinkTime,
 };
 use beacon::Beacon;
 use futures::TryFutureExt;
-use lorawan::PHYPayload;
+use lorawan::{Direction, PHYPayload};
+use rust_decimal::Decimal;
 use semtech_udp::{
-    pull_resp,
+    pull_resp, push_data,
     server_runtime::{Error as SemtechError, Event, UdpRuntime},
-    tx_ack, CodingRate, MacAddress, Modulation,
+    tx_ack, CodingRate, MacAddress, Modulation, StringOrNum,
 };
+use serde::{Deserialize, Serialize};
 use slog::{debug, info, o, warn, Logger};
 use std::{
+    collections::HashMap,
     convert::TryFrom,
+    fmt,
+    future::Future,
+    sync::Arc,
     time::{Duration, Instant},
 };
-use tokio::sync::mpsc;
+use tokio::{
+    sync::{broadcast, mpsc, watch, Semaphore},
+    time,
+};
 
 pub const DOWNLINK_TIMEOUT_SECS: u64 = 5;
 pub const UPLINK_TIMEOUT_SECS: u64 = 6;
 
+/// Number of transmit results buffered for slow subscribers before the oldest
+/// are skipped
+const TRANSMIT_EVENTS_CAPACITY: usize = 64;
+
+/// Number of devices whose strongest uplink antenna is remembered
+const UPLINK_ANTENNAS_CAPACITY: usize = 1024;
+
+/// RF chain downlinks transmit on unless an antenna mapping selects another
+const DEFAULT_RF_CHAIN: u64 = 0;
+
 #[derive(Debug)]
 pub struct BeaconResp {
     pub powe: i32,
@@ -83,6 +108,607 @@ impl MessageSender {
     }
 }
 
+/// A destination for downlink packets. Implemented by the gateway message
+/// sender, and by an in-memory sink in tests.
+#[async_trait::async_trait]
+pub trait DownlinkSink: Send + Sync {
+    async fn downlink(&self, packet: Packet) -> Result;
+}
+
+#[async_trait::async_trait]
+impl DownlinkSink for MessageSender {
+    async fn downlink(&self, packet: Packet) -> Result {
+        MessageSender::downlink(self, packet).await
+    }
+}
+
+/// Records pushed downlinks in memory for assertions
+#[cfg(test)]
+#[derive(Debug, Clone, Default)]
+pub(crate) struct MemoryDownlinkSink(Arc<std::sync::Mutex<Vec<Packet>>>);
+
+#[cfg(test)]
+impl MemoryDownlinkSink {
+    /// Takes the downlinks pushed so far
+    pub fn take(&self) -> Vec<Packet> {
+        std::mem::take(&mut *self.0.lock().expect("downlink sink lock"))
+    }
+}
+
+#[cfg(test)]
+#[async_trait::async_trait]
+impl DownlinkSink for MemoryDownlinkSink {
+    async fn downlink(&self, packet: Packet) -> Result {
+        self.0.lock().expect("downlink sink lock").push(packet);
+        Ok(())
+    }
+}
+
+/// The class of a downlink, used to decide which downlink to keep when
+/// downlinks collide
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DownlinkClass {
+    /// A time critical join-accept, kept over other downlinks
+    JoinAccept,
+    Data,
+}
+
+impl From<&Packet> for DownlinkClass {
+    fn from(packet: &Packet) -> Self {
+        if packet.is_join_accept() {
+            Self::JoinAccept
+        } else {
+            Self::Data
+        }
+    }
+}
+
+/// Which downlink to keep when a new downlink collides with pending ones and
+/// neither of its windows keeps the minimum gap
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum CollisionStrategy {
+    /// Keep the pending downlinks and drop the new one
+    KeepFirst,
+    /// Keep join-accepts over data downlinks, and pending downlinks over new
+    /// ones of the same class
+    #[default]
+    KeepHighestPriority,
+    /// Keep the downlink transmitting first
+    KeepEarliestWindow,
+}
+
+/// What to do with a downlink when neither its rx1 nor its rx2 window has a
+/// usable transmit time, frequency and datarate
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum NoWindowPolicy {
+    /// Drop the downlink
+    #[default]
+    Drop,
+    /// Transmit the downlink immediately as a best effort
+    Immediate,
+}
+
+impl NoWindowPolicy {
+    /// The transmit packet for a downlink without a valid window, or None if
+    /// the downlink is to be dropped
+    pub fn pull_resp(&self, downlink: &Packet, tx_power: u32) -> Option<pull_resp::TxPk> {
+        match self {
+            Self::Drop => None,
+            Self::Immediate => downlink.to_immediate_pull_resp(tx_power),
+        }
+    }
+}
+
+/// A downlink handed to the packet forwarder that has not completed yet
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ScheduledDownlink {
+    pub id: u64,
+    pub class: DownlinkClass,
+    /// Concentrator timestamp the downlink is scheduled to transmit at
+    pub tmst: u64,
+    /// Time on air of the downlink in microseconds, zero if unknown
+    pub airtime: u64,
+    /// Region of the region params the downlink was prepared with
+    pub region: Region,
+    /// Transmit power the downlink was prepared with
+    pub tx_power: u32,
+}
+
+impl ScheduledDownlink {
+    /// The time in microseconds from the end of the earlier to the start of
+    /// the later of this downlink and one at the given timestamp and airtime.
+    /// Negative if the two overlap.
+    fn gap(&self, tmst: u64, airtime: u64) -> i64 {
+        let delta = counter_delta(tmst, self.tmst);
+        if delta >= 0 {
+            delta - self.airtime as i64
+        } else {
+            -delta - airtime as i64
+        }
+    }
+
+    /// Whether the downlink can still be sent under the given region params.
+    /// It must be for the same region and must not exceed the transmit power
+    /// they allow.
+    pub fn is_valid(&self, region_params: &RegionParams) -> bool {
+        self.region == region_params.region
+            && region_params
+                .tx_power()
+                .map_or(false, |tx_power| self.tx_power <= tx_power)
+    }
+}
+
+/// The signed difference in microseconds from concentrator timestamp `from`
+/// to `to`. Counter values wrap at 32 bits, so differences are within half
+/// the counter range.
+fn counter_delta(to: u64, from: u64) -> i64 {
+    (to as u32).wrapping_sub(from as u32) as i32 as i64
+}
+
+/// Snapshot of the pending downlinks, ordered by transmit time.
+///
+/// Downlinks are handed to the packet forwarder as soon as they are scheduled
+/// and stay pending until their transmit confirmation. Removing a pending
+/// downlink only keeps it from being handed off if it has not been yet, and
+/// keeps an rx1 downlink from being retried on rx2. A downlink the packet
+/// forwarder already has is still transmitted.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct DownlinkSchedule {
+    #[serde(skip)]
+    next_id: u64,
+    pending: Vec<ScheduledDownlink>,
+}
+
+pub type DownlinkScheduleReceiver = watch::Receiver<DownlinkSchedule>;
+
+impl DownlinkSchedule {
+    /// Adds a downlink transmitting at the given timestamp for the given
+    /// airtime and returns its id
+    fn schedule(
+        &mut self,
+        class: DownlinkClass,
+        tmst: u64,
+        airtime: u64,
+        region: Region,
+        tx_power: u32,
+    ) -> u64 {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        let index = self.pending.partition_point(|pending| pending.tmst <= tmst);
+        self.pending.insert(
+            index,
+            ScheduledDownlink {
+                id,
+                class,
+                tmst,
+                airtime,
+                region,
+                tx_power,
+            },
+        );
+        id
+    }
+
+    fn complete(&mut self, id: u64) {
+        self.pending.retain(|pending| pending.id != id);
+    }
+
+    /// Removes and returns the pending downlinks that are not valid under the
+    /// given region params. See the type docs for which of them are still
+    /// transmitted.
+    fn revalidate(&mut self, region_params: &RegionParams) -> Vec<ScheduledDownlink> {
+        let (valid, invalid) = self
+            .pending
+            .iter()
+            .partition(|pending| pending.is_valid(region_params));
+        self.pending = valid;
+        invalid
+    }
+
+    /// Whether a downlink at the given timestamp and airtime keeps at least
+    /// `min_gap` between its transmission and that of every pending downlink
+    pub fn fits(&self, tmst: u64, airtime: u64, min_gap: u64) -> bool {
+        self.pending
+            .iter()
+            .all(|pending| pending.gap(tmst, airtime) >= min_gap as i64)
+    }
+
+    /// Picks the receive window for a downlink with the given rx1 and optional
+    /// rx2 timestamp and airtime pairs that keeps `min_gap` from the pending
+    /// downlinks. Returns whether to use the rx2 window, or `None` if neither
+    /// fits.
+    pub fn select_window(
+        &self,
+        rx1: (u64, u64),
+        rx2: Option<(u64, u64)>,
+        min_gap: u64,
+    ) -> Option<bool> {
+        if self.fits(rx1.0, rx1.1, min_gap) {
+            Some(false)
+        } else {
+            rx2.filter(|(tmst, airtime)| self.fits(*tmst, *airtime, min_gap))
+                .map(|_| true)
+        }
+    }
+
+    /// Resolves a collision of a new downlink of the given class at the given
+    /// timestamp with the pending downlinks within `min_gap` of it. If the
+    /// strategy keeps the new downlink the colliding ones are removed and
+    /// returned, otherwise `None` is returned and nothing is removed. See the
+    /// type docs for which removed downlinks are still transmitted.
+    fn resolve(
+        &mut self,
+        strategy: CollisionStrategy,
+        class: DownlinkClass,
+        tmst: u64,
+        airtime: u64,
+        min_gap: u64,
+    ) -> Option<Vec<ScheduledDownlink>> {
+        let (colliding, clear): (Vec<_>, Vec<_>) = self
+            .pending
+            .iter()
+            .partition(|pending| pending.gap(tmst, airtime) < min_gap as i64);
+        let keep_new = match strategy {
+            CollisionStrategy::KeepFirst => colliding.is_empty(),
+            CollisionStrategy::KeepHighestPriority => {
+                class == DownlinkClass::JoinAccept
+                    && colliding
+                        .iter()
+                        .all(|pending| pending.class != DownlinkClass::JoinAccept)
+            }
+            CollisionStrategy::KeepEarliestWindow => colliding
+                .iter()
+                .all(|pending| counter_delta(tmst, pending.tmst) < 0),
+        };
+        if !keep_new {
+            return None;
+        }
+        self.pending = clear;
+        Some(colliding)
+    }
+
+    /// Whether the downlink with the given id is still pending
+    pub fn contains(&self, id: u64) -> bool {
+        self.pending.iter().any(|pending| pending.id == id)
+    }
+
+    pub fn pending(&self) -> &[ScheduledDownlink] {
+        &self.pending
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// The transmit timestamp of the next pending downlink
+    pub fn next_tmst(&self) -> Option<u64> {
+        self.pending.first().map(|pending| pending.tmst)
+    }
+}
+
+/// Drops downlinks scheduled implausibly far ahead of the concentrator
+/// counter, as last seen on an uplink. Counter values wrap at 32 bits.
+#[derive(Debug)]
+struct DownlinkLookahead {
+    max: Duration,
+    skew: Duration,
+    counter: Option<u32>,
+    dropped: u64,
+}
+
+impl DownlinkLookahead {
+    /// Construct a lookahead check allowing downlinks up to `max` ahead, plus
+    /// the given clock skew tolerance
+    fn new(max: Duration, skew: Duration) -> Self {
+        Self {
+            max,
+            skew,
+            counter: None,
+            dropped: 0,
+        }
+    }
+
+    /// Records the concentrator counter of a received uplink
+    fn observe(&mut self, tmst: u64) {
+        self.counter = Some(tmst as u32);
+    }
+
+    /// Returns whether a downlink at the given timestamp is within the
+    /// lookahead. Dropped downlinks are counted. Without an observed counter
+    /// all downlinks are allowed. Timestamps up to half the counter range
+    /// behind the counter are late rather than far ahead, and are allowed so
+    /// the no window policy handles them.
+    fn check(&mut self, tmst: u64) -> bool {
+        let counter = match self.counter {
+            Some(counter) => counter,
+            None => return true,
+        };
+        let delta = (tmst as u32).wrapping_sub(counter);
+        if delta > i32::MAX as u32 {
+            return true;
+        }
+        let ahead = Duration::from_micros(delta as u64);
+        if ahead > self.max + self.skew {
+            self.dropped += 1;
+            return false;
+        }
+        true
+    }
+}
+
+/// How decode errors of packet forwarder traffic are surfaced
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum DecodeStrictness {
+    /// Log decode errors as warnings and raise an alert for each
+    #[default]
+    Strict,
+    /// Only count decode errors and log them at debug level
+    Lenient,
+}
+
+/// Counts decode errors and decides how to surface them. In strict mode every
+/// error is an alert, counted in the decode error metrics.
+#[derive(Debug)]
+struct DecodeErrors {
+    strictness: DecodeStrictness,
+    count: u64,
+    alerts: u64,
+}
+
+impl DecodeErrors {
+    fn new(strictness: DecodeStrictness) -> Self {
+        Self {
+            strictness,
+            count: 0,
+            alerts: 0,
+        }
+    }
+
+    /// Records a decode error. Returns the level to log it at.
+    fn record(&mut self) -> slog::Level {
+        self.count += 1;
+        match self.strictness {
+            DecodeStrictness::Strict => {
+                self.alerts += 1;
+                slog::Level::Warning
+            }
+            DecodeStrictness::Lenient => slog::Level::Debug,
+        }
+    }
+
+    fn counters(&self) -> Counters {
+        Counters(vec![("decode_errors", self.count), ("alerts", self.alerts)])
+    }
+}
+
+/// The receive window a downlink was transmitted in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransmitWindow {
+    Rx1,
+    Rx2,
+    /// Sent immediately for lack of a valid receive window
+    Immediate,
+}
+
+/// The outcome of a downlink transmit attempt. A downlink that is retried in
+/// rx2 after failing rx1 produces a result for each window.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TransmitResult {
+    /// Hash of the downlink payload
+    pub hash: Vec<u8>,
+    /// Whether the packet forwarder acknowledged the transmit. A transmit
+    /// with adjusted power counts as successful.
+    pub success: bool,
+    pub window: TransmitWindow,
+    /// Requested transmit power in dBm
+    pub power: u32,
+}
+
+pub type TransmitResultReceiver = broadcast::Receiver<TransmitResult>;
+
+/// Publishes downlink transmit results to any subscribers
+#[derive(Debug, Clone)]
+struct TransmitEvents(broadcast::Sender<TransmitResult>);
+
+impl TransmitEvents {
+    fn new() -> Self {
+        Self(broadcast::channel(TRANSMIT_EVENTS_CAPACITY).0)
+    }
+
+    fn subscribe(&self) -> TransmitResultReceiver {
+        self.0.subscribe()
+    }
+
+    fn emit<T>(
+        &self,
+        downlink: &Packet,
+        window: TransmitWindow,
+        power: u32,
+        result: &std::result::Result<T, SemtechError>,
+    ) {
+        let success = matches!(
+            result,
+            Ok(_)
+                | Err(SemtechError::Ack(tx_ack::Error::AdjustedTransmitPower(
+                    _,
+                    _
+                )))
+        );
+        // Sending only fails without subscribers
+        let _ = self.0.send(TransmitResult {
+            hash: downlink.hash(),
+            success,
+            window,
+            power,
+        });
+    }
+}
+
+/// Counts downlink transmit results per receive window. Exported with the
+/// other gateway metrics.
+#[derive(Debug, Default)]
+pub struct TransmitCounts {
+    rx1_success: u64,
+    rx1_failure: u64,
+    rx2_success: u64,
+    rx2_failure: u64,
+    immediate_success: u64,
+    immediate_failure: u64,
+    /// Results skipped because the counter fell behind the transmit tasks
+    skipped: u64,
+}
+
+impl TransmitCounts {
+    pub fn record(&mut self, result: &TransmitResult) {
+        let count = match (result.window, result.success) {
+            (TransmitWindow::Rx1, true) => &mut self.rx1_success,
+            (TransmitWindow::Rx1, false) => &mut self.rx1_failure,
+            (TransmitWindow::Rx2, true) => &mut self.rx2_success,
+            (TransmitWindow::Rx2, false) => &mut self.rx2_failure,
+            (TransmitWindow::Immediate, true) => &mut self.immediate_success,
+            (TransmitWindow::Immediate, false) => &mut self.immediate_failure,
+        };
+        *count += 1;
+    }
+
+    /// Records results that were dropped before they could be counted
+    pub fn record_skipped(&mut self, skipped: u64) {
+        self.skipped += skipped;
+    }
+
+    /// The current counter values, for logging
+    pub fn counters(&self) -> Counters {
+        Counters(vec![
+            ("rx1_success", self.rx1_success),
+            ("rx1_failure", self.rx1_failure),
+            ("rx2_success", self.rx2_success),
+            ("rx2_failure", self.rx2_failure),
+            ("immediate_success", self.immediate_success),
+            ("immediate_failure", self.immediate_failure),
+            ("skipped", self.skipped),
+        ])
+    }
+}
+
+/// Runs downlink dispatches, each awaiting its transmit confirmation, as
+/// independent tasks. An optional limit bounds how many run at the same time.
+/// A limit of zero is taken as unlimited.
+#[derive(Debug, Clone, Default)]
+struct DownlinkConfirmations(Option<Arc<Semaphore>>);
+
+impl DownlinkConfirmations {
+    fn new(max_pending: Option<usize>) -> Self {
+        Self(
+            max_pending
+                .filter(|max| *max > 0)
+                .map(|max| Arc::new(Semaphore::new(max))),
+        )
+    }
+
+    /// Spawns the given dispatch unless the limit of pending confirmations is
+    /// reached. Dispatches never wait for a slot since a downlink that is
+    /// held back would miss its window. Returns whether the dispatch was
+    /// spawned.
+    fn spawn<F>(&self, dispatch: F) -> bool
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let permit = match &self.0 {
+            Some(slots) => match slots.clone().try_acquire_owned() {
+                Ok(permit) => Some(permit),
+                Err(_) => return false,
+            },
+            None => None,
+        };
+        tokio::spawn(async move {
+            let _permit = permit;
+            dispatch.await
+        });
+        true
+    }
+}
+
+/// Remembers the antenna that received the latest uplink of each device the
+/// strongest, so downlinks to that device transmit on the RF chain mapped to
+/// that antenna. Only mappings to RF chains that can transmit are kept.
+/// Downlinks that do not correlate to a remembered uplink, or whose antenna is
+/// not mapped, transmit on the default RF chain.
+#[derive(Debug)]
+pub struct UplinkAntennas {
+    antennas: DevAddrMap<u64>,
+    chains: HashMap<u64, u64>,
+}
+
+impl UplinkAntennas {
+    pub fn new(capacity: usize, chains: &[AntennaChain], tx_rf_chains: &[u64]) -> Self {
+        Self {
+            antennas: DevAddrMap::new(capacity),
+            chains: chains
+                .iter()
+                .filter(|chain| tx_rf_chains.contains(&chain.rfch))
+                .map(|chain| (chain.antenna, chain.rfch))
+                .collect(),
+        }
+    }
+
+    /// Whether any antenna maps to an RF chain that can transmit
+    pub fn is_empty(&self) -> bool {
+        self.chains.is_empty()
+    }
+
+    /// The remembered devices and their evictions, for logging
+    pub fn counters(&self) -> Counters {
+        self.antennas.counters()
+    }
+
+    /// Records the antenna that received the given uplink the strongest
+    pub fn record(&mut self, uplink: &Packet, antenna: Option<u64>) {
+        if let (Some(devaddr), Some(antenna)) = (uplink.dev_addr(Direction::Uplink), antenna) {
+            self.antennas.insert(devaddr, antenna);
+        }
+    }
+
+    /// The RF chain to transmit the given downlink on
+    pub fn select(&mut self, downlink: &Packet) -> u64 {
+        downlink
+            .dev_addr(Direction::Downlink)
+            .and_then(|devaddr| self.antennas.get_mut(devaddr).copied())
+            .and_then(|antenna| self.chains.get(&antenna).copied())
+            .unwrap_or(DEFAULT_RF_CHAIN)
+    }
+}
+
+/// The antenna that received an uplink with the strongest signal. Packet
+/// forwarders without per antenna signal data report the receiving RF chain.
+pub fn strongest_antenna(rxpk: &push_data::RxPk) -> Option<u64> {
+    match rxpk {
+        push_data::RxPk::V1(rxpk) => Some(rxpk.rfch),
+        push_data::RxPk::V2(rxpk) => rxpk
+            .rsig
+            .iter()
+            .max_by_key(|rsig| rsig.rssic)
+            .map(|rsig| rsig.ant as u64),
+    }
+}
+
+/// The region overriding the gateway region for uplinks of the packet
+/// forwarder with the given MAC address, if any
+fn region_override(overrides: &[RegionOverride], mac: &str) -> Option<Region> {
+    overrides
+        .iter()
+        .find(|region_override| region_override.mac.eq_ignore_ascii_case(mac))
+        .map(|region_override| region_override.region)
+}
+
 pub struct Gateway {
     uplinks: dispatcher::MessageSender,
     messages: MessageReceiver,
@@ -91,6 +717,23 @@ pub struct Gateway {
     udp_runtime: UdpRuntime,
     listen_address: String,
     region_params: Option<RegionParams>,
+    redact_payload: bool,
+    location: Option<LocationSettings>,
+    region_overrides: Vec<RegionOverride>,
+    downlink_limiter: Option<RateLimiter>,
+    schedule: Arc<watch::Sender<DownlinkSchedule>>,
+    confirmations: DownlinkConfirmations,
+    min_gap: Option<u64>,
+    collision: CollisionStrategy,
+    no_window: NoWindowPolicy,
+    no_window_downlinks: u64,
+    lookahead: Option<DownlinkLookahead>,
+    max_eirp: Option<Decimal>,
+    uplink_time_sources: Vec<TimestampSource>,
+    decode_errors: DecodeErrors,
+    transmit_events: TransmitEvents,
+    transmit_counts: TransmitCounts,
+    antennas: Option<UplinkAntennas>,
 }
 
 impl Gateway {
@@ -99,28 +742,102 @@ impl Gateway {
         messages: MessageReceiver,
         beacon_handler: beaconer::MessageSender,
         settings: &Settings,
+        shutdown: &triggered::Listener,
+        logger: &Logger,
     ) -> Result<Self> {
+        let udp_runtime =
+            bind_udp_runtime(&settings.listen, &settings.listen_retry, shutdown, logger).await?;
+        for chain in &settings.downlink.antenna_chains {
+            if !settings.downlink.tx_rf_chains.contains(&chain.rfch) {
+                warn!(logger, "ignoring antenna mapped to an RF chain that cannot transmit";
+                    "antenna" => chain.antenna,
+                    "rfch" => chain.rfch);
+            }
+        }
+        let antennas = UplinkAntennas::new(
+            UPLINK_ANTENNAS_CAPACITY,
+            &settings.downlink.antenna_chains,
+            &settings.downlink.tx_rf_chains,
+        );
         let gateway = Gateway {
             uplinks,
             downlink_mac: Default::default(),
             messages,
             beacon_handler,
             listen_address: settings.listen.clone(),
-            udp_runtime: UdpRuntime::new(&settings.listen).await.map_err(Box::new)?,
+            udp_runtime,
             region_params: None,
+            redact_payload: settings.log.redact_payload,
+            location: settings.location,
+            region_overrides: settings.region_overrides.clone(),
+            downlink_limiter: settings.downlink.max_rate.map(RateLimiter::new),
+            schedule: Arc::new(watch::channel(DownlinkSchedule::default()).0),
+            confirmations: DownlinkConfirmations::new(settings.downlink.max_pending_confirmations),
+            min_gap: settings.downlink.min_gap,
+            collision: settings.downlink.collision,
+            no_window: settings.downlink.no_window,
+            no_window_downlinks: 0,
+            lookahead: settings
+                .downlink
+                .max_lookahead()
+                .map(|max| DownlinkLookahead::new(max, settings.clock_skew())),
+            max_eirp: settings.downlink.max_eirp,
+            uplink_time_sources: settings.uplink_time_sources.clone(),
+            decode_errors: DecodeErrors::new(settings.decode_strictness),
+            transmit_events: TransmitEvents::new(),
+            transmit_counts: TransmitCounts::default(),
+            antennas: Some(antennas).filter(|antennas| !antennas.is_empty()),
         };
         Ok(gateway)
     }
 
+    /// Watch the downlinks pending transmission
+    pub fn downlink_schedule(&self) -> DownlinkScheduleReceiver {
+        self.schedule.subscribe()
+    }
+
+    /// Subscribe to the results of downlink transmit attempts
+    pub fn transmit_results(&self) -> TransmitResultReceiver {
+        self.transmit_events.subscribe()
+    }
+
+    /// Logs the downlink transmit results, the decode errors and alerts, and
+    /// the current uplink antenna mapping size and evictions
+    fn log_metrics(&self, logger: &Logger) {
+        debug!(logger, "downlink transmits"; self.transmit_counts.counters());
+        debug!(logger, "decode errors"; self.decode_errors.counters());
+        if let Some(antennas) = &self.antennas {
+            debug!(logger, "uplink antennas"; antennas.counters());
+        }
+    }
+
+    fn decode_error(&mut self, logger: &Logger, msg: fmt::Arguments) {
+        let count = self.decode_errors.count + 1;
+        match self.decode_errors.record() {
+            slog::Level::Warning => warn!(logger, "{msg}"; "decode_errors" => count),
+            _ => debug!(logger, "{msg}"; "decode_errors" => count),
+        }
+    }
+
     pub async fn run(&mut self, shutdown: triggered::Listener, logger: &Logger) -> Result {
         let logger = logger.new(o!("module" => "gateway"));
         info!(logger, "starting"; "listen" => &self.listen_address);
+        let mut metrics_timer = time::interval(METRICS_INTERVAL);
+        let mut transmit_results = self.transmit_results();
         loop {
             tokio::select! {
                 _ = shutdown.clone() => {
                     info!(logger, "shutting down");
                     return Ok(())
                 },
+                _ = metrics_timer.tick() => self.log_metrics(&logger),
+                result = transmit_results.recv() => match result {
+                    Ok(result) => self.transmit_counts.record(&result),
+                    Err(broadcast::error::RecvError::Lagged(skipped)) =>
+                        self.transmit_counts.record_skipped(skipped),
+                    // The gateway holds the sender, so results never close
+                    Err(broadcast::error::RecvError::Closed) => (),
+                },
                 event = self.udp_runtime.recv() =>
                     self.handle_udp_event(&logger, event).await?,
                 message = self.messages.recv() => match message {
@@ -137,9 +854,13 @@ impl Gateway {
     async fn handle_udp_event(&mut self, logger: &Logger, event: Event) -> Result {
         match event {
             Event::UnableToParseUdpFrame(e, buf) => {
-                warn!(
+                let redact_payload = self.redact_payload;
+                self.decode_error(
                     logger,
-                    "ignoring semtech udp parsing error {e}, raw bytes {buf:?}"
+                    format_args!(
+                        "ignoring semtech udp parsing error {e}, raw bytes {}",
+                        LogPayload::new(&buf, redact_payload)
+                    ),
                 );
             }
             Event::NewClient((mac, addr)) => {
@@ -152,15 +873,32 @@ impl Gateway {
             Event::ClientDisconnected((mac, addr)) => {
                 info!(logger, "disconnected packet forwarder: {mac}, {addr}")
             }
-            Event::PacketReceived(rxpk, _gateway_mac) => match Packet::try_from(rxpk) {
-                Ok(packet) if packet.is_potential_beacon() => {
-                    self.beacon_handler.received_beacon(packet).await
-                }
-                Ok(packet) => self.handle_uplink(logger, packet, Instant::now()).await,
-                Err(err) => {
-                    warn!(logger, "ignoring push_data: {err:?}");
+            Event::PacketReceived(rxpk, gateway_mac) => {
+                let time = UplinkTime::from_rxpk(&self.uplink_time_sources, &rxpk);
+                let antenna = strongest_antenna(&rxpk);
+                let source = UplinkSource {
+                    listener: self.listen_address.clone(),
+                    mac: gateway_mac.to_string(),
+                };
+                let region = region_override(&self.region_overrides, &source.mac);
+                match Packet::try_from(rxpk)
+                    .map(|packet| packet.with_source(source).with_time(time))
+                {
+                    Ok(packet) if packet.is_potential_beacon() => {
+                        self.beacon_handler.received_beacon(packet).await
+                    }
+                    Ok(packet) => {
+                        if let Some(antennas) = self.antennas.as_mut() {
+                            antennas.record(&packet, antenna);
+                        }
+                        self.handle_uplink(logger, packet, region, Instant::now())
+                            .await
+                    }
+                    Err(err) => {
+                        self.decode_error(logger, format_args!("ignoring push_data: {err:?}"));
+                    }
                 }
-            },
+            }
             Event::NoClientWithMac(_packet, mac) => {
                 info!(logger, "ignoring send to client with unknown MAC: {mac}")
             }
@@ -171,9 +909,32 @@ impl Gateway {
         Ok(())
     }
 
-    async fn handle_uplink(&mut self, logger: &Logger, packet: Packet, received: Instant) {
-        info!(logger, "uplink {} from {}", packet, self.downlink_mac);
-        match self.uplinks.uplink(packet, received).await {
+    async fn handle_uplink(
+        &mut self,
+        logger: &Logger,
+        packet: Packet,
+        region: Option<Region>,
+        received: Instant,
+    ) {
+        if let Some(lookahead) = self.lookahead.as_mut() {
+            lookahead.observe(packet.timestamp);
+        }
+        let packet = packet.with_location(self.location);
+        let time_source = packet.time().map(|time| time.source.to_string());
+        let time = packet.time().map(|time| time.time);
+        let source = packet.source().map(UplinkSource::to_string);
+        match packet.location() {
+            Some(location) => info!(logger, "uplink {} from {}", packet, self.downlink_mac;
+                "location" => location.to_string(),
+                "time" => time,
+                "time_source" => time_source,
+                "source" => source),
+            None => info!(logger, "uplink {} from {}", packet, self.downlink_mac;
+                "time" => time,
+                "time_source" => time_source,
+                "source" => source),
+        }
+        match self.uplinks.uplink(packet, region, received).await {
             Ok(()) => (),
             Err(err) => warn!(logger, "ignoring uplink error {:?}", err),
         }
@@ -181,7 +942,24 @@ impl Gateway {
 
     async fn handle_message(&mut self, logger: &Logger, message: Message) {
         match message {
-            Message::Downlink(packet) => self.handle_downlink(logger, packet).await,
+            Message::Downlink(packet) => {
+                if let Some(lookahead) = self.lookahead.as_mut() {
+                    if !lookahead.check(packet.timestamp) {
+                        warn!(logger, "dropping implausible far future downlink";
+                            "tmst" => packet.timestamp,
+                            "dropped" => lookahead.dropped);
+                        return;
+                    }
+                }
+                if let Some(limiter) = self.downlink_limiter.as_mut() {
+                    if !limiter.check(Instant::now()) {
+                        warn!(logger, "dropping rate limited downlink";
+                            "dropped" => limiter.rejected());
+                        return;
+                    }
+                }
+                self.handle_downlink(logger, packet).await
+            }
             Message::TransmitBeacon(beacon, tx_resp) => {
                 self.handle_transmit_beacon(logger, beacon, tx_resp).await
             }
@@ -189,6 +967,15 @@ impl Gateway {
                 self.beacon_handler
                     .region_params_changed(region_params.clone())
                     .await;
+                let mut dropped = vec![];
+                self.schedule
+                    .send_modify(|schedule| dropped = schedule.revalidate(&region_params));
+                for downlink in dropped {
+                    warn!(logger, "cancelling downlink invalidated by region params";
+                        "tmst" => downlink.tmst,
+                        "region" => downlink.region,
+                        "tx_power" => downlink.tx_power);
+                }
                 self.region_params = Some(region_params);
                 info!(logger, "updated region";
                     "region" => RegionParams::to_string(&self.region_params));
@@ -196,6 +983,13 @@ impl Gateway {
         }
     }
 
+    /// The RF chain to transmit the given downlink on
+    fn rf_chain(&mut self, downlink: &Packet) -> u64 {
+        self.antennas
+            .as_mut()
+            .map_or(DEFAULT_RF_CHAIN, |antennas| antennas.select(downlink))
+    }
+
     fn tx_power(&mut self) -> Result<u32> {
         let region_params = if let Some(region_params) = &self.region_params {
             region_params
@@ -286,7 +1080,7 @@ impl Gateway {
         });
     }
 
-    async fn handle_downlink(&mut self, logger: &Logger, downlink: Packet) {
+    async fn handle_downlink(&mut self, logger: &Logger, mut downlink: Packet) {
         let tx_power = match self.tx_power() {
             Ok(tx_power) => tx_power,
             Err(err) => {
@@ -301,37 +1095,180 @@ impl Gateway {
             // 2nd downlink window if requested by the router response
             self.udp_runtime.prepare_empty_downlink(self.downlink_mac),
         );
-        let logger = logger.clone();
-        tokio::spawn(async move {
+        // Region params are known once a transmit power is
+        let region_params = match &self.region_params {
+            Some(region_params) => region_params,
+            None => return,
+        };
+        let region = region_params.region;
+
+        if !downlink.has_rx1_window() {
+            if downlink.has_rx2_window() {
+                debug!(logger, "moving downlink without a valid rx1 window to rx2");
+                downlink.use_rx2_window();
+            } else {
+                self.no_window_downlinks += 1;
+                let mut txpk = match self.no_window.pull_resp(&downlink, tx_power) {
+                    Some(txpk) => txpk,
+                    None => {
+                        warn!(logger, "dropping downlink without a valid window";
+                            "tmst" => downlink.timestamp,
+                            "policy" => format!("{:?}", self.no_window),
+                            "no_window" => self.no_window_downlinks);
+                        return;
+                    }
+                };
+                let frequency = (txpk.freq * 1e6).round() as u64;
+                let tx_power = region_params.clamp_tx_power(tx_power, frequency, self.max_eirp);
+                txpk.powe = tx_power as u64;
+                txpk.rfch = self.rf_chain(&downlink);
+                warn!(logger, "transmitting downlink without a valid window immediately";
+                    "no_window" => self.no_window_downlinks);
+                let transmit_events = self.transmit_events.clone();
+                let dispatch_logger = logger.clone();
+                let redact_payload = self.redact_payload;
+                let spawned = self.confirmations.spawn(async move {
+                    let logger = dispatch_logger;
+                    info!(
+                        logger,
+                        "immediate downlink {} via {}",
+                        LogTxPk::new(&txpk, &downlink, redact_payload),
+                        downlink_rx1.get_destination_mac()
+                    );
+                    downlink_rx1.set_packet(txpk);
+                    let result = downlink_rx1
+                        .dispatch(Some(Duration::from_secs(DOWNLINK_TIMEOUT_SECS)))
+                        .await;
+                    transmit_events.emit(&downlink, TransmitWindow::Immediate, tx_power, &result);
+                    if let Err(err) = result {
+                        warn!(logger, "ignoring immediate downlink error: {:?}", err);
+                    }
+                });
+                if !spawned {
+                    warn!(logger, "dropping downlink, too many pending confirmations");
+                }
+                return;
+            }
+        }
+
+        let class = DownlinkClass::from(&downlink);
+        if let Some(min_gap) = self.min_gap {
+            let rx1 = (
+                downlink.timestamp,
+                airtime_micros(&downlink, false, &region),
+            );
+            let rx2 = downlink
+                .rx2_window
+                .as_ref()
+                .map(|rx2| (rx2.timestamp, airtime_micros(&downlink, true, &region)));
+            let use_rx2 = self.schedule.borrow().select_window(rx1, rx2, min_gap);
+            match use_rx2 {
+                Some(false) => (),
+                Some(true) => {
+                    debug!(logger, "moving downlink to rx2 to keep downlink gap");
+                    downlink.use_rx2_window();
+                }
+                None => {
+                    let mut removed = None;
+                    self.schedule.send_modify(|schedule| {
+                        removed = schedule.resolve(self.collision, class, rx1.0, rx1.1, min_gap)
+                    });
+                    match removed {
+                        Some(removed) => {
+                            for pending in removed {
+                                warn!(logger, "cancelling downlink colliding with a kept downlink";
+                                    "tmst" => pending.tmst,
+                                    "strategy" => format!("{:?}", self.collision));
+                            }
+                        }
+                        None => {
+                            warn!(logger, "dropping downlink colliding with pending downlinks";
+                                "tmst" => downlink.timestamp,
+                                "strategy" => format!("{:?}", self.collision));
+                            return;
+                        }
+                    }
+                }
+            }
+        }
+
+        // Clamp the transmit power of each window to the regional limit
+        let clamp = |frequency: f32| {
+            let frequency = (frequency as f64 * 1e6).round() as u64;
+            region_params.clamp_tx_power(tx_power, frequency, self.max_eirp)
+        };
+        let rx2_tx_power = downlink
+            .rx2_window
+            .as_ref()
+            .map_or(tx_power, |rx2| clamp(rx2.frequency));
+        let tx_power = clamp(downlink.frequency);
+
+        let rfch = self.rf_chain(&downlink);
+        let downlink_tmst = downlink.timestamp;
+        let mut id = 0;
+        let airtime = airtime_micros(&downlink, false, &region);
+        self.schedule.send_modify(|schedule| {
+            id = schedule.schedule(class, downlink.timestamp, airtime, region, tx_power)
+        });
+        {
+            let schedule = self.schedule.borrow();
+            debug!(logger, "downlink scheduled";
+                "pending" => schedule.len(),
+                "next_tmst" => schedule.next_tmst());
+        }
+        let schedule = self.schedule.clone();
+        let transmit_events = self.transmit_events.clone();
+        let dispatch_logger = logger.clone();
+        let redact_payload = self.redact_payload;
+        let spawned = self.confirmations.spawn(async move {
+            let logger = dispatch_logger;
+            let is_pending = || schedule.borrow().contains(id);
+            if !is_pending() {
+                info!(logger, "skipping invalidated downlink");
+                return;
+            }
             match downlink.to_pull_resp(false, tx_power).unwrap() {
                 None => (),
-                Some(txpk) => {
+                Some(mut txpk) => {
+                    txpk.rfch = rfch;
                     info!(
                         logger,
                         "rx1 downlink {} via {}",
-                        txpk,
+                        LogTxPk::new(&txpk, &downlink, redact_payload),
                         downlink_rx1.get_destination_mac()
                     );
                     downlink_rx1.set_packet(txpk);
-                    match downlink_rx1
+                    let result = downlink_rx1
                         .dispatch(Some(Duration::from_secs(DOWNLINK_TIMEOUT_SECS)))
-                        .await
-                    {
+                        .await;
+                    transmit_events.emit(&downlink, TransmitWindow::Rx1, tx_power, &result);
+                    match result {
                         // On a too early or too late error retry on the rx2 slot if available.
                         Err(SemtechError::Ack(tx_ack::Error::TooEarly))
-                        | Err(SemtechError::Ack(tx_ack::Error::TooLate)) => {
-                            if let Some(txpk) = downlink.to_pull_resp(true, tx_power).unwrap() {
+                        | Err(SemtechError::Ack(tx_ack::Error::TooLate))
+                            if is_pending() =>
+                        {
+                            if let Some(mut txpk) =
+                                downlink.to_pull_resp(true, rx2_tx_power).unwrap()
+                            {
+                                txpk.rfch = rfch;
                                 info!(
                                     logger,
                                     "rx2 downlink {} via {}",
-                                    txpk,
+                                    LogTxPk::new(&txpk, &downlink, redact_payload),
                                     downlink_rx2.get_destination_mac()
                                 );
                                 downlink_rx2.set_packet(txpk);
-                                if let Err(err) = downlink_rx2
+                                let result = downlink_rx2
                                     .dispatch(Some(Duration::from_secs(DOWNLINK_TIMEOUT_SECS)))
-                                    .await
-                                {
+                                    .await;
+                                transmit_events.emit(
+                                    &downlink,
+                                    TransmitWindow::Rx2,
+                                    rx2_tx_power,
+                                    &result,
+                                );
+                                if let Err(err) = result {
                                     if let SemtechError::Ack(
                                         tx_ack::Error::AdjustedTransmitPower(_, _),
                                     ) = err
@@ -356,7 +1293,83 @@ impl Gateway {
                     }
                 }
             }
+            schedule.send_modify(|schedule| schedule.complete(id));
         });
+        if !spawned {
+            warn!(logger, "dropping downlink, too many pending confirmations";
+                "tmst" => downlink_tmst);
+            self.schedule.send_modify(|schedule| schedule.complete(id));
+        }
+    }
+}
+
+/// The time on air in microseconds of a downlink in its rx1 or rx2 window,
+/// zero if it is not known
+fn airtime_micros(downlink: &Packet, use_rx2: bool, region: &Region) -> u64 {
+    downlink
+        .downlink_airtime(use_rx2, region)
+        .map_or(0, |airtime| airtime.as_micros() as u64)
+}
+
+/// Formats a downlink transmit for logging. The payload is formatted with
+/// `LogPayload`, so it is redacted when configured.
+struct LogTxPk<'a> {
+    txpk: &'a pull_resp::TxPk,
+    payload: LogPayload<'a>,
+}
+
+impl<'a> LogTxPk<'a> {
+    fn new(txpk: &'a pull_resp::TxPk, downlink: &'a Packet, redact: bool) -> Self {
+        Self {
+            txpk,
+            payload: downlink.log_payload(redact),
+        }
+    }
+}
+
+impl fmt::Display for LogTxPk<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let txpk = self.txpk;
+        match &txpk.tmst {
+            Some(StringOrNum::N(tmst)) => write!(f, "@{tmst} us, ")?,
+            _ if txpk.imme => f.write_str("immediate, ")?,
+            _ => (),
+        }
+        write!(
+            f,
+            "{:.2} MHz, {}, powe: {}, rfch: {}, payload: {}",
+            txpk.freq, txpk.datr, txpk.powe, txpk.rfch, self.payload
+        )
+    }
+}
+
+/// Binds the semtech UDP runtime to the given listen address. Bind failures,
+/// for example when the network interface is not up yet at boot, are retried
+/// with the given retry policy. The last bind error is returned when its
+/// attempts are exhausted or on shutdown.
+async fn bind_udp_runtime(
+    listen: &str,
+    retry: &RetryPolicy,
+    shutdown: &triggered::Listener,
+    logger: &Logger,
+) -> Result<UdpRuntime> {
+    let mut attempt = 0;
+    loop {
+        let err = match UdpRuntime::new(listen).await {
+            Ok(udp_runtime) => return Ok(udp_runtime),
+            Err(err) => err,
+        };
+        attempt += 1;
+        let wait = match retry.delay(attempt) {
+            Some(wait) => wait,
+            None => return Err(Box::new(err).into()),
+        };
+        warn!(logger, "failed to bind {listen}, retrying in {}ms: {err:?}", wait.as_millis();
+            "attempt" => attempt);
+        tokio::select! {
+            _ = shutdown.clone() => return Err(Box::new(err).into()),
+            _ = time::sleep(wait) => (),
+        }
     }
 }
 
@@ -385,3 +1398,718 @@ pub fn beacon_to_pull_resp(beacon: &Beacon, tx_power: u64) -> Result<pull_resp::
         ncrc: None,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use helium_proto::Region as ProtoRegion;
+
+    fn mk_region(region: ProtoRegion) -> Region {
+        Region::from_i32(region.into()).expect("region")
+    }
+
+    fn mk_region_params(region: ProtoRegion, max_eirp: i32) -> RegionParams {
+        RegionParams {
+            gain: rust_decimal::Decimal::new(12, 1),
+            region: mk_region(region),
+            params: vec![helium_proto::BlockchainRegionParamV1 {
+                channel_frequency: 868_100_000,
+                max_eirp,
+                ..Default::default()
+            }],
+        }
+    }
+
+    #[test]
+    fn downlink_antenna() {
+        let mk_packet = |mhdr: u8, devaddr: u8| -> Packet {
+            helium_proto::Packet {
+                payload: vec![mhdr, devaddr, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+                ..Default::default()
+            }
+            .into()
+        };
+        let chains = [
+            AntennaChain {
+                antenna: 0,
+                rfch: 0,
+            },
+            AntennaChain {
+                antenna: 1,
+                rfch: 1,
+            },
+            // RF chain 2 cannot transmit
+            AntennaChain {
+                antenna: 2,
+                rfch: 2,
+            },
+        ];
+        let mut antennas = UplinkAntennas::new(4, &chains, &[0, 1]);
+        antennas.record(&mk_packet(0x40, 1), Some(1));
+        antennas.record(&mk_packet(0x40, 2), Some(0));
+        antennas.record(&mk_packet(0x40, 4), Some(2));
+        // Uplinks without antenna data do not replace the known antenna
+        antennas.record(&mk_packet(0x40, 1), None);
+
+        // A downlink to a device that was heard uses the chain mapped to its
+        // strongest antenna
+        assert_eq!(1, antennas.select(&mk_packet(0x60, 1)));
+        assert_eq!(0, antennas.select(&mk_packet(0x60, 2)));
+        // Antennas mapped to a chain that cannot transmit use the default
+        assert_eq!(DEFAULT_RF_CHAIN, antennas.select(&mk_packet(0x60, 4)));
+        // Downlinks without a matching uplink use the default chain
+        assert_eq!(DEFAULT_RF_CHAIN, antennas.select(&mk_packet(0x60, 3)));
+        let join_accept: Packet = helium_proto::Packet {
+            payload: vec![0x20; 17],
+            ..Default::default()
+        }
+        .into();
+        assert_eq!(DEFAULT_RF_CHAIN, antennas.select(&join_accept));
+
+        // Without usable mappings antenna selection is disabled
+        assert!(UplinkAntennas::new(4, &chains[2..], &[0]).is_empty());
+    }
+
+    #[test]
+    fn strongest_antenna_rsig() {
+        let rxpk = |json| -> push_data::RxPk { serde_json::from_value(json).expect("rxpk") };
+        let v1 = rxpk(serde_json::json!({
+            "tmst": 3512348611u32,
+            "chan": 2,
+            "rfch": 1,
+            "freq": 866.349812,
+            "stat": 1,
+            "modu": "LORA",
+            "datr": "SF7BW125",
+            "codr": "4/6",
+            "rssi": -35,
+            "lsnr": 5.1,
+            "size": 3,
+            "data": "AQID"
+        }));
+        // Without per antenna signal data the receiving RF chain is reported
+        assert_eq!(Some(1), strongest_antenna(&v1));
+
+        let v2 = rxpk(serde_json::json!({
+            "jver": 2,
+            "tmst": 3512348611u32,
+            "chan": 2,
+            "rfch": 0,
+            "freq": 866.349812,
+            "mid": 8,
+            "stat": 1,
+            "modu": "LORA",
+            "datr": "SF7BW125",
+            "codr": "4/6",
+            "size": 3,
+            "data": "AQID",
+            "rsig": [
+                {"ant": 0, "chan": 7, "rssic": -95, "lsnr": 2.0},
+                {"ant": 1, "chan": 7, "rssic": -88, "lsnr": 4.5}
+            ]
+        }));
+        assert_eq!(Some(1), strongest_antenna(&v2));
+    }
+
+    #[test]
+    fn region_overrides() {
+        let overrides = vec![RegionOverride {
+            mac: "AA555A0000000001".to_string(),
+            region: mk_region(ProtoRegion::Eu868),
+        }];
+        assert_eq!(
+            Some(mk_region(ProtoRegion::Eu868)),
+            region_override(&overrides, "aa555a0000000001")
+        );
+        assert_eq!(None, region_override(&overrides, "aa555a0000000002"));
+        assert_eq!(None, region_override(&[], "aa555a0000000001"));
+    }
+
+    #[test]
+    fn transmit_result_rx1() {
+        let events = TransmitEvents::new();
+        let mut results = events.subscribe();
+        let downlink: Packet = helium_proto::Packet {
+            payload: vec![1, 2, 3],
+            ..Default::default()
+        }
+        .into();
+        events.emit(
+            &downlink,
+            TransmitWindow::Rx1,
+            27,
+            &Ok::<_, SemtechError>(()),
+        );
+        assert_eq!(
+            TransmitResult {
+                hash: downlink.hash(),
+                success: true,
+                window: TransmitWindow::Rx1,
+                power: 27,
+            },
+            results.try_recv().expect("transmit result")
+        );
+
+        // Results are counted per window and outcome
+        let mut counts = TransmitCounts::default();
+        events.emit(
+            &downlink,
+            TransmitWindow::Rx2,
+            27,
+            &Err::<(), _>(SemtechError::Ack(tx_ack::Error::TooLate)),
+        );
+        while let Ok(result) = results.try_recv() {
+            counts.record(&result);
+        }
+        counts.record_skipped(2);
+        let counters = counts.counters();
+        let count = |name: &str| counters.0.iter().find(|(n, _)| *n == name).map(|(_, c)| *c);
+        assert_eq!(Some(1), count("rx2_failure"));
+        assert_eq!(Some(0), count("rx1_success"));
+        assert_eq!(Some(2), count("skipped"));
+    }
+
+    #[test]
+    fn region_params_invalidate_downlinks() {
+        let eu868 = mk_region(ProtoRegion::Eu868);
+        let mut schedule = DownlinkSchedule::default();
+        let low = schedule.schedule(DownlinkClass::Data, 1_000_000, 0, eu868, 12);
+        let high = schedule.schedule(DownlinkClass::Data, 2_000_000, 0, eu868, 14);
+
+        // A refresh of the same params keeps both
+        let params = mk_region_params(ProtoRegion::Eu868, 160);
+        assert_eq!(Some(14), params.tx_power());
+        assert!(schedule.revalidate(&params).is_empty());
+        assert_eq!(2, schedule.len());
+
+        // A lower transmit power drops the downlink exceeding it
+        let params = mk_region_params(ProtoRegion::Eu868, 140);
+        let dropped = schedule.revalidate(&params);
+        assert_eq!(vec![high], dropped.iter().map(|d| d.id).collect::<Vec<_>>());
+        assert!(schedule.contains(low));
+        assert!(!schedule.contains(high));
+
+        // A different region drops everything pending
+        let params = mk_region_params(ProtoRegion::Us915, 300);
+        assert_eq!(1, schedule.revalidate(&params).len());
+        assert!(schedule.is_empty());
+    }
+
+    #[test]
+    fn join_accept_priority() {
+        let eu868 = mk_region(ProtoRegion::Eu868);
+        let min_gap = 50_000;
+        let mut schedule = DownlinkSchedule::default();
+        let data = schedule.schedule(DownlinkClass::Data, 1_000_000, 0, eu868, 14);
+        let other = schedule.schedule(DownlinkClass::Data, 3_000_000, 0, eu868, 14);
+
+        let priority = CollisionStrategy::KeepHighestPriority;
+        // A join accept colliding with a data downlink preempts it
+        let preempted = schedule
+            .resolve(priority, DownlinkClass::JoinAccept, 1_010_000, 0, min_gap)
+            .expect("preempted");
+        assert_eq!(
+            vec![data],
+            preempted.iter().map(|p| p.id).collect::<Vec<_>>()
+        );
+        assert!(!schedule.contains(data));
+        assert!(schedule.contains(other));
+        let join_accept = schedule.schedule(DownlinkClass::JoinAccept, 1_010_000, 0, eu868, 14);
+
+        // A join accept is never preempted
+        assert_eq!(
+            None,
+            schedule.resolve(priority, DownlinkClass::JoinAccept, 1_020_000, 0, min_gap)
+        );
+        assert!(schedule.contains(join_accept));
+
+        let packet = |mhdr: u8| -> Packet {
+            helium_proto::Packet {
+                payload: vec![mhdr, 0, 0, 0, 0],
+                ..Default::default()
+            }
+            .into()
+        };
+        assert_eq!(
+            DownlinkClass::JoinAccept,
+            DownlinkClass::from(&packet(0x20))
+        );
+        assert_eq!(DownlinkClass::Data, DownlinkClass::from(&packet(0x60)));
+    }
+
+    #[test]
+    fn collision_strategies() {
+        let eu868 = mk_region(ProtoRegion::Eu868);
+        let min_gap = 50_000;
+        // A pending data downlink and a new downlink 10ms later or earlier
+        let collide = |strategy, class, tmst| {
+            let mut schedule = DownlinkSchedule::default();
+            let pending = schedule.schedule(DownlinkClass::Data, 1_000_000, 0, eu868, 14);
+            let kept_new = schedule
+                .resolve(strategy, class, tmst, 0, min_gap)
+                .is_some();
+            assert_eq!(kept_new, !schedule.contains(pending));
+            kept_new
+        };
+
+        use CollisionStrategy::*;
+        use DownlinkClass::*;
+        assert!(!collide(KeepFirst, Data, 1_010_000));
+        assert!(!collide(KeepFirst, JoinAccept, 990_000));
+
+        assert!(!collide(KeepHighestPriority, Data, 990_000));
+        assert!(collide(KeepHighestPriority, JoinAccept, 1_010_000));
+
+        assert!(!collide(KeepEarliestWindow, JoinAccept, 1_010_000));
+        assert!(collide(KeepEarliestWindow, Data, 990_000));
+    }
+
+    #[test]
+    fn decode_strictness() {
+        let mut strict = DecodeErrors::new(DecodeStrictness::Strict);
+        assert_eq!(slog::Level::Warning, strict.record());
+        assert_eq!(slog::Level::Warning, strict.record());
+        assert_eq!(2, strict.count);
+        assert_eq!(
+            Counters(vec![("decode_errors", 2), ("alerts", 2)]),
+            strict.counters()
+        );
+
+        // Lenient decoding counts errors but raises no alerts
+        let mut lenient = DecodeErrors::new(DecodeStrictness::Lenient);
+        assert_eq!(slog::Level::Debug, lenient.record());
+        assert_eq!(1, lenient.count);
+        assert_eq!(
+            Counters(vec![("decode_errors", 1), ("alerts", 0)]),
+            lenient.counters()
+        );
+    }
+
+    #[test]
+    fn downlink_lookahead() {
+        let mut lookahead = DownlinkLookahead::new(Duration::from_secs(10), Duration::ZERO);
+        // Nothing to compare against before the first uplink
+        assert!(lookahead.check(u64::from(u32::MAX)));
+
+        lookahead.observe(5_000_000);
+        assert!(lookahead.check(6_000_000));
+        assert!(lookahead.check(15_000_000));
+        // A downlink half an hour ahead is dropped
+        assert!(!lookahead.check(5_000_000 + 1_800_000_000));
+        assert_eq!(1, lookahead.dropped);
+        // A downlink behind the counter is late, not far ahead
+        assert!(lookahead.check(4_000_000));
+        assert!(lookahead.check(5_000_000 + 3_600_000_000));
+        assert_eq!(1, lookahead.dropped);
+
+        // Timestamps past a counter wrap are still near, and timestamps
+        // before one are still late
+        lookahead.observe(u64::from(u32::MAX) - 500_000);
+        assert!(lookahead.check(1_000_000));
+        lookahead.observe(1_000_000);
+        assert!(lookahead.check(u64::from(u32::MAX) - 500_000));
+        assert_eq!(1, lookahead.dropped);
+    }
+
+    #[test]
+    fn downlink_lookahead_skew() {
+        let mut lookahead =
+            DownlinkLookahead::new(Duration::from_secs(1), Duration::from_millis(100));
+        lookahead.observe(0);
+        // Within the skew tolerance past the lookahead
+        assert!(lookahead.check(1_050_000));
+        assert!(lookahead.check(1_100_000));
+        // Beyond the tolerance
+        assert!(!lookahead.check(1_100_001));
+        assert_eq!(1, lookahead.dropped);
+    }
+
+    #[test]
+    fn downlink_gap() {
+        let eu868 = mk_region(ProtoRegion::Eu868);
+        let min_gap = 50_000;
+        let mut schedule = DownlinkSchedule::default();
+        schedule.schedule(DownlinkClass::Data, 1_000_000, 0, eu868, 14);
+
+        // Clear of the pending downlink uses rx1
+        assert_eq!(
+            Some(false),
+            schedule.select_window((1_100_000, 0), Some((2_100_000, 0)), min_gap)
+        );
+        // Too close in rx1 is spaced out to rx2
+        assert_eq!(
+            Some(true),
+            schedule.select_window((1_010_000, 0), Some((2_010_000, 0)), min_gap)
+        );
+        schedule.schedule(DownlinkClass::Data, 2_010_000, 0, eu868, 14);
+        // Too close in both windows, or without an rx2 window, is dropped
+        assert_eq!(
+            None,
+            schedule.select_window((990_000, 0), Some((2_000_000, 0)), min_gap)
+        );
+        assert_eq!(None, schedule.select_window((1_020_000, 0), None, min_gap));
+
+        let mut downlink: Packet = helium_proto::Packet {
+            timestamp: 1_010_000,
+            frequency: 868.1,
+            datarate: "SF12BW125".to_string(),
+            rx2_window: Some(helium_proto::WindowV1 {
+                timestamp: 2_010_000,
+                frequency: 869.525,
+                datarate: "SF9BW125".to_string(),
+            }),
+            ..Default::default()
+        }
+        .into();
+        assert!(downlink.use_rx2_window());
+        assert_eq!(2_010_000, downlink.timestamp);
+        assert_eq!(869.525, downlink.frequency);
+        assert_eq!("SF9BW125", downlink.datarate);
+        assert!(downlink.rx2_window.is_none());
+        assert!(!downlink.use_rx2_window());
+    }
+
+    #[test]
+    fn downlink_gap_airtime() {
+        let eu868 = mk_region(ProtoRegion::Eu868);
+        let min_gap = 50_000;
+        let mut schedule = DownlinkSchedule::default();
+        // A downlink on air for a second
+        schedule.schedule(DownlinkClass::Data, 1_000_000, 1_000_000, eu868, 14);
+        // Gaps are measured from the end of the earlier transmission
+        assert!(!schedule.fits(1_500_000, 0, min_gap));
+        assert!(!schedule.fits(2_040_000, 0, min_gap));
+        assert!(schedule.fits(2_050_000, 0, min_gap));
+        assert!(!schedule.fits(900_000, 60_000, min_gap));
+        assert!(schedule.fits(900_000, 50_000, min_gap));
+
+        // Gaps are measured across a counter wrap
+        let mut schedule = DownlinkSchedule::default();
+        let before_wrap = u64::from(u32::MAX) - 100_000;
+        let pending = schedule.schedule(DownlinkClass::Data, before_wrap, 200_000, eu868, 14);
+        assert!(!schedule.fits(120_000, 0, min_gap));
+        assert!(schedule.fits(150_000, 0, min_gap));
+        // A downlink past the wrap is later, one before it is earlier
+        let earliest = CollisionStrategy::KeepEarliestWindow;
+        assert_eq!(
+            None,
+            schedule.resolve(earliest, DownlinkClass::Data, 120_000, 0, min_gap)
+        );
+        let removed = schedule
+            .resolve(
+                earliest,
+                DownlinkClass::Data,
+                before_wrap - 10_000,
+                0,
+                min_gap,
+            )
+            .expect("kept earlier downlink");
+        assert_eq!(
+            vec![pending],
+            removed.iter().map(|p| p.id).collect::<Vec<_>>()
+        );
+
+        // Airtime follows the data rate of the window
+        let downlink: Packet = helium_proto::Packet {
+            payload: vec![0; 13],
+            datarate: "SF7BW125".to_string(),
+            ..Default::default()
+        }
+        .into();
+        assert_eq!(46_336, airtime_micros(&downlink, false, &eu868));
+        assert_eq!(0, airtime_micros(&downlink, true, &eu868));
+    }
+
+    #[tokio::test]
+    async fn concurrent_confirmations() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use tokio::sync::Barrier;
+
+        // Two dispatches that each wait on the other complete only when run
+        // at the same time
+        let confirmations = DownlinkConfirmations::new(Some(2));
+        let barrier = Arc::new(Barrier::new(3));
+        for _ in 0..2 {
+            let barrier = barrier.clone();
+            confirmations.spawn(async move {
+                barrier.wait().await;
+            });
+        }
+        time::timeout(Duration::from_secs(1), barrier.wait())
+            .await
+            .expect("concurrent confirmations");
+
+        // A limit of one skips dispatches while one is pending
+        let confirmations = DownlinkConfirmations::new(Some(1));
+        let dispatched = Arc::new(AtomicUsize::new(0));
+        let (release_tx, release_rx) = tokio::sync::oneshot::channel::<()>();
+        let (done_tx, mut done_rx) = mpsc::channel(2);
+        let dispatch = |release: Option<tokio::sync::oneshot::Receiver<()>>| {
+            let (dispatched, done_tx) = (dispatched.clone(), done_tx.clone());
+            async move {
+                if let Some(release) = release {
+                    let _ = release.await;
+                }
+                dispatched.fetch_add(1, Ordering::SeqCst);
+                let _ = done_tx.send(()).await;
+            }
+        };
+        assert!(confirmations.spawn(dispatch(Some(release_rx))));
+        assert!(!confirmations.spawn(dispatch(None)));
+        let _ = release_tx.send(());
+        done_rx.recv().await.expect("dispatch done");
+        // The slot frees up once the pending dispatch completes
+        time::timeout(Duration::from_secs(1), async {
+            while !confirmations.spawn(dispatch(None)) {
+                time::sleep(Duration::from_millis(1)).await;
+            }
+        })
+        .await
+        .expect("free slot");
+        done_rx.recv().await.expect("dispatch done");
+        assert_eq!(2, dispatched.load(Ordering::SeqCst));
+
+        // A limit of zero never waits
+        let confirmations = DownlinkConfirmations::new(Some(0));
+        assert!(confirmations.0.is_none());
+        assert!(confirmations.spawn(dispatch(None)));
+        done_rx.recv().await.expect("dispatch done");
+    }
+
+    #[test]
+    fn downlink_schedule_snapshot() {
+        let (tx, rx) = watch::channel(DownlinkSchedule::default());
+        assert!(rx.borrow().is_empty());
+        assert_eq!(None, rx.borrow().next_tmst());
+
+        let mut ids = vec![];
+        for tmst in [3_000_000, 1_000_000, 2_000_000] {
+            tx.send_modify(|schedule| {
+                ids.push(schedule.schedule(
+                    DownlinkClass::Data,
+                    tmst,
+                    0,
+                    mk_region(ProtoRegion::Eu868),
+                    14,
+                ))
+            });
+        }
+        let snapshot = rx.borrow().clone();
+        assert_eq!(3, snapshot.len());
+        assert_eq!(Some(1_000_000), snapshot.next_tmst());
+        let order: Vec<(u64, u64)> = snapshot
+            .pending()
+            .iter()
+            .map(|pending| (pending.id, pending.tmst))
+            .collect();
+        assert_eq!(
+            vec![
+                (ids[1], 1_000_000),
+                (ids[2], 2_000_000),
+                (ids[0], 3_000_000)
+            ],
+            order
+        );
+        // As served by the local api
+        let json = serde_json::to_value(&snapshot).expect("json");
+        let first = &json["pending"][0];
+        assert_eq!(ids[1], first["id"]);
+        assert_eq!("data", first["class"]);
+        assert_eq!(1_000_000, first["tmst"]);
+        assert_eq!(14, first["tx_power"]);
+
+        tx.send_modify(|schedule| schedule.complete(ids[1]));
+        assert_eq!(2, rx.borrow().len());
+        assert_eq!(Some(2_000_000), rx.borrow().next_tmst());
+    }
+
+    #[test]
+    fn no_window_policy() {
+        // Neither window has a transmit time
+        let downlink: Packet = helium_proto::Packet {
+            timestamp: 0,
+            frequency: 869.525,
+            datarate: "SF12BW125".to_string(),
+            rx2_window: Some(helium_proto::WindowV1 {
+                timestamp: 0,
+                frequency: 869.525,
+                datarate: "SF12BW125".to_string(),
+            }),
+            ..Default::default()
+        }
+        .into();
+        assert!(!downlink.has_rx1_window());
+        assert!(!downlink.has_rx2_window());
+
+        assert!(NoWindowPolicy::default().pull_resp(&downlink, 27).is_none());
+
+        let txpk = NoWindowPolicy::Immediate
+            .pull_resp(&downlink, 27)
+            .expect("immediate transmit");
+        assert!(txpk.imme);
+        assert_eq!(27, txpk.powe);
+        assert!((txpk.freq - 869.525).abs() < 1e-3);
+
+        // Without a usable frequency and datarate nothing can be transmitted
+        let downlink: Packet = helium_proto::Packet {
+            datarate: "bogus".to_string(),
+            ..Default::default()
+        }
+        .into();
+        assert!(NoWindowPolicy::Immediate.pull_resp(&downlink, 27).is_none());
+    }
+
+    #[tokio::test]
+    async fn rebind_after_bind_failure() {
+        let logger = Logger::root(slog::Discard, o!());
+        let (_trigger, shutdown) = triggered::trigger();
+        // Hold the listen address so the first bind attempt fails
+        let blocker = std::net::UdpSocket::bind("127.0.0.1:0").expect("blocker socket");
+        let listen = blocker.local_addr().expect("blocker address").to_string();
+
+        let retry = |max_attempts| RetryPolicy {
+            max_attempts,
+            base_delay: 100,
+            max_delay: 1_000,
+            jitter: 0.0,
+        };
+
+        assert!(bind_udp_runtime(&listen, &retry(0), &shutdown, &logger)
+            .await
+            .is_err());
+
+        let release = tokio::spawn(async move {
+            time::sleep(Duration::from_millis(200)).await;
+            drop(blocker);
+        });
+        bind_udp_runtime(&listen, &retry(3), &shutdown, &logger)
+            .await
+            .expect("rebind after release");
+        release.await.expect("release task");
+    }
+
+    #[tokio::test]
+    async fn uplink_location() {
+        let logger = Logger::root(slog::Discard, o!());
+        let location = LocationSettings {
+            lat: 52.37,
+            lon: 4.89,
+            elevation: Some(12),
+        };
+        for configured in [Some(location), None] {
+            let mut settings = crate::settings::mk_test_settings();
+            settings.listen = "127.0.0.1:0".to_string();
+            settings.location = configured;
+            let (_trigger, shutdown) = triggered::trigger();
+            let (uplinks, mut uplinks_rx) = dispatcher::message_channel(1);
+            let (_messages, messages_rx) = message_channel(1);
+            let (beacons, _beacons_rx) = beaconer::message_channel(1);
+            let mut gateway =
+                Gateway::new(uplinks, messages_rx, beacons, &settings, &shutdown, &logger)
+                    .await
+                    .expect("gateway");
+
+            let uplink: Packet = helium_proto::Packet {
+                payload: vec![1, 2, 3],
+                ..Default::default()
+            }
+            .into();
+            gateway
+                .handle_uplink(&logger, uplink, None, Instant::now())
+                .await;
+            match uplinks_rx.recv().await {
+                Some(dispatcher::Message::Uplink { packet, .. }) => {
+                    assert_eq!(configured.as_ref(), packet.location())
+                }
+                other => panic!("expected uplink, got {other:?}"),
+            }
+        }
+    }
+
+    /// Collects the messages logged through it
+    #[derive(Clone, Default)]
+    struct LogCapture(Arc<std::sync::Mutex<Vec<String>>>);
+
+    impl slog::Drain for LogCapture {
+        type Ok = ();
+        type Err = slog::Never;
+
+        fn log(
+            &self,
+            record: &slog::Record,
+            _values: &slog::OwnedKVList,
+        ) -> std::result::Result<(), slog::Never> {
+            self.0
+                .lock()
+                .expect("log capture")
+                .push(record.msg().to_string());
+            Ok(())
+        }
+    }
+
+    impl LogCapture {
+        async fn wait_for(&self, prefix: &str) -> String {
+            time::timeout(Duration::from_secs(1), async {
+                loop {
+                    let logged = self.0.lock().expect("log capture").clone();
+                    if let Some(message) = logged.into_iter().find(|m| m.starts_with(prefix)) {
+                        return message;
+                    }
+                    time::sleep(Duration::from_millis(10)).await;
+                }
+            })
+            .await
+            .expect("logged message")
+        }
+    }
+
+    #[tokio::test]
+    async fn downlink_logs_redact_payload() {
+        let payload = vec![222, 173, 190, 239, 66];
+        let rx1: Packet = helium_proto::Packet {
+            timestamp: 1_000_000,
+            frequency: 868.1,
+            datarate: "SF7BW125".to_string(),
+            payload: payload.clone(),
+            ..Default::default()
+        }
+        .into();
+        let immediate: Packet = helium_proto::Packet {
+            frequency: 868.1,
+            datarate: "SF7BW125".to_string(),
+            payload: payload.clone(),
+            ..Default::default()
+        }
+        .into();
+
+        for redact in [true, false] {
+            let mut settings = crate::settings::mk_test_settings();
+            settings.listen = "127.0.0.1:0".to_string();
+            settings.log.redact_payload = redact;
+            settings.downlink.no_window = NoWindowPolicy::Immediate;
+            let capture = LogCapture::default();
+            let logger = Logger::root(capture.clone(), o!());
+            let (_trigger, shutdown) = triggered::trigger();
+            let (uplinks, _uplinks_rx) = dispatcher::message_channel(1);
+            let (_messages, messages_rx) = message_channel(1);
+            let (beacons, _beacons_rx) = beaconer::message_channel(1);
+            let mut gateway =
+                Gateway::new(uplinks, messages_rx, beacons, &settings, &shutdown, &logger)
+                    .await
+                    .expect("gateway");
+            gateway.region_params = Some(mk_region_params(ProtoRegion::Eu868, 160));
+
+            gateway.handle_downlink(&logger, rx1.clone()).await;
+            gateway.handle_downlink(&logger, immediate.clone()).await;
+            for prefix in ["rx1 downlink", "immediate downlink"] {
+                let logged = capture.wait_for(prefix).await;
+                assert_eq!(
+                    !redact,
+                    logged.contains(&format!("{payload:?}")),
+                    "{logged}"
+                );
+                assert_eq!(redact, logged.contains("len: 5, hash: "), "{logged}");
+            }
+        }
+    }
+}
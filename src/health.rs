@@ -0,0 +1,72 @@
+//! Liveness and readiness reporting for orchestration.
+//!
+//! Liveness is reported as long as the local API is serving. Readiness
+//! requires a connected gateway service and a known region, and flips back to
+//! not-ready while reconnecting.
+
+use tokio::sync::watch;
+
+/// The health service name reporting process liveness
+pub const LIVENESS_SERVICE: &str = "liveness";
+/// The health service name reporting readiness to route packets
+pub const READINESS_SERVICE: &str = "readiness";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Readiness {
+    /// Whether a gateway service connection is established
+    pub connected: bool,
+    /// Whether region parameters have been received at least once
+    pub region: bool,
+}
+
+impl Readiness {
+    pub fn is_ready(&self) -> bool {
+        self.connected && self.region
+    }
+}
+
+#[derive(Debug)]
+pub struct HealthSender(watch::Sender<Readiness>);
+pub type HealthReceiver = watch::Receiver<Readiness>;
+
+pub fn health_channel() -> (HealthSender, HealthReceiver) {
+    let (tx, rx) = watch::channel(Readiness::default());
+    (HealthSender(tx), rx)
+}
+
+impl HealthSender {
+    pub fn set_connected(&self, connected: bool) {
+        self.0
+            .send_modify(|readiness| readiness.connected = connected);
+    }
+
+    pub fn set_region(&self, region: bool) {
+        self.0.send_modify(|readiness| readiness.region = region);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn readiness_transitions() {
+        let (tx, rx) = health_channel();
+        assert!(!rx.borrow().is_ready());
+
+        // Connected but no region yet
+        tx.set_connected(true);
+        assert!(!rx.borrow().is_ready());
+
+        tx.set_region(true);
+        assert!(rx.borrow().is_ready());
+
+        // Reconnecting flips back to not ready while keeping the region
+        tx.set_connected(false);
+        assert!(!rx.borrow().is_ready());
+        assert!(rx.borrow().region);
+
+        tx.set_connected(true);
+        assert!(rx.borrow().is_ready());
+    }
+}
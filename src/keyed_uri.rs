@@ -1,6 +1,6 @@
 use crate::{PublicKey, Result};
 use http::Uri;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::{
     fmt,
     hash::{Hash, Hasher},
@@ -9,7 +9,7 @@ use std::{
 };
 
 /// A URI that has an associated public key
-#[derive(Clone, Deserialize, Eq)]
+#[derive(Clone, Deserialize, Serialize, Eq)]
 pub struct KeyedUri {
     #[serde(with = "http_serde::uri")]
     pub uri: Uri,
@@ -68,3 +68,13 @@ impl TryFrom<helium_proto::RoutingAddress> for KeyedUri {
         Ok(result)
     }
 }
+
+/// A keyed uri for the given uri with the public key of a new keypair, for
+/// tests
+#[cfg(test)]
+pub(crate) fn mk_test_uri(uri: &str) -> KeyedUri {
+    KeyedUri {
+        uri: uri.parse().expect("test uri"),
+        pubkey: Arc::new(crate::keypair::mk_test_keypair().public_key().clone()),
+    }
+}
@@ -9,7 +9,7 @@ use rand::rngs::OsRng;
 use serde::{de, Deserializer};
 #[cfg(feature = "ecc608")]
 use std::path::Path;
-use std::{collections::HashMap, convert::TryFrom, fmt, fs, io, path, str::FromStr};
+use std::{collections::HashMap, convert::TryFrom, fmt, fs, io, path, str::FromStr, sync::Arc};
 
 #[derive(Debug)]
 pub struct Keypair(helium_crypto::Keypair);
@@ -46,6 +46,15 @@ impl From<helium_crypto::Keypair> for Keypair {
 impl FromStr for Keypair {
     type Err = Error;
     fn from_str(str: &str) -> Result<Self> {
+        // pkcs11 uris have no authority and do not parse as a Uri
+        if str.starts_with(PKCS11_SCHEME) {
+            let uri = Pkcs11Uri::from_str(str)?;
+            return Err(uri_error!(
+                "no pkcs11 signing backend available for object {:?} on token {:?}",
+                uri.object,
+                uri.token
+            ));
+        }
         let url: Uri = str
             .parse()
             .map_err(|err| uri_error!("invalid keypair url \"{str}\": {err:?}"))?;
@@ -126,6 +135,153 @@ impl std::ops::Deref for Keypair {
     }
 }
 
+const PKCS11_SCHEME: &str = "pkcs11:";
+
+/// A parsed RFC 7512 PKCS#11 uri identifying a private key on a token, for
+/// example `pkcs11:token=gateway;object=key?module-path=/usr/lib/pkcs11.so`.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct Pkcs11Uri {
+    pub token: Option<String>,
+    pub slot_id: Option<u64>,
+    pub object: Option<String>,
+    pub id: Option<Vec<u8>>,
+    pub module_path: Option<String>,
+    pub pin_source: Option<String>,
+    pub pin_value: Option<String>,
+}
+
+impl FromStr for Pkcs11Uri {
+    type Err = Error;
+
+    fn from_str(str: &str) -> Result<Self> {
+        let rest = str
+            .strip_prefix(PKCS11_SCHEME)
+            .ok_or_else(|| uri_error!("not a pkcs11 uri \"{str}\""))?;
+        let (path, query) = rest.split_once('?').unwrap_or((rest, ""));
+        let mut uri = Self::default();
+        let path_attrs = path.split(';').map(|attr| (attr, true));
+        let query_attrs = query.split('&').map(|attr| (attr, false));
+        for (attr, in_path) in path_attrs.chain(query_attrs) {
+            if attr.is_empty() {
+                continue;
+            }
+            let (name, value) = attr
+                .split_once('=')
+                .ok_or_else(|| uri_error!("invalid pkcs11 attribute \"{attr}\""))?;
+            let value = percent_encoding::percent_decode_str(value).collect::<Vec<u8>>();
+            let text = || {
+                String::from_utf8(value.clone())
+                    .map_err(|_| uri_error!("invalid pkcs11 {name} value"))
+            };
+            match (name, in_path) {
+                ("token", true) => uri.token = Some(text()?),
+                ("object", true) => uri.object = Some(text()?),
+                ("id", true) => uri.id = Some(value.clone()),
+                ("slot-id", true) => {
+                    uri.slot_id = Some(
+                        text()?
+                            .parse()
+                            .map_err(|err| uri_error!("invalid pkcs11 slot-id: {err:?}"))?,
+                    )
+                }
+                ("type", true) if value == b"private" => (),
+                ("type", true) => return Err(uri_error!("pkcs11 object type must be private")),
+                ("module-path", false) => uri.module_path = Some(text()?),
+                ("pin-source", false) => uri.pin_source = Some(text()?),
+                ("pin-value", false) => uri.pin_value = Some(text()?),
+                // Other standard attributes only narrow the token match
+                (
+                    "manufacturer"
+                    | "serial"
+                    | "model"
+                    | "library-manufacturer"
+                    | "library-description"
+                    | "library-version"
+                    | "slot-description"
+                    | "slot-manufacturer",
+                    true,
+                )
+                | ("module-name", false) => (),
+                _ => return Err(uri_error!("unknown pkcs11 attribute \"{name}\"")),
+            }
+        }
+        if uri.object.is_none() && uri.id.is_none() {
+            return Err(uri_error!("pkcs11 uri requires an object or id"));
+        }
+        Ok(uri)
+    }
+}
+
+impl fmt::Debug for Pkcs11Uri {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Pkcs11Uri")
+            .field("token", &self.token)
+            .field("slot_id", &self.slot_id)
+            .field("object", &self.object)
+            .field("id", &self.id)
+            .field("module_path", &self.module_path)
+            .field("pin_source", &self.pin_source)
+            .field("pin_value", &self.pin_value.as_ref().map(|_| "<redacted>"))
+            .finish()
+    }
+}
+
+pub type Pkcs11Error = Box<dyn std::error::Error + Send + Sync>;
+
+/// Performs key operations on a PKCS#11 token
+pub trait Pkcs11Backend: Send + Sync {
+    /// Returns the public key of the object identified by the given uri
+    fn public_key(&self, uri: &Pkcs11Uri) -> std::result::Result<PublicKey, Pkcs11Error>;
+    /// Signs the given message with the object identified by the given uri
+    fn sign(&self, uri: &Pkcs11Uri, msg: &[u8]) -> std::result::Result<Vec<u8>, Pkcs11Error>;
+}
+
+/// A private key held on a PKCS#11 token. Signing happens on the token
+/// through the backend, failures there are reported as crypto errors.
+#[derive(Clone)]
+pub struct Pkcs11Keypair {
+    uri: Pkcs11Uri,
+    public_key: PublicKey,
+    backend: Arc<dyn Pkcs11Backend>,
+}
+
+impl fmt::Debug for Pkcs11Keypair {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Pkcs11Keypair")
+            .field("uri", &self.uri)
+            .field("public_key", &self.public_key)
+            .finish()
+    }
+}
+
+fn pkcs11_error(err: Pkcs11Error) -> Error {
+    helium_crypto::Error::from(signature::Error::from_source(err)).into()
+}
+
+impl Pkcs11Keypair {
+    /// Looks up the key identified by the given uri through the backend
+    pub fn new(uri: Pkcs11Uri, backend: Arc<dyn Pkcs11Backend>) -> Result<Self> {
+        let public_key = backend.public_key(&uri).map_err(pkcs11_error)?;
+        Ok(Self {
+            uri,
+            public_key,
+            backend,
+        })
+    }
+
+    pub fn uri(&self) -> &Pkcs11Uri {
+        &self.uri
+    }
+
+    pub fn public_key(&self) -> &PublicKey {
+        &self.public_key
+    }
+
+    pub fn sign(&self, msg: &[u8]) -> Result<Vec<u8>> {
+        self.backend.sign(&self.uri, msg).map_err(pkcs11_error)
+    }
+}
+
 #[derive(Debug)]
 struct KeypairArgs(HashMap<String, String>);
 
@@ -178,6 +334,18 @@ impl<'de> de::Deserialize<'de> for Keypair {
     }
 }
 
+/// A new Ed25519 keypair, for tests
+#[cfg(test)]
+pub(crate) fn mk_test_keypair() -> helium_crypto::Keypair {
+    helium_crypto::Keypair::generate(
+        KeyTag {
+            network: Network::MainNet,
+            key_type: KeyType::Ed25519,
+        },
+        &mut OsRng,
+    )
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -194,4 +362,96 @@ mod tests {
                 .expect("network")
         );
     }
+
+    #[test]
+    fn pkcs11_uri() {
+        let uri = Pkcs11Uri::from_str(
+            "pkcs11:token=gateway%20hsm;object=swarm;id=%01%02;slot-id=3;type=private\
+             ?module-path=/usr/lib/softhsm/libsofthsm2.so&pin-source=file:/etc/pin",
+        )
+        .expect("pkcs11 uri");
+        assert_eq!(
+            Pkcs11Uri {
+                token: Some("gateway hsm".to_string()),
+                slot_id: Some(3),
+                object: Some("swarm".to_string()),
+                id: Some(vec![1, 2]),
+                module_path: Some("/usr/lib/softhsm/libsofthsm2.so".to_string()),
+                pin_source: Some("file:/etc/pin".to_string()),
+                pin_value: None,
+            },
+            uri
+        );
+
+        for invalid in [
+            "pkcs11:token=gateway",
+            "pkcs11:object=swarm;slot-id=abc",
+            "pkcs11:object=swarm;type=public",
+            "pkcs11:object=swarm;bogus=1",
+            "pkcs11:object",
+        ] {
+            assert!(
+                matches!(
+                    Pkcs11Uri::from_str(invalid),
+                    Err(Error::Decode(error::DecodeError::KeypairUri(_)))
+                ),
+                "{invalid}"
+            );
+        }
+        // Without a signing backend a keypair can not be loaded from it
+        assert!(matches!(
+            Keypair::from_str("pkcs11:object=swarm"),
+            Err(Error::Decode(error::DecodeError::KeypairUri(_)))
+        ));
+    }
+
+    struct MockBackend {
+        keypair: helium_crypto::Keypair,
+        object: &'static str,
+    }
+
+    impl Pkcs11Backend for MockBackend {
+        fn public_key(&self, uri: &Pkcs11Uri) -> std::result::Result<PublicKey, Pkcs11Error> {
+            match uri.object.as_deref() {
+                Some(object) if object == self.object => Ok(self.keypair.public_key().clone()),
+                _ => Err("object not found".into()),
+            }
+        }
+
+        fn sign(&self, uri: &Pkcs11Uri, msg: &[u8]) -> std::result::Result<Vec<u8>, Pkcs11Error> {
+            use helium_crypto::Sign;
+            if msg.is_empty() {
+                return Err("token removed".into());
+            }
+            self.public_key(uri)?;
+            Ok(self.keypair.sign(msg)?)
+        }
+    }
+
+    #[test]
+    fn pkcs11_sign() {
+        use helium_crypto::Verify;
+        let backend = Arc::new(MockBackend {
+            keypair: mk_test_keypair(),
+            object: "swarm",
+        });
+        let uri = Pkcs11Uri::from_str("pkcs11:token=gateway;object=swarm?pin-value=1234")
+            .expect("pkcs11 uri");
+        let debug = format!("{uri:?}");
+        assert!(debug.contains("<redacted>") && !debug.contains("1234"));
+
+        let keypair = Pkcs11Keypair::new(uri, backend.clone()).expect("pkcs11 keypair");
+        assert_eq!(backend.keypair.public_key(), keypair.public_key());
+        let signature = keypair.sign(b"hello").expect("signature");
+        assert!(keypair.public_key().verify(b"hello", &signature).is_ok());
+        assert!(!format!("{keypair:?}").contains("1234"));
+
+        // Token failures are crypto errors
+        assert!(matches!(keypair.sign(b""), Err(Error::CryptoError(_))));
+        let missing = Pkcs11Uri::from_str("pkcs11:object=other").expect("pkcs11 uri");
+        assert!(matches!(
+            Pkcs11Keypair::new(missing, backend),
+            Err(Error::CryptoError(_))
+        ));
+    }
 }
@@ -1,12 +1,17 @@
+pub mod airtime;
 pub mod beaconer;
 pub mod cmd;
 pub mod curl;
 pub mod error;
 pub mod gateway;
+pub mod health;
 pub mod keyed_uri;
 pub mod keypair;
+pub mod metrics;
 pub mod packet;
 pub mod region;
+pub mod region_params;
+pub mod retry;
 pub mod router;
 pub mod server;
 pub mod service;
@@ -18,12 +23,16 @@ pub mod updater;
 mod api;
 mod traits;
 
+pub use airtime::airtime;
 pub use error::{Error, Result};
 pub use keyed_uri::KeyedUri;
 pub use keypair::{Keypair, PublicKey};
-pub use packet::Packet;
-pub use region::{Region, RegionParams};
-pub use settings::{CacheSettings, Settings};
+pub use packet::{Packet, PacketField, TimestampSource, UplinkSource, UplinkTime};
+pub use region::{ChannelPlan, PlanChannel, Region, RegionInference, RegionParams};
+pub use retry::RetryPolicy;
+pub use settings::{
+    CacheSettings, Capabilities, DownlinkSettings, LocationSettings, RouterSettings, Settings,
+};
 pub use traits::*;
 pub use updater::{releases, Updater};
 
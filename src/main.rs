@@ -37,6 +37,9 @@ pub enum Cmd {
     Update(cmd::update::Cmd),
     Server(cmd::server::Cmd),
     Add(Box<cmd::add::Cmd>),
+    Config(cmd::config::Cmd),
+    RouterSelftest(cmd::router_selftest::Cmd),
+    RouterFlush(cmd::router_flush::Cmd),
 }
 
 /// An empty timestamp function for when timestamp should not be included in
@@ -150,6 +153,9 @@ pub async fn run(
         Cmd::Info(cmd) => cmd.run(settings).await,
         Cmd::Update(cmd) => cmd.run(settings).await,
         Cmd::Add(cmd) => cmd.run(settings).await,
+        Cmd::Config(cmd) => cmd.run(settings).await,
+        Cmd::RouterSelftest(cmd) => cmd.run(settings, &logger).await,
+        Cmd::RouterFlush(cmd) => cmd.run(settings).await,
         Cmd::Server(cmd) => cmd.run(shutdown_listener, settings, &logger).await,
     }
 }
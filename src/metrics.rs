@@ -0,0 +1,351 @@
+//! Lightweight in-process metrics.
+//!
+//! Metrics are owned by the component that records them and are exported by
+//! logging their current values periodically.
+
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::{Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+/// How often components log the current values of their metrics
+pub const METRICS_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Named counter values of a component. Logged as one key value pair per
+/// counter, so log based collectors can pick up each counter on its own.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Counters(pub Vec<(&'static str, u64)>);
+
+impl slog::KV for Counters {
+    fn serialize(
+        &self,
+        _record: &slog::Record,
+        serializer: &mut dyn slog::Serializer,
+    ) -> slog::Result {
+        for (name, value) in &self.0 {
+            serializer.emit_u64(name, *value)?;
+        }
+        Ok(())
+    }
+}
+
+/// A histogram of durations with fixed millisecond bucket upper bounds. Values
+/// above the largest bound are counted in an overflow bucket.
+#[derive(Debug, Clone, Serialize)]
+pub struct Histogram {
+    bounds: Vec<u64>,
+    counts: Vec<u64>,
+    count: u64,
+    sum: u64,
+}
+
+impl Histogram {
+    /// Construct a histogram with the given bucket upper bounds in
+    /// milliseconds. The bounds are sorted and deduplicated.
+    pub fn new(bounds: &[u64]) -> Self {
+        let mut bounds = bounds.to_vec();
+        bounds.sort_unstable();
+        bounds.dedup();
+        let counts = vec![0; bounds.len() + 1];
+        Self {
+            bounds,
+            counts,
+            count: 0,
+            sum: 0,
+        }
+    }
+
+    pub fn record(&mut self, duration: Duration) {
+        let millis = duration.as_millis() as u64;
+        let index = self
+            .bounds
+            .iter()
+            .position(|bound| millis <= *bound)
+            .unwrap_or(self.bounds.len());
+        self.counts[index] += 1;
+        self.count += 1;
+        self.sum += millis;
+    }
+
+    /// The number of recorded values
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// The sum of all recorded values in milliseconds
+    pub fn sum(&self) -> u64 {
+        self.sum
+    }
+
+    /// The bucket counts, the overflow bucket count, the count and the sum as
+    /// counters named `le_<bound>ms`, `inf`, `count` and `sum_ms`
+    pub fn counters(&self) -> Counters {
+        let mut counters: Vec<(&'static str, u64)> = self
+            .bounds
+            .iter()
+            .zip(&self.counts)
+            .map(|(bound, count)| (bucket_key(*bound), *count))
+            .collect();
+        counters.push(("inf", self.counts[self.bounds.len()]));
+        counters.push(("count", self.count));
+        counters.push(("sum_ms", self.sum));
+        Counters(counters)
+    }
+
+    /// The (non-cumulative) count for each bucket, followed by the overflow
+    /// bucket.
+    pub fn counts(&self) -> &[u64] {
+        &self.counts
+    }
+}
+
+/// The counter name of the bucket with the given upper bound. Log keys are
+/// static, so names are interned and each configured bound is allocated once.
+fn bucket_key(bound: u64) -> &'static str {
+    static KEYS: Mutex<BTreeMap<u64, &'static str>> = Mutex::new(BTreeMap::new());
+    let mut keys = KEYS.lock().unwrap_or_else(PoisonError::into_inner);
+    let key = keys
+        .entry(bound)
+        .or_insert_with(|| Box::leak(format!("le_{bound}ms").into_boxed_str()));
+    *key
+}
+
+/// A rolling per-second event rate meter with an alert threshold.
+///
+/// Alerts fire once when the rate rises above the threshold and re-arm once
+/// the rate has dropped back to or below it.
+#[derive(Debug)]
+pub struct RateMeter {
+    events: VecDeque<Instant>,
+    threshold: u32,
+    alerting: bool,
+}
+
+/// Raised when a [`RateMeter`] rate first exceeds its threshold
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateAlert {
+    /// Events seen in the last second
+    pub rate: u32,
+    pub threshold: u32,
+}
+
+impl RateMeter {
+    const WINDOW: Duration = Duration::from_secs(1);
+
+    pub fn new(threshold: u32) -> Self {
+        Self {
+            events: VecDeque::new(),
+            threshold,
+            alerting: false,
+        }
+    }
+
+    /// Records an event at the given time and returns an alert if this event
+    /// pushed the rate over the threshold.
+    pub fn record(&mut self, now: Instant) -> Option<RateAlert> {
+        self.expire(now);
+        self.events.push_back(now);
+        let rate = self.rate();
+        if rate <= self.threshold {
+            self.alerting = false;
+            return None;
+        }
+        if self.alerting {
+            return None;
+        }
+        self.alerting = true;
+        Some(RateAlert {
+            rate,
+            threshold: self.threshold,
+        })
+    }
+
+    /// The number of events in the current one second window
+    pub fn rate(&self) -> u32 {
+        self.events.len() as u32
+    }
+
+    /// The number of events in the one second window ending at the given time
+    pub fn rate_at(&mut self, now: Instant) -> u32 {
+        self.expire(now);
+        self.rate()
+    }
+
+    fn expire(&mut self, now: Instant) {
+        while let Some(oldest) = self.events.front() {
+            if now.saturating_duration_since(*oldest) < Self::WINDOW {
+                break;
+            }
+            self.events.pop_front();
+        }
+    }
+}
+
+/// Limits events to a maximum number per rolling second, counting the events
+/// that were rejected.
+#[derive(Debug)]
+pub struct RateLimiter {
+    meter: RateMeter,
+    limit: u32,
+    rejected: u64,
+}
+
+impl RateLimiter {
+    pub fn new(limit: u32) -> Self {
+        Self {
+            meter: RateMeter::new(limit),
+            limit,
+            rejected: 0,
+        }
+    }
+
+    /// Returns whether an event at the given time is within the limit. Allowed
+    /// events are recorded, rejected ones are counted.
+    pub fn check(&mut self, now: Instant) -> bool {
+        if self.meter.rate_at(now) >= self.limit {
+            self.rejected += 1;
+            return false;
+        }
+        self.meter.record(now);
+        true
+    }
+
+    /// The maximum number of events per second
+    pub fn limit(&self) -> u32 {
+        self.limit
+    }
+
+    /// The total number of rejected events
+    pub fn rejected(&self) -> u64 {
+        self.rejected
+    }
+}
+
+/// Collects the key value pairs of logged records as `key=value` strings,
+/// for tests to check what was logged
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub(crate) struct KvCapture(std::sync::Arc<Mutex<Vec<String>>>);
+
+#[cfg(test)]
+impl KvCapture {
+    /// A logger that collects into this capture
+    pub fn logger(&self) -> slog::Logger {
+        slog::Logger::root(self.clone(), slog::o!())
+    }
+
+    /// The key value pairs logged so far, in logging order
+    pub fn logged(&self) -> Vec<String> {
+        self.0.lock().expect("kv capture").clone()
+    }
+}
+
+#[cfg(test)]
+impl slog::Drain for KvCapture {
+    type Ok = ();
+    type Err = slog::Never;
+
+    fn log(
+        &self,
+        record: &slog::Record,
+        _values: &slog::OwnedKVList,
+    ) -> std::result::Result<(), slog::Never> {
+        use slog::KV;
+        let _ = record.kv().serialize(record, &mut self.clone());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+impl slog::Serializer for KvCapture {
+    fn emit_arguments(&mut self, key: slog::Key, val: &std::fmt::Arguments) -> slog::Result {
+        self.0
+            .lock()
+            .expect("kv capture")
+            .push(format!("{key}={val}"));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counters_logged() {
+        let capture = KvCapture::default();
+        let counters = Counters(vec![("offered", 7), ("evictions", 0)]);
+        slog::info!(capture.logger(), "metrics"; counters);
+        assert_eq!(vec!["offered=7", "evictions=0"], capture.logged());
+    }
+
+    #[test]
+    fn histogram_buckets() {
+        let mut histogram = Histogram::new(&[100, 10, 1000]);
+        histogram.record(Duration::from_millis(5));
+        histogram.record(Duration::from_millis(10));
+        histogram.record(Duration::from_millis(250));
+        histogram.record(Duration::from_secs(5));
+        assert_eq!(&[2, 0, 1, 1], histogram.counts());
+        assert_eq!(4, histogram.count());
+        assert_eq!(5265, histogram.sum());
+        assert_eq!(
+            Counters(vec![
+                ("le_10ms", 2),
+                ("le_100ms", 0),
+                ("le_1000ms", 1),
+                ("inf", 1),
+                ("count", 4),
+                ("sum_ms", 5265),
+            ]),
+            histogram.counters()
+        );
+    }
+
+    #[test]
+    fn rate_meter_alert() {
+        let mut meter = RateMeter::new(3);
+        let start = Instant::now();
+        let at = |millis| start + Duration::from_millis(millis);
+
+        // Steady traffic under the threshold never alerts
+        for i in 0..3 {
+            assert_eq!(None, meter.record(at(i * 100)));
+        }
+        // A burst above the threshold alerts once
+        assert_eq!(
+            Some(RateAlert {
+                rate: 4,
+                threshold: 3
+            }),
+            meter.record(at(300))
+        );
+        assert_eq!(None, meter.record(at(400)));
+        assert_eq!(5, meter.rate());
+
+        // Once the window slides past the burst the alert re-arms
+        assert_eq!(None, meter.record(at(2000)));
+        assert_eq!(1, meter.rate());
+        for i in 1..3 {
+            assert_eq!(None, meter.record(at(2000 + i * 10)));
+        }
+        assert!(meter.record(at(2050)).is_some());
+    }
+
+    #[test]
+    fn rate_limiter_throttles() {
+        let mut limiter = RateLimiter::new(5);
+        let start = Instant::now();
+        // A flood of 20 events within one second only lets the limit through
+        let allowed = (0..20)
+            .filter(|i| limiter.check(start + Duration::from_millis(i * 10)))
+            .count();
+        assert_eq!(5, allowed);
+        assert_eq!(15, limiter.rejected());
+
+        // The next second allows events again
+        assert!(limiter.check(start + Duration::from_millis(1100)));
+    }
+}
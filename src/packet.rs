@@ -1,7 +1,7 @@
-use crate::{error::DecodeError, Error, Result};
+use crate::{error::DecodeError, Base64, Error, LocationSettings, Region, Result};
 use helium_proto::{
     packet::PacketType, routing_information::Data as RoutingData, services::poc_lora,
-    BlockchainStateChannelResponseV1, DataRate as ProtoDataRate, Eui, RoutingInformation,
+    BlockchainStateChannelResponseV1, DataRate as ProtoDataRate, Eui, Message, RoutingInformation,
 };
 use lorawan::{Direction, PHYPayloadFrame, MHDR};
 use semtech_udp::{
@@ -9,17 +9,173 @@ use semtech_udp::{
     push_data::{self, CRC},
     CodingRate, DataRate, Modulation, StringOrNum,
 };
+use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use std::{
     convert::TryFrom,
     fmt,
     ops::Deref,
     str::FromStr,
-    time::{SystemTime, UNIX_EPOCH},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 #[derive(Debug, Clone)]
-pub struct Packet(helium_proto::Packet);
+pub struct Packet(
+    helium_proto::Packet,
+    Option<UplinkSource>,
+    Option<UplinkTime>,
+    Option<LocationSettings>,
+);
+
+/// Where an uplink was received: the listen address of the packet forwarder
+/// listener and the MAC of the concentrator that forwarded it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UplinkSource {
+    pub listener: String,
+    pub mac: String,
+}
+
+impl fmt::Display for UplinkSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}@{}", self.mac, self.listener)
+    }
+}
+
+/// Packet fields that can be left out of the normalized packet hash
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PacketField {
+    SignalStrength,
+    Snr,
+    Timestamp,
+    Frequency,
+    Datarate,
+}
+
+/// Time sources an uplink can be stamped with
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TimestampSource {
+    /// The GPS time reported by the packet forwarder
+    Gps,
+    /// The concentrator counter of the packet forwarder
+    Counter,
+    /// The system clock of the gateway
+    System,
+}
+
+impl fmt::Display for TimestampSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Gps => f.write_str("gps"),
+            Self::Counter => f.write_str("counter"),
+            Self::System => f.write_str("system"),
+        }
+    }
+}
+
+/// The time an uplink was received and the source it was taken from. GPS and
+/// system times are microseconds since the unix epoch, counter times are
+/// microseconds of the concentrator counter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UplinkTime {
+    pub source: TimestampSource,
+    pub time: u64,
+}
+
+impl UplinkTime {
+    /// Stamps a received packet using the first available source in the
+    /// given preference order.
+    pub fn from_rxpk(preference: &[TimestampSource], rxpk: &push_data::RxPk) -> Option<Self> {
+        Self::select(preference, |source| match source {
+            TimestampSource::Gps => rxpk.get_time().as_deref().and_then(parse_utc_micros),
+            TimestampSource::Counter => Some(*rxpk.get_timestamp() as u64),
+            TimestampSource::System => SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .ok()
+                .map(|time| time.as_micros() as u64),
+        })
+    }
+
+    /// Picks the first source in the given preference order that has a time,
+    /// falling through to the next source when one is unavailable.
+    pub fn select<F>(preference: &[TimestampSource], mut time: F) -> Option<Self>
+    where
+        F: FnMut(TimestampSource) -> Option<u64>,
+    {
+        preference.iter().find_map(|source| {
+            time(*source).map(|time| Self {
+                source: *source,
+                time,
+            })
+        })
+    }
+}
+
+/// Parses a UTC time in the compact ISO 8601 format of the packet forwarder,
+/// for example "2013-03-31T16:21:17.528002Z", into microseconds since the unix
+/// epoch.
+fn parse_utc_micros(value: &str) -> Option<u64> {
+    let value = value.strip_suffix('Z')?;
+    let (date, time) = value.split_once('T')?;
+    let mut date = date.splitn(3, '-').map(|v| v.parse::<i64>().ok());
+    let (year, month, day) = (date.next()??, date.next()??, date.next()??);
+    let (time, fraction) = time.split_once('.').unwrap_or((time, ""));
+    let mut time = time.splitn(3, ':').map(|v| v.parse::<i64>().ok());
+    let (hour, minute, second) = (time.next()??, time.next()??, time.next()??);
+    if !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || hour > 23
+        || minute > 59
+        || !(0..=59).contains(&second)
+    {
+        return None;
+    }
+    let micros = if fraction.is_empty() {
+        0
+    } else {
+        let digits = &fraction[..fraction.len().min(6)];
+        digits.parse::<i64>().ok()? * 10i64.pow(6 - digits.len() as u32)
+    };
+    // Days since the unix epoch of the civil date, after Howard Hinnant's
+    // days_from_civil
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+    let seconds = days * 86_400 + hour * 3_600 + minute * 60 + second;
+    u64::try_from(seconds * 1_000_000 + micros).ok()
+}
+
+/// Formats payload bytes for logging. When redacted only the size and a hash
+/// of the payload are shown.
+pub struct LogPayload<'a> {
+    payload: &'a [u8],
+    redact: bool,
+}
+
+impl<'a> LogPayload<'a> {
+    pub fn new(payload: &'a [u8], redact: bool) -> Self {
+        Self { payload, redact }
+    }
+}
+
+impl fmt::Display for LogPayload<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.redact {
+            write!(
+                f,
+                "len: {}, hash: {}",
+                self.payload.len(),
+                Sha256::digest(self.payload).to_vec().to_b64()
+            )
+        } else {
+            write!(f, "{:?}", self.payload)
+        }
+    }
+}
 
 impl Deref for Packet {
     type Target = helium_proto::Packet;
@@ -68,7 +224,7 @@ impl TryFrom<push_data::RxPk> for Packet {
                 rx2_window: None,
                 oui: 0,
             };
-            Ok(Self(packet))
+            Ok(Self(packet, None, None, None))
         } else {
             Err(DecodeError::invalid_crc())
         }
@@ -77,11 +233,45 @@ impl TryFrom<push_data::RxPk> for Packet {
 
 impl From<helium_proto::Packet> for Packet {
     fn from(v: helium_proto::Packet) -> Self {
-        Self(v)
+        Self(v, None, None, None)
     }
 }
 
 impl Packet {
+    /// Tags this uplink with where it was received. The tag is not part of
+    /// the routed packet.
+    pub fn with_source(self, source: UplinkSource) -> Self {
+        Self(self.0, Some(source), self.2, self.3)
+    }
+
+    /// Where this uplink was received, if tagged
+    pub fn source(&self) -> Option<&UplinkSource> {
+        self.1.as_ref()
+    }
+
+    /// Stamps this uplink with the time it was received, if known. The
+    /// stamp is not part of the routed packet.
+    pub fn with_time(self, time: Option<UplinkTime>) -> Self {
+        Self(self.0, self.1, time, self.3)
+    }
+
+    /// The time this uplink was received, if stamped
+    pub fn time(&self) -> Option<UplinkTime> {
+        self.2
+    }
+
+    /// Tags this uplink with the location of the gateway that received it,
+    /// if configured. The state channel packet has no location field, so the
+    /// tag is not part of the routed packet.
+    pub fn with_location(self, location: Option<LocationSettings>) -> Self {
+        Self(self.0, self.1, self.2, location)
+    }
+
+    /// The location of the gateway that received this uplink, if tagged
+    pub fn location(&self) -> Option<&LocationSettings> {
+        self.3.as_ref()
+    }
+
     pub fn routing(&self) -> &Option<RoutingInformation> {
         &self.0.routing
     }
@@ -94,6 +284,10 @@ impl Packet {
         &self.0.payload
     }
 
+    pub fn log_payload(&self, redact: bool) -> LogPayload {
+        LogPayload::new(self.payload(), redact)
+    }
+
     pub fn routing_information(frame: &PHYPayloadFrame) -> Result<Option<RoutingInformation>> {
         let routing_data = match frame {
             PHYPayloadFrame::JoinRequest(request) => Some(RoutingData::Eui(Eui {
@@ -108,6 +302,14 @@ impl Packet {
         Ok(routing_data.map(|r| RoutingInformation { data: Some(r) }))
     }
 
+    /// The DevAddr of a data frame travelling in the given direction
+    pub fn dev_addr(&self, direction: lorawan::Direction) -> Option<u32> {
+        match Self::parse_frame(direction, self.payload()) {
+            Ok(PHYPayloadFrame::MACPayload(mac_payload)) => Some(mac_payload.dev_addr()),
+            _ => None,
+        }
+    }
+
     pub fn parse_frame(direction: lorawan::Direction, payload: &[u8]) -> Result<PHYPayloadFrame> {
         use std::io::Cursor;
         lorawan::PHYPayload::read(direction, &mut Cursor::new(payload))
@@ -120,6 +322,12 @@ impl Packet {
         lorawan::MHDR::read(&mut Cursor::new(payload)).map_err(Error::from)
     }
 
+    pub fn is_join_accept(&self) -> bool {
+        Self::parse_header(self.payload())
+            .map(|header| header.mtype() == lorawan::MType::JoinAccept)
+            .unwrap_or(false)
+    }
+
     pub fn is_potential_beacon(&self) -> bool {
         Self::parse_header(self.payload())
             .map(|header| header.mtype() == lorawan::MType::Proprietary)
@@ -140,7 +348,66 @@ impl Packet {
                 self.0.datarate.parse()?,
             )
         };
-        Ok(Some(pull_resp::TxPk {
+        Ok(Some(
+            self.mk_pull_resp(timestamp, frequency, datarate, tx_power),
+        ))
+    }
+
+    /// The time on air of this downlink in its rx1 window, or its rx2 window
+    /// if `use_rx2`, in the given region. None without that window or with a
+    /// data rate the region does not use.
+    pub fn downlink_airtime(&self, use_rx2: bool, region: &Region) -> Option<Duration> {
+        let datarate = if use_rx2 {
+            &self.0.rx2_window.as_ref()?.datarate
+        } else {
+            &self.0.datarate
+        };
+        let datarate = ProtoDataRate::from_str(datarate).ok()?;
+        crate::airtime(datarate, self.0.payload.len(), region).ok()
+    }
+
+    /// Whether the rx1 window of this downlink can be transmitted in. A
+    /// window needs a transmit timestamp, a frequency and a known datarate.
+    pub fn has_rx1_window(&self) -> bool {
+        is_valid_window(self.0.timestamp, self.0.frequency, &self.0.datarate)
+    }
+
+    /// Whether this downlink has an rx2 window that can be transmitted in
+    pub fn has_rx2_window(&self) -> bool {
+        self.0.rx2_window.as_ref().map_or(false, |rx2| {
+            is_valid_window(rx2.timestamp, rx2.frequency, &rx2.datarate)
+        })
+    }
+
+    /// A best effort immediate transmit of a downlink that has no valid
+    /// window, using the frequency and datarate of the first window that has
+    /// both. Returns None if neither window does.
+    pub fn to_immediate_pull_resp(&self, tx_power: u32) -> Option<pull_resp::TxPk> {
+        let rx1 = (self.0.frequency, self.0.datarate.as_str());
+        let rx2 = self
+            .0
+            .rx2_window
+            .as_ref()
+            .map(|rx2| (rx2.frequency, rx2.datarate.as_str()));
+        std::iter::once(rx1)
+            .chain(rx2)
+            .filter(|(frequency, _)| *frequency > 0.0)
+            .find_map(|(frequency, datarate)| {
+                datarate
+                    .parse()
+                    .ok()
+                    .map(|datarate| self.mk_pull_resp(None, frequency, datarate, tx_power))
+            })
+    }
+
+    fn mk_pull_resp(
+        &self,
+        timestamp: Option<u64>,
+        frequency: f32,
+        datarate: DataRate,
+        tx_power: u32,
+    ) -> pull_resp::TxPk {
+        pull_resp::TxPk {
             imme: timestamp.is_none(),
             ipol: true,
             modu: Modulation::LORA,
@@ -160,7 +427,48 @@ impl Packet {
             fdev: None,
             prea: None,
             ncrc: None,
-        }))
+        }
+    }
+
+    /// Reschedules this downlink to transmit the given delay after the
+    /// timestamp of the uplink it responds to. An rx2 window, if present, is
+    /// moved to follow one second after the new rx1 window. Timestamps wrap
+    /// like the 32 bit concentrator counter they refer to.
+    pub fn set_rx1_delay(&mut self, uplink_timestamp: u64, delay: Duration) {
+        let rx1_timestamp = (uplink_timestamp as u32).wrapping_add(delay.as_micros() as u32);
+        self.0.timestamp = rx1_timestamp as u64;
+        if let Some(rx2) = self.0.rx2_window.as_mut() {
+            rx2.timestamp = rx1_timestamp.wrapping_add(1_000_000) as u64;
+        }
+    }
+
+    /// Moves the transmit timestamps of this downlink earlier by the given
+    /// offset to compensate for fixed hardware transmit latency. Timestamps
+    /// wrap like the 32 bit concentrator counter they refer to.
+    pub fn advance_timestamps(&mut self, offset: Duration) {
+        if offset.is_zero() {
+            return;
+        }
+        let offset = offset.as_micros() as u32;
+        let advance = |timestamp: u64| (timestamp as u32).wrapping_sub(offset) as u64;
+        self.0.timestamp = advance(self.0.timestamp);
+        if let Some(rx2) = self.0.rx2_window.as_mut() {
+            rx2.timestamp = advance(rx2.timestamp);
+        }
+    }
+
+    /// Moves the rx2 window of this downlink into its primary timing, so it is
+    /// sent in the rx2 window only. Returns false if there is no rx2 window.
+    pub fn use_rx2_window(&mut self) -> bool {
+        match self.0.rx2_window.take() {
+            Some(rx2) => {
+                self.0.timestamp = rx2.timestamp;
+                self.0.frequency = rx2.frequency;
+                self.0.datarate = rx2.datarate;
+                true
+            }
+            None => false,
+        }
     }
 
     pub fn from_state_channel_response(response: BlockchainStateChannelResponseV1) -> Option<Self> {
@@ -171,6 +479,22 @@ impl Packet {
         Sha256::digest(&self.0.payload).to_vec()
     }
 
+    /// A hash of the full encoded packet with the given fields cleared, so
+    /// receptions differing only in those fields hash the same.
+    pub fn normalized_hash(&self, exclude: &[PacketField]) -> Vec<u8> {
+        let mut packet = self.0.clone();
+        for field in exclude {
+            match field {
+                PacketField::SignalStrength => packet.signal_strength = 0.0,
+                PacketField::Snr => packet.snr = 0.0,
+                PacketField::Timestamp => packet.timestamp = 0,
+                PacketField::Frequency => packet.frequency = 0.0,
+                PacketField::Datarate => packet.datarate.clear(),
+            }
+        }
+        Sha256::digest(packet.encode_to_vec()).to_vec()
+    }
+
     pub fn dc_payload(&self) -> u64 {
         const DC_PAYLOAD_SIZE: usize = 24;
         let payload_size = self.payload().len();
@@ -228,3 +552,230 @@ impl Packet {
 fn to_hz(mhz: f32) -> u64 {
     (mhz * 1_000_000f32).trunc() as u64
 }
+
+fn is_valid_window(timestamp: u64, frequency: f32, datarate: &str) -> bool {
+    timestamp > 0 && frequency > 0.0 && datarate.parse::<DataRate>().is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tmst(txpk: &pull_resp::TxPk) -> u32 {
+        match txpk.tmst {
+            Some(StringOrNum::N(tmst)) => tmst,
+            _ => panic!("expected numeric tmst"),
+        }
+    }
+
+    #[test]
+    fn uplink_time_fallthrough() {
+        use TimestampSource::*;
+        let preference = [Gps, Counter, System];
+
+        // No GPS time falls through to the counter
+        let time = UplinkTime::select(&preference, |source| match source {
+            Gps => None,
+            Counter => Some(1_000),
+            System => Some(2_000),
+        });
+        assert_eq!(
+            Some(UplinkTime {
+                source: Counter,
+                time: 1_000
+            }),
+            time
+        );
+
+        // The preferred source wins when available
+        let time = UplinkTime::select(&preference, |_| Some(3_000)).expect("uplink time");
+        assert_eq!(Gps, time.source);
+
+        // Sources not in the preference are never used
+        assert_eq!(
+            None,
+            UplinkTime::select(&[Gps], |source| match source {
+                Gps => None,
+                _ => Some(4_000),
+            })
+        );
+
+        assert_eq!(
+            Some(1_364_746_877_528_002),
+            parse_utc_micros("2013-03-31T16:21:17.528002Z")
+        );
+        assert_eq!(Some(0), parse_utc_micros("1970-01-01T00:00:00Z"));
+        assert_eq!(None, parse_utc_micros("2013-03-31 16:21:17"));
+        assert_eq!(None, parse_utc_micros("2013-03-31T16:21:75Z"));
+        assert_eq!(None, parse_utc_micros("2013-03-31T16:21:-1Z"));
+
+        // The stamp travels with the packet
+        let time = UplinkTime {
+            source: Counter,
+            time: 1_000,
+        };
+        let packet = Packet::from(helium_proto::Packet::default()).with_time(Some(time));
+        assert_eq!(Some(time), packet.time());
+    }
+
+    #[test]
+    fn rx1_delay_override() {
+        let mut downlink = Packet::from(helium_proto::Packet {
+            timestamp: 1_000_000 + 1_000_000,
+            frequency: 868.1,
+            datarate: "SF7BW125".to_string(),
+            rx2_window: Some(helium_proto::WindowV1 {
+                timestamp: 1_000_000 + 2_000_000,
+                frequency: 869.525,
+                datarate: "SF12BW125".to_string(),
+            }),
+            ..Default::default()
+        });
+        let rx1 = downlink.to_pull_resp(false, 27).unwrap().unwrap();
+        assert_eq!(2_000_000, tmst(&rx1));
+
+        downlink.set_rx1_delay(1_000_000, Duration::from_secs(5));
+        let rx1 = downlink.to_pull_resp(false, 27).unwrap().unwrap();
+        assert_eq!(6_000_000, tmst(&rx1));
+        let rx2 = downlink.to_pull_resp(true, 27).unwrap().unwrap();
+        assert_eq!(7_000_000, tmst(&rx2));
+
+        // Windows past the end of the counter wrap like the concentrator
+        // counter
+        let uplink_timestamp = u32::MAX as u64 - 1_499_999;
+        downlink.set_rx1_delay(uplink_timestamp, Duration::from_secs(1));
+        assert_eq!(u32::MAX as u64 - 499_999, downlink.timestamp);
+        let rx2 = downlink.rx2_window.as_ref().expect("rx2 window");
+        assert_eq!(500_000, rx2.timestamp);
+
+        downlink.set_rx1_delay(uplink_timestamp, Duration::from_secs(2));
+        assert_eq!(500_000, downlink.timestamp);
+        let rx2 = downlink.rx2_window.as_ref().expect("rx2 window");
+        assert_eq!(1_500_000, rx2.timestamp);
+    }
+
+    #[test]
+    fn redacted_payload() {
+        let payload = [0xde, 0xad, 0xbe, 0xef, 0x01, 0x02];
+        let redacted = LogPayload::new(&payload, true).to_string();
+        assert!(redacted.starts_with("len: 6, hash: "));
+        assert!(!redacted.contains(&format!("{payload:?}")));
+        assert!(!redacted.contains(&payload.to_vec().to_b64()));
+
+        let full = LogPayload::new(&payload, false).to_string();
+        assert_eq!(format!("{payload:?}"), full);
+    }
+
+    #[test]
+    fn tx_compensation() {
+        let mut downlink: Packet = helium_proto::Packet {
+            timestamp: 6_000_000,
+            rx2_window: Some(helium_proto::WindowV1 {
+                timestamp: 7_000_000,
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+        .into();
+        downlink.advance_timestamps(Duration::from_micros(1_500));
+        assert_eq!(5_998_500, downlink.timestamp);
+        assert_eq!(
+            6_998_500,
+            downlink.rx2_window.as_ref().expect("rx2 window").timestamp
+        );
+
+        // Advancing past zero wraps like the concentrator counter
+        let mut downlink: Packet = helium_proto::Packet {
+            timestamp: 1_000,
+            ..Default::default()
+        }
+        .into();
+        downlink.advance_timestamps(Duration::from_micros(1_500));
+        assert_eq!(u32::MAX as u64 - 499, downlink.timestamp);
+    }
+
+    /// Feeds a payload through the uplink decode path, checking that it only
+    /// ever fails with a decode error.
+    fn decode_uplink(payload: &[u8]) {
+        for direction in [Direction::Uplink, Direction::Downlink] {
+            match Packet::parse_frame(direction, payload) {
+                Ok(frame) => {
+                    Packet::routing_information(&frame).expect("routing information");
+                }
+                Err(Error::Decode(_)) => (),
+                Err(err) => panic!("unexpected error for {payload:?}: {err:?}"),
+            }
+        }
+        let packet: Packet = helium_proto::Packet {
+            payload: payload.to_vec(),
+            datarate: "SF7BW125".to_string(),
+            ..Default::default()
+        }
+        .into();
+        if packet.is_potential_beacon() {
+            let _ = packet.to_witness_report();
+        }
+    }
+
+    #[test]
+    fn normalized_hash() {
+        let mk_packet = |signal_strength, snr| -> Packet {
+            helium_proto::Packet {
+                payload: vec![1, 2, 3],
+                timestamp: 1_000,
+                signal_strength,
+                snr,
+                ..Default::default()
+            }
+            .into()
+        };
+        let (a, b) = (mk_packet(-80.0, 5.5), mk_packet(-95.0, -2.0));
+        assert_ne!(a.normalized_hash(&[]), b.normalized_hash(&[]));
+
+        let exclude = [PacketField::SignalStrength, PacketField::Snr];
+        assert_eq!(a.normalized_hash(&exclude), b.normalized_hash(&exclude));
+        // Fields that are not excluded still tell packets apart
+        let c: Packet = helium_proto::Packet {
+            timestamp: 2_000,
+            ..a.0.clone()
+        }
+        .into();
+        assert_ne!(a.normalized_hash(&exclude), c.normalized_hash(&exclude));
+    }
+
+    #[test]
+    fn decode_fuzz() {
+        use rand::{rngs::StdRng, Rng, SeedableRng};
+
+        let mut rng = StdRng::seed_from_u64(0x6c6f7261);
+        // Every header byte with every length around the frame size limits
+        for mhdr in 0..=u8::MAX {
+            for len in 0..40 {
+                let mut payload = vec![mhdr];
+                payload.extend((0..len).map(|_| rng.gen::<u8>()));
+                decode_uplink(&payload);
+            }
+        }
+        // Random payloads up to the maximum LoRa payload size
+        for _ in 0..10_000 {
+            let len = rng.gen_range(0..=255);
+            let payload: Vec<u8> = (0..len).map(|_| rng.gen()).collect();
+            decode_uplink(&payload);
+        }
+    }
+
+    #[test]
+    fn decode_regressions() {
+        // Data frames claiming more fopts than they carry
+        decode_uplink(&[0x40, 1, 2, 3, 4, 0x0f, 0, 0, 0, 0, 0, 0]);
+        // fport 0 with fopts
+        decode_uplink(&[0x40, 1, 2, 3, 4, 0x01, 0, 0, 0xaa, 0, 0, 0, 0, 0]);
+        // Empty and header only payloads
+        decode_uplink(&[]);
+        decode_uplink(&[0xe0]);
+        assert!(matches!(
+            Packet::parse_frame(Direction::Uplink, &[0xc0; 12]),
+            Err(Error::Decode(DecodeError::LoraWan(_)))
+        ));
+    }
+}
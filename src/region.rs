@@ -4,10 +4,10 @@ use helium_proto::{
     Region as ProtoRegion,
 };
 use rust_decimal::Decimal;
-use serde::{de, Deserialize, Deserializer};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 use std::{fmt, str::FromStr};
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Region(ProtoRegion);
 
 impl From<Region> for ProtoRegion {
@@ -44,6 +44,12 @@ impl<'de> Deserialize<'de> for Region {
     }
 }
 
+impl Serialize for Region {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
 impl fmt::Display for Region {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         self.0.fmt(f)
@@ -81,6 +87,133 @@ impl slog::Value for Region {
     }
 }
 
+/// Uplink channel frequencies in Hz of the common channel plans, used to infer
+/// a region from observed traffic.
+fn uplink_channels(region: ProtoRegion) -> Vec<u64> {
+    let spaced = |start: u64, step: u64, count: u64| (0..count).map(move |n| start + n * step);
+    match region {
+        ProtoRegion::Us915 => spaced(902_300_000, 200_000, 64)
+            .chain(spaced(903_000_000, 1_600_000, 8))
+            .collect(),
+        ProtoRegion::Au915 => spaced(915_200_000, 200_000, 64)
+            .chain(spaced(915_900_000, 1_600_000, 8))
+            .collect(),
+        ProtoRegion::Cn470 => spaced(470_300_000, 200_000, 96).collect(),
+        ProtoRegion::Eu868 => spaced(867_100_000, 200_000, 5)
+            .chain(spaced(868_100_000, 200_000, 3))
+            .collect(),
+        ProtoRegion::In865 => vec![865_062_500, 865_402_500, 865_985_000],
+        ProtoRegion::Kr920 => spaced(922_100_000, 200_000, 3).collect(),
+        ProtoRegion::As9231 => spaced(922_000_000, 200_000, 8).collect(),
+        _ => vec![],
+    }
+}
+
+const INFERABLE_REGIONS: &[ProtoRegion] = &[
+    ProtoRegion::Us915,
+    ProtoRegion::Au915,
+    ProtoRegion::Cn470,
+    ProtoRegion::Eu868,
+    ProtoRegion::In865,
+    ProtoRegion::Kr920,
+    ProtoRegion::As9231,
+];
+
+/// Maximum distance in Hz between an uplink and a channel frequency to count
+/// as a match.
+const CHANNEL_MATCH_TOLERANCE: u64 = 1_000;
+
+/// Infers the region of a gateway from the frequencies of observed uplinks.
+#[derive(Debug)]
+pub struct RegionInference {
+    warmup: u32,
+    observed: u32,
+    tallies: Vec<(ProtoRegion, Vec<u64>, u32)>,
+}
+
+impl RegionInference {
+    /// Construct an inference that decides after `warmup` uplinks have been
+    /// seen on a known channel.
+    pub fn new(warmup: u32) -> Self {
+        let tallies = INFERABLE_REGIONS
+            .iter()
+            .map(|region| (*region, uplink_channels(*region), 0))
+            .collect();
+        Self {
+            warmup,
+            observed: 0,
+            tallies,
+        }
+    }
+
+    /// Records an uplink frequency in MHz. Returns the inferred region once
+    /// the warm-up is complete and a single region matched the most uplinks.
+    /// Ties keep the inference open until more uplinks break them.
+    pub fn observe(&mut self, frequency: f32) -> Option<Region> {
+        let frequency = (frequency as f64 * 1e6).round() as u64;
+        let mut matched = false;
+        for (_, channels, tally) in self.tallies.iter_mut() {
+            if channels
+                .iter()
+                .any(|channel| channel.abs_diff(frequency) <= CHANNEL_MATCH_TOLERANCE)
+            {
+                *tally += 1;
+                matched = true;
+            }
+        }
+        if matched {
+            self.observed += 1;
+        }
+        if self.observed < self.warmup {
+            return None;
+        }
+        let best = self.tallies.iter().map(|(_, _, tally)| *tally).max()?;
+        let mut leaders = self.tallies.iter().filter(|(_, _, tally)| *tally == best);
+        match (leaders.next(), leaders.next()) {
+            (Some((region, _, _)), None) if best > 0 => Some(Region(*region)),
+            _ => None,
+        }
+    }
+}
+
+/// The regulatory max EIRP in dBm of a region for a frequency in Hz, per the
+/// LoRaWAN regional parameters. Regions without an entry are not limited.
+pub fn regulatory_max_eirp(region: &Region, frequency: u64) -> Option<Decimal> {
+    let dbm = |value: i64| Some(Decimal::new(value, 0));
+    match region.0 {
+        // The g3 sub-band allows 500 mW ERP, the others 25 mW
+        ProtoRegion::Eu868 if (869_400_000..=869_650_000).contains(&frequency) => dbm(27),
+        ProtoRegion::Eu868 => dbm(16),
+        ProtoRegion::Eu433 => Some(Decimal::new(1215, 2)),
+        ProtoRegion::Cn470 => Some(Decimal::new(1915, 2)),
+        ProtoRegion::Us915 | ProtoRegion::Au915 | ProtoRegion::In865 => dbm(30),
+        ProtoRegion::As9231 | ProtoRegion::Ru864 => dbm(16),
+        ProtoRegion::Kr920 if frequency < 922_000_000 => dbm(10),
+        ProtoRegion::Kr920 => dbm(14),
+        _ => None,
+    }
+}
+
+/// A channel of the resolved channel plan of a region
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PlanChannel {
+    /// Channel frequency in Hz
+    pub frequency: u64,
+    /// Channel bandwidth in Hz
+    pub bandwidth: u64,
+    /// Max EIRP in dBm
+    pub max_eirp: Decimal,
+    /// The data rates allowed on the channel, for example "SF7BW125"
+    pub data_rates: Vec<String>,
+}
+
+/// The channel plan resolved from the active region params
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ChannelPlan {
+    pub region: Region,
+    pub channels: Vec<PlanChannel>,
+}
+
 #[derive(Debug, Clone)]
 pub struct RegionParams {
     pub gain: Decimal,
@@ -142,6 +275,50 @@ impl RegionParams {
             .and_then(|max_eirp| (max_eirp - self.gain).trunc().to_u32())
     }
 
+    /// The channel plan of these region params, ordered by frequency
+    pub fn channel_plan(&self) -> ChannelPlan {
+        let mut channels: Vec<PlanChannel> = self
+            .params
+            .iter()
+            .map(|param| {
+                let bandwidth_khz = param.bandwidth / 1000;
+                let data_rates = param
+                    .spreading
+                    .iter()
+                    .flat_map(|spreading| spreading.tagged_spreading.iter())
+                    // Region spreading values 1 through 6 are SF7 through SF12
+                    .filter(|tagged| (1..=6).contains(&tagged.region_spreading))
+                    .map(|tagged| format!("SF{}BW{bandwidth_khz}", tagged.region_spreading + 6))
+                    .collect();
+                PlanChannel {
+                    frequency: param.channel_frequency,
+                    bandwidth: param.bandwidth,
+                    max_eirp: Decimal::new(param.max_eirp as i64, 1),
+                    data_rates,
+                }
+            })
+            .collect();
+        channels.sort_by_key(|channel| channel.frequency);
+        ChannelPlan {
+            region: self.region,
+            channels,
+        }
+    }
+
+    /// Clamps a transmit power in dBm so that with the antenna gain it stays
+    /// within the max EIRP for the given frequency in Hz. The given max EIRP
+    /// overrides the regulatory limit of the region.
+    pub fn clamp_tx_power(&self, tx_power: u32, frequency: u64, max_eirp: Option<Decimal>) -> u32 {
+        use rust_decimal::prelude::ToPrimitive;
+        match max_eirp.or_else(|| regulatory_max_eirp(&self.region, frequency)) {
+            Some(max_eirp) => {
+                let limit = (max_eirp - self.gain).trunc().max(Decimal::ZERO);
+                tx_power.min(limit.to_u32().unwrap_or(0))
+            }
+            None => tx_power,
+        }
+    }
+
     pub fn to_string(v: &Option<Self>) -> String {
         match v {
             None => "none".to_string(),
@@ -149,3 +326,102 @@ impl RegionParams {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mk_params(frequencies: &[u64]) -> RegionParams {
+        RegionParams {
+            gain: Decimal::new(12, 1),
+            region: Region(ProtoRegion::Eu868),
+            params: frequencies
+                .iter()
+                .map(|frequency| BlockchainRegionParamV1 {
+                    channel_frequency: *frequency,
+                    ..Default::default()
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn channel_plan() {
+        use helium_proto::{BlockchainRegionSpreadingV1, TaggedSpreading};
+        let spreading = |factors: &[i32]| BlockchainRegionSpreadingV1 {
+            tagged_spreading: factors
+                .iter()
+                .map(|factor| TaggedSpreading {
+                    // SF7 is region spreading 1
+                    region_spreading: factor - 6,
+                    max_packet_size: 51,
+                })
+                .collect(),
+        };
+        let mut params = mk_params(&[868_300_000, 868_100_000]);
+        for param in params.params.iter_mut() {
+            param.bandwidth = 125_000;
+            param.max_eirp = 160;
+            param.spreading = Some(spreading(&[12, 7]));
+        }
+
+        let plan = params.channel_plan();
+        assert_eq!(Region(ProtoRegion::Eu868), plan.region);
+        assert_eq!(
+            vec![868_100_000, 868_300_000],
+            plan.channels
+                .iter()
+                .map(|channel| channel.frequency)
+                .collect::<Vec<_>>()
+        );
+        let channel = &plan.channels[0];
+        assert_eq!(125_000, channel.bandwidth);
+        assert_eq!(Decimal::new(16, 0), channel.max_eirp);
+        assert_eq!(vec!["SF12BW125", "SF7BW125"], channel.data_rates);
+    }
+
+    #[test]
+    fn clamp_tx_power() {
+        // 1.2 dBi antenna gain
+        let mut params = mk_params(&[]);
+        // EU868 allows 16 dBm, and 27 dBm in the g3 sub-band
+        assert_eq!(14, params.clamp_tx_power(27, 868_100_000, None));
+        assert_eq!(25, params.clamp_tx_power(27, 869_525_000, None));
+        assert_eq!(12, params.clamp_tx_power(12, 868_100_000, None));
+        // An operator override replaces the regional limit
+        let max_eirp = Some(Decimal::new(20, 0));
+        assert_eq!(18, params.clamp_tx_power(27, 868_100_000, max_eirp));
+
+        // KR920 allows 10 dBm below 922 MHz and 14 dBm above
+        params.region = Region(ProtoRegion::Kr920);
+        assert_eq!(8, params.clamp_tx_power(27, 921_900_000, None));
+        assert_eq!(12, params.clamp_tx_power(27, 922_100_000, None));
+
+        // A gain above the limit clamps to zero
+        params.gain = Decimal::new(150, 1);
+        assert_eq!(0, params.clamp_tx_power(27, 921_900_000, None));
+    }
+
+    #[test]
+    fn infer_region() {
+        let mut inference = RegionInference::new(4);
+        // EU868 default channels
+        assert!(inference.observe(868.1).is_none());
+        assert!(inference.observe(868.3).is_none());
+        // Unknown frequencies do not count towards the warm-up
+        assert!(inference.observe(100.0).is_none());
+        assert!(inference.observe(868.5).is_none());
+        let region = inference.observe(867.1).expect("inferred region");
+        assert_eq!(i32::from(ProtoRegion::Eu868), i32::from(region));
+    }
+
+    #[test]
+    fn infer_region_tie() {
+        let mut inference = RegionInference::new(1);
+        // 923.2 MHz is both an AU915 and an AS923 channel
+        assert!(inference.observe(923.2).is_none());
+        // 915.2 MHz is only an AU915 channel
+        let region = inference.observe(915.2).expect("inferred region");
+        assert_eq!(i32::from(ProtoRegion::Au915), i32::from(region));
+    }
+}
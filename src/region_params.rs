@@ -0,0 +1,246 @@
+//! Sources for region parameters.
+//!
+//! Region params are normally fetched from the connected gateway service. For
+//! offline or lab setups they can instead be loaded from a local file holding
+//! a protobuf encoded `GatewayRegionParamsRespV1`.
+
+use crate::{service::gateway::GatewayService, Error, Keypair, Region, RegionParams, Result};
+use futures::{
+    future::{BoxFuture, Shared},
+    FutureExt,
+};
+use helium_proto::{GatewayRegionParamsRespV1, Message};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+use tokio::sync::Semaphore;
+
+#[async_trait::async_trait]
+pub trait RegionParamsSource: Send {
+    /// Fetch the region params for the given region
+    async fn region_params(&mut self, region: &Region) -> Result<RegionParams>;
+}
+
+/// Fetches region params from a gateway service
+#[derive(Debug, Clone)]
+pub struct GatewayRegionParams {
+    gateway: GatewayService,
+    keypair: Arc<Keypair>,
+}
+
+impl GatewayRegionParams {
+    pub fn new(gateway: GatewayService, keypair: Arc<Keypair>) -> Self {
+        Self { gateway, keypair }
+    }
+}
+
+#[async_trait::async_trait]
+impl RegionParamsSource for GatewayRegionParams {
+    async fn region_params(&mut self, region: &Region) -> Result<RegionParams> {
+        self.gateway
+            .region_params_for(region, self.keypair.clone())
+            .await
+    }
+}
+
+/// Loads static region params from a local file. The region of the loaded
+/// params is used regardless of the requested region.
+#[derive(Debug, Clone)]
+pub struct FileRegionParams {
+    path: PathBuf,
+}
+
+impl FileRegionParams {
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+        }
+    }
+
+    pub fn load(&self) -> Result<RegionParams> {
+        let data = fs::read(&self.path)?;
+        let resp = GatewayRegionParamsRespV1::decode(data.as_ref()).map_err(Error::from)?;
+        RegionParams::try_from(resp)
+    }
+}
+
+#[async_trait::async_trait]
+impl RegionParamsSource for FileRegionParams {
+    async fn region_params(&mut self, _region: &Region) -> Result<RegionParams> {
+        self.load()
+    }
+}
+
+type SharedFetch = Shared<BoxFuture<'static, std::result::Result<RegionParams, String>>>;
+
+/// Shares a region params source between concurrent users. At most `limit`
+/// fetches run against the source at the same time, and concurrent fetches
+/// of the same region wait for the one fetch in flight instead of starting
+/// their own. Clones share the limit and the fetches in flight.
+#[derive(Clone)]
+pub struct SharedRegionParams<S> {
+    source: S,
+    limit: Arc<Semaphore>,
+    in_flight: Arc<Mutex<HashMap<i32, SharedFetch>>>,
+}
+
+impl<S> SharedRegionParams<S>
+where
+    S: RegionParamsSource + Clone + Sync + 'static,
+{
+    /// Construct a shared source running at most `limit` fetches at a time.
+    /// A limit of zero is treated as one.
+    pub fn new(source: S, limit: usize) -> Self {
+        Self {
+            source,
+            limit: Arc::new(Semaphore::new(limit.max(1))),
+            in_flight: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Fetch the region params for the given region, joining a fetch of the
+    /// same region if one is in flight
+    pub async fn fetch(&self, region: &Region) -> Result<RegionParams> {
+        let key = i32::from(*region);
+        let fetch = self
+            .in_flight
+            .lock()
+            .expect("region params fetches lock")
+            .entry(key)
+            .or_insert_with(|| {
+                let mut source = self.source.clone();
+                let limit = self.limit.clone();
+                let in_flight = self.in_flight.clone();
+                let region = *region;
+                async move {
+                    let result = match limit.acquire_owned().await {
+                        Ok(_permit) => source
+                            .region_params(&region)
+                            .await
+                            .map_err(|err| err.to_string()),
+                        Err(err) => Err(err.to_string()),
+                    };
+                    // Later fetches start over to pick up changed params
+                    in_flight
+                        .lock()
+                        .expect("region params fetches lock")
+                        .remove(&key);
+                    result
+                }
+                .boxed()
+                .shared()
+            })
+            .clone();
+        fetch.await.map_err(Error::custom)
+    }
+}
+
+#[async_trait::async_trait]
+impl<S> RegionParamsSource for SharedRegionParams<S>
+where
+    S: RegionParamsSource + Clone + Sync + 'static,
+{
+    async fn region_params(&mut self, region: &Region) -> Result<RegionParams> {
+        self.fetch(region).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use helium_proto::{BlockchainRegionParamV1, BlockchainRegionParamsV1, Region as ProtoRegion};
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    /// Counts upstream fetches and the most fetches running at once
+    #[derive(Debug, Clone, Default)]
+    struct CountingSource {
+        calls: Arc<AtomicUsize>,
+        active: Arc<AtomicUsize>,
+        max_active: Arc<AtomicUsize>,
+    }
+
+    #[async_trait::async_trait]
+    impl RegionParamsSource for CountingSource {
+        async fn region_params(&mut self, region: &Region) -> Result<RegionParams> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            let active = self.active.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_active.fetch_max(active, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            self.active.fetch_sub(1, Ordering::SeqCst);
+            Ok(RegionParams {
+                gain: rust_decimal::Decimal::new(12, 1),
+                region: *region,
+                params: vec![],
+            })
+        }
+    }
+
+    fn mk_region(region: ProtoRegion) -> Region {
+        Region::from_i32(region.into()).expect("region")
+    }
+
+    #[tokio::test]
+    async fn shared_fetches_coalesce() {
+        let counting = CountingSource::default();
+        let shared = SharedRegionParams::new(counting.clone(), 1);
+        let us915 = mk_region(ProtoRegion::Us915);
+        let eu868 = mk_region(ProtoRegion::Eu868);
+
+        let fetches = (0..5).map(|_| shared.fetch(&us915));
+        let results = futures::future::join_all(fetches).await;
+        assert!(results.iter().all(|result| result.is_ok()));
+        assert_eq!(1, counting.calls.load(Ordering::SeqCst));
+
+        // A finished fetch is not reused
+        let mut source = shared.clone();
+        source.region_params(&us915).await.expect("region params");
+        assert_eq!(2, counting.calls.load(Ordering::SeqCst));
+
+        // Different regions fetch separately, within the limit
+        let (us, eu) = tokio::join!(shared.fetch(&us915), shared.fetch(&eu868));
+        assert_eq!(
+            i32::from(ProtoRegion::Us915),
+            i32::from(us.expect("us915").region)
+        );
+        assert_eq!(
+            i32::from(ProtoRegion::Eu868),
+            i32::from(eu.expect("eu868").region)
+        );
+        assert_eq!(4, counting.calls.load(Ordering::SeqCst));
+        assert_eq!(1, counting.max_active.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn file_source() {
+        let resp = GatewayRegionParamsRespV1 {
+            region: ProtoRegion::Eu868.into(),
+            gain: 12,
+            params: Some(BlockchainRegionParamsV1 {
+                region_params: vec![BlockchainRegionParamV1 {
+                    channel_frequency: 868_100_000,
+                    max_eirp: 160,
+                    ..Default::default()
+                }],
+            }),
+            ..Default::default()
+        };
+        let path = std::env::temp_dir().join("gateway-rs-region-params-test.bin");
+        fs::write(&path, resp.encode_to_vec()).expect("write region params");
+
+        let mut source = FileRegionParams::new(&path);
+        let region = Region::from_i32(ProtoRegion::Us915.into()).expect("region");
+        let params = source.region_params(&region).await.expect("region params");
+        let _ = fs::remove_file(&path);
+
+        assert_eq!(i32::from(ProtoRegion::Eu868), i32::from(params.region));
+        assert_eq!(1, params.params.len());
+        // 16 dBm max eirp with 1.2 dBi gain
+        assert_eq!(Some(14), params.tx_power());
+    }
+}
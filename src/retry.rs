@@ -0,0 +1,100 @@
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// An exponential backoff policy for retried operations.
+///
+/// The delay before retry attempt `n`, counting from one, is `base_delay`
+/// doubled for every earlier attempt and capped at `max_delay`. Jitter
+/// shortens each delay by a random fraction of up to `jitter`, so clients
+/// that fail together do not retry together.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub struct RetryPolicy {
+    /// Number of attempts the policy backs off for
+    pub max_attempts: u32,
+    /// Delay in milliseconds before the first retry
+    pub base_delay: u64,
+    /// Maximum delay in milliseconds between retries
+    pub max_delay: u64,
+    /// Fraction of each delay, from 0 to 1, to randomly shorten it by
+    pub jitter: f64,
+}
+
+impl RetryPolicy {
+    pub fn max_delay(&self) -> Duration {
+        Duration::from_millis(self.max_delay)
+    }
+
+    /// The jittered delay before the given retry attempt, or None once the
+    /// attempts are exhausted
+    pub fn delay(&self, attempt: u32) -> Option<Duration> {
+        self.delay_with(&mut rand::thread_rng(), attempt)
+    }
+
+    pub fn delay_with<R: Rng>(&self, rng: &mut R, attempt: u32) -> Option<Duration> {
+        if attempt == 0 || attempt > self.max_attempts {
+            return None;
+        }
+        let delay = self
+            .base_delay
+            .saturating_mul(1u64.checked_shl(attempt - 1).unwrap_or(u64::MAX))
+            .min(self.max_delay);
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        let delay = Duration::from_millis(delay);
+        if jitter > 0.0 {
+            Some(delay.mul_f64(1.0 - rng.gen_range(0.0..jitter)))
+        } else {
+            Some(delay)
+        }
+    }
+
+    /// The delay before the given retry attempt, staying at the maximum delay
+    /// once the attempts are exhausted. For operations that never give up.
+    pub fn delay_or_max(&self, attempt: u32) -> Duration {
+        self.delay(attempt).unwrap_or_else(|| self.max_delay())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::OsRng;
+
+    fn mk_policy(jitter: f64) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 8,
+            base_delay: 1_000,
+            max_delay: 30_000,
+            jitter,
+        }
+    }
+
+    #[test]
+    fn delay_sequence() {
+        let policy = mk_policy(0.0);
+        let delays: Vec<u64> = (1..=8)
+            .map(|attempt| policy.delay(attempt).expect("delay").as_millis() as u64)
+            .collect();
+        assert_eq!(
+            vec![1_000, 2_000, 4_000, 8_000, 16_000, 30_000, 30_000, 30_000],
+            delays
+        );
+        assert_eq!(None, policy.delay(0));
+        assert_eq!(None, policy.delay(9));
+        assert_eq!(Duration::from_secs(30), policy.delay_or_max(9));
+        assert_eq!(None, policy.delay(u32::MAX));
+    }
+
+    #[test]
+    fn delay_jitter() {
+        let policy = mk_policy(0.5);
+        let mut rng = OsRng;
+        for attempt in 1..=8 {
+            let max = mk_policy(0.0).delay(attempt).expect("delay");
+            for _ in 0..100 {
+                let delay = policy.delay_with(&mut rng, attempt).expect("delay");
+                assert!(delay <= max && delay >= max / 2);
+            }
+        }
+    }
+}
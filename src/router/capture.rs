@@ -0,0 +1,141 @@
+use crate::{Packet, Result};
+use bytes::BufMut;
+use helium_proto::Message;
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// The direction of a captured packet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptureDirection {
+    Uplink = 0,
+    Downlink = 1,
+}
+
+/// Appends packets to a capture file for offline analysis. Each record is the
+/// direction byte, the capture time in milliseconds since the unix epoch and
+/// the length delimited packet. Once the file would grow beyond the maximum
+/// size it is rotated to a `.1` file, replacing any previous one.
+#[derive(Debug)]
+pub struct PacketCapture {
+    path: PathBuf,
+    max_size: u64,
+    size: u64,
+}
+
+impl PacketCapture {
+    pub fn new(path: &Path, max_size: u64) -> Self {
+        let size = fs::metadata(path).map_or(0, |metadata| metadata.len());
+        Self {
+            path: path.to_path_buf(),
+            max_size,
+            size,
+        }
+    }
+
+    /// The path of the rotated capture file
+    pub fn rotated_path(&self) -> PathBuf {
+        let mut rotated = self.path.clone().into_os_string();
+        rotated.push(".1");
+        rotated.into()
+    }
+
+    /// Appends a packet record with the current time
+    pub fn record(&mut self, direction: CaptureDirection, packet: &Packet) -> Result {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?;
+        self.record_at(direction, packet, now.as_millis() as u64)
+    }
+
+    /// Appends a packet record with the given time in milliseconds since the
+    /// unix epoch
+    pub fn record_at(&mut self, direction: CaptureDirection, packet: &Packet, time: u64) -> Result {
+        let mut buf = vec![];
+        buf.put_u8(direction as u8);
+        buf.put_u64(time);
+        packet.encode_length_delimited(&mut buf)?;
+
+        if self.size > 0 && self.size + buf.len() as u64 > self.max_size {
+            fs::rename(&self.path, self.rotated_path())?;
+            self.size = 0;
+        }
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        file.write_all(&buf)?;
+        self.size += buf.len() as u64;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Buf;
+
+    fn mk_packet(payload: &[u8]) -> Packet {
+        helium_proto::Packet {
+            payload: payload.to_vec(),
+            timestamp: 42,
+            ..Default::default()
+        }
+        .into()
+    }
+
+    fn read_records(path: &Path) -> Vec<(u8, u64, helium_proto::Packet)> {
+        let data = fs::read(path).expect("capture file");
+        let mut buf = &data[..];
+        let mut records = vec![];
+        while buf.has_remaining() {
+            let direction = buf.get_u8();
+            let time = buf.get_u64();
+            let packet =
+                helium_proto::Packet::decode_length_delimited(&mut buf).expect("captured packet");
+            records.push((direction, time, packet));
+        }
+        records
+    }
+
+    #[test]
+    fn capture_rotates() {
+        let dir = std::env::temp_dir().join(format!("capture_rotates_{}", std::process::id()));
+        let path = dir.join("router.capture");
+        let _ = fs::remove_dir_all(&dir);
+
+        // Records of a 3 byte payload packet take 17 bytes
+        let mut capture = PacketCapture::new(&path, 50);
+        capture
+            .record_at(CaptureDirection::Uplink, &mk_packet(&[1, 2, 3]), 1_000)
+            .expect("uplink record");
+        capture
+            .record_at(CaptureDirection::Downlink, &mk_packet(&[4, 5, 6]), 2_000)
+            .expect("downlink record");
+        let records = read_records(&path);
+        assert_eq!(2, records.len());
+        assert_eq!((0, 1_000), (records[0].0, records[0].1));
+        assert_eq!(vec![1, 2, 3], records[0].2.payload);
+        assert_eq!((1, 2_000), (records[1].0, records[1].1));
+        assert_eq!(42, records[1].2.timestamp);
+
+        // The third record exceeds the size and rotates the file
+        capture
+            .record_at(CaptureDirection::Uplink, &mk_packet(&[7, 8, 9]), 3_000)
+            .expect("rotated record");
+        assert_eq!(2, read_records(&capture.rotated_path()).len());
+        let records = read_records(&path);
+        assert_eq!(1, records.len());
+        assert_eq!(vec![7, 8, 9], records[0].2.payload);
+
+        // A reopened capture continues from the existing size
+        let reopened = PacketCapture::new(&path, 50);
+        assert_eq!(capture.size, reopened.size);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
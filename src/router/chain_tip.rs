@@ -0,0 +1,59 @@
+use crate::{Error, Result};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+
+/// The age of the chain tip as last reported by the gateway service
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChainTip {
+    /// Age of the tip block in seconds when it was checked
+    pub block_age: u64,
+    pub checked: Instant,
+}
+
+pub type ChainTipSender = watch::Sender<Option<ChainTip>>;
+pub type ChainTipReceiver = watch::Receiver<Option<ChainTip>>;
+
+pub fn chain_tip_channel() -> (ChainTipSender, ChainTipReceiver) {
+    watch::channel(None)
+}
+
+impl ChainTip {
+    pub fn new(block_age: u64, checked: Instant) -> Self {
+        Self { block_age, checked }
+    }
+
+    /// The block age at the given time, aged by the time since the check
+    pub fn block_age_at(&self, now: Instant) -> u64 {
+        self.block_age
+            .saturating_add(now.saturating_duration_since(self.checked).as_secs())
+    }
+
+    /// Checks that the tip is no older than `max_age` at the given time. An
+    /// unknown tip passes since nothing is known to be stale yet.
+    pub fn check(tip: Option<&Self>, max_age: Duration, now: Instant) -> Result {
+        match tip.map(|tip| tip.block_age_at(now)) {
+            Some(block_age) if block_age > max_age.as_secs() => {
+                Err(Error::gateway_service_check(block_age, max_age.as_secs()))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tip_ages() {
+        let max_age = Duration::from_secs(60);
+        let now = Instant::now();
+        assert!(ChainTip::check(None, max_age, now).is_ok());
+
+        let tip = ChainTip::new(30, now);
+        assert!(ChainTip::check(Some(&tip), max_age, now).is_ok());
+        // The tip goes stale as time passes without a fresh check
+        assert_eq!(61, tip.block_age_at(now + Duration::from_secs(31)));
+        assert!(ChainTip::check(Some(&tip), max_age, now + Duration::from_secs(31)).is_err());
+    }
+}
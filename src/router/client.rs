@@ -1,26 +1,49 @@
 use crate::{
     error::Error,
-    gateway,
-    router::{QuePacket, RouterStore},
-    service::router::RouterService,
-    state_channel::StateChannelMessage,
-    Base64, CacheSettings, KeyedUri, Keypair, Packet, Region, Result,
+    gateway::DownlinkSink,
+    metrics::Histogram,
+    router::{
+        chain_tip::ChainTipReceiver, store, CaptureDirection, ChainTip, FileWriter, LogLimit,
+        PacketCapture, QuePacket, ReplayWindow, RouterStore,
+    },
+    service::router::{RouterService, RouterTransport},
+    state_channel::{
+        validate_summary, SignatureCache, SigningPool, StateChannelMessage, StateChannelMetrics,
+    },
+    Base64, CacheSettings, DownlinkSettings, KeyedUri, Keypair, Packet, Region, Result,
+    RetryPolicy, Settings, UplinkSource,
 };
 use futures::TryFutureExt;
+use helium_proto::BlockchainStateChannelPacketV1;
+use rand::Rng;
 use slog::{debug, info, o, warn, Logger};
-use std::{sync::Arc, time::Instant};
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
 use tokio::{
-    sync::mpsc,
+    sync::{mpsc, watch},
     time::{self, Duration, MissedTickBehavior},
 };
 
 pub const STORE_GC_INTERVAL: Duration = Duration::from_secs(60);
+const RESIDENCE_CHECK_MIN: Duration = Duration::from_millis(100);
 pub const STATE_CHANNEL_CONNECT_INTERVAL: Duration = Duration::from_secs(60);
 
+/// How long routed packets are remembered to drop exact replays
+const REPLAY_WINDOW: Duration = Duration::from_secs(120);
+
 #[derive(Debug)]
 pub enum Message {
-    Uplink { packet: Packet, received: Instant },
+    Uplink {
+        packet: Packet,
+        region: Option<Region>,
+        received: Instant,
+    },
     RegionChanged(Region),
+    RegionRefresh,
+    FlushDrop,
     Stop,
 }
 
@@ -33,53 +56,385 @@ pub fn message_channel(size: usize) -> (MessageSender, MessageReceiver) {
     (MessageSender(tx), rx)
 }
 
+/// The routing state of a router client, reported to router selection
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RouterStatus {
+    /// Set while routes to the router fail or time out, until one succeeds
+    pub failing: bool,
+    /// Set while the queue is at capacity and drops its oldest packets
+    pub backpressure: bool,
+}
+
+impl RouterStatus {
+    pub fn is_healthy(&self) -> bool {
+        !self.failing && !self.backpressure
+    }
+}
+
+pub type RouterStatusSender = watch::Sender<RouterStatus>;
+pub type RouterStatusReceiver = watch::Receiver<RouterStatus>;
+
+pub fn router_status_channel() -> (RouterStatusSender, RouterStatusReceiver) {
+    watch::channel(RouterStatus::default())
+}
+
 impl MessageSender {
     pub async fn region_changed(&self, region: Region) {
         let _ = self.0.send(Message::RegionChanged(region)).await;
     }
 
+    /// Holds routing of queued packets while region params are refreshed,
+    /// until the next region change or the region refresh pause passes
+    pub async fn region_refresh(&self) {
+        let _ = self.0.send(Message::RegionRefresh).await;
+    }
+
     pub async fn uplink(&self, packet: Packet, received: Instant) -> Result {
+        self.uplink_in(packet, None, received).await
+    }
+
+    /// Sends an uplink to route with the given region instead of the router
+    /// region, if one is given
+    pub async fn uplink_in(
+        &self,
+        packet: Packet,
+        region: Option<Region>,
+        received: Instant,
+    ) -> Result {
         self.0
-            .send(Message::Uplink { packet, received })
+            .send(Message::Uplink {
+                packet,
+                region,
+                received,
+            })
             .map_err(|_| Error::channel())
             .await
     }
 
+    /// Drops all queued packets without sending them
+    pub async fn flush_drop(&self) {
+        let _ = self.0.send(Message::FlushDrop).await;
+    }
+
     pub async fn stop(&self) {
         let _ = self.0.send(Message::Stop).await;
     }
 }
 
+/// Settings shared by all router clients
+#[derive(Debug, Clone)]
+pub struct ClientSettings {
+    pub cache: CacheSettings,
+    pub downlink: DownlinkSettings,
+    pub transport: RouterTransport,
+    /// The chain tip as reported by the dispatcher
+    pub chain_tip: ChainTipReceiver,
+    /// Maximum chain tip age to route packets at. Packets are queued while
+    /// the tip is older.
+    pub max_block_age: Option<Duration>,
+    /// Clock skew tolerated in the chain tip age and queued packet expiry
+    pub clock_skew: Duration,
+    /// Optional folder to capture routed uplinks and received downlinks in
+    pub capture: Option<PathBuf>,
+    /// Size in bytes at which capture files are rotated
+    pub capture_max_size: u64,
+    /// Optional interval to space out sends of queued packets by
+    pub send_pacing: Option<Duration>,
+    /// Optional time after which a queued packet is attempted even while
+    /// routing is paused or paced
+    pub max_residence: Option<Duration>,
+    /// Number of queued packets to sign concurrently
+    pub signing_tasks: usize,
+    /// Backoff between attempts to route to a failing router
+    pub route_retry: RetryPolicy,
+    /// Backoff between attempts to sign a packet that failed to sign
+    pub sign_retry: RetryPolicy,
+    /// Maximum time routing is held for a region params refresh
+    pub region_refresh_pause: Duration,
+    /// Optional interval to summarize repeated warnings over
+    pub log_repeat_interval: Option<Duration>,
+}
+
+impl ClientSettings {
+    pub fn new(settings: &Settings, chain_tip: ChainTipReceiver) -> Self {
+        let router_settings = &settings.router;
+        Self {
+            cache: settings.cache.clone(),
+            downlink: settings.downlink.clone(),
+            transport: RouterTransport::new(router_settings)
+                .with_platform(&settings.update.platform),
+            chain_tip,
+            max_block_age: router_settings.max_block_age(),
+            clock_skew: settings.clock_skew(),
+            capture: router_settings.capture.clone(),
+            capture_max_size: router_settings.capture_max_size,
+            send_pacing: router_settings.send_pacing(),
+            max_residence: router_settings.max_residence(),
+            signing_tasks: router_settings.signing_tasks.unwrap_or(1),
+            route_retry: router_settings.route_retry,
+            sign_retry: router_settings.sign_retry,
+            region_refresh_pause: router_settings.region_refresh_pause(),
+            log_repeat_interval: router_settings.log_repeat_interval(),
+        }
+    }
+}
+
 pub struct RouterClient {
     router: RouterService,
     oui: u32,
     region: Region,
     keypair: Arc<Keypair>,
-    downlinks: gateway::MessageSender,
+    downlinks: Arc<dyn DownlinkSink>,
     store: RouterStore,
+    store_path: Option<PathBuf>,
+    store_flush: StoreFlush,
+    downlink_settings: DownlinkSettings,
+    queue_time: Histogram,
+    route_attempts: RouteAttempts,
+    route_retry: RetryPolicy,
+    sign_retry: RetryPolicy,
+    signatures: SignatureCache,
+    sc_metrics: StateChannelMetrics,
+    replays: ReplayWindow,
+    chain_tip: ChainTipReceiver,
+    max_block_age: Option<Duration>,
+    clock_skew: Duration,
+    degraded: bool,
+    // Set once the gateway stops taking downlinks
+    downlinks_closed: bool,
+    // Shared with the writes queued on the file writer
+    capture: Option<Arc<Mutex<PacketCapture>>>,
+    writer: FileWriter,
+    pacing: Option<SendPacing>,
+    // Earliest time the next packet is sent when pacing sends
+    next_send: Option<Instant>,
+    max_residence: Option<Duration>,
+    signing_tasks: usize,
+    // Set while routing is held for a region params refresh
+    region_refresh: Option<Instant>,
+    region_refresh_pause: Duration,
+    log_limit: LogLimit,
+    status: RouterStatusSender,
+}
+
+/// Spaces out sends from the queue by a jittered delay of 50% to 150% of the
+/// pacing interval. Packets that would reach the maximum hold time by waiting
+/// are sent without delay. Waits are timed by the run loop so messages are
+/// still handled while sends are paced.
+#[derive(Debug, Clone, Copy)]
+struct SendPacing {
+    interval: Duration,
+    max_hold: Duration,
+}
+
+impl SendPacing {
+    fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            max_hold: STORE_GC_INTERVAL,
+        }
+    }
+
+    /// Sends packets without delay once they would wait longer than the
+    /// given maximum residence, rather than only when they would age out
+    fn with_max_residence(self, max_residence: Option<Duration>) -> Self {
+        Self {
+            max_hold: max_residence.map_or(self.max_hold, |max| max.min(self.max_hold)),
+            ..self
+        }
+    }
+
+    /// The delay from one send to the next
+    fn delay<R: Rng>(&self, rng: &mut R) -> Duration {
+        self.interval.mul_f64(rng.gen_range(0.5..1.5))
+    }
+
+    /// The time a packet held for the given time still waits for its send,
+    /// with the given time left until the next send
+    fn wait(&self, hold_time: Duration, remaining: Duration) -> Duration {
+        if hold_time + remaining >= self.max_hold {
+            Duration::ZERO
+        } else {
+            remaining
+        }
+    }
+}
+
+/// Decides when changes to the persisted queue are written out. A change is
+/// written once the given number of changes built up, or the flush interval
+/// passed since the last write. Writes on every change without either.
+#[derive(Debug)]
+struct StoreFlush {
+    interval: Option<Duration>,
+    max_changes: Option<u32>,
+    changes: u32,
+    flushed: Instant,
+}
+
+impl StoreFlush {
+    fn new(interval: Option<Duration>, max_changes: Option<u32>, now: Instant) -> Self {
+        let max_changes = match (interval, max_changes) {
+            (None, None) => Some(1),
+            (_, max_changes) => max_changes.map(|max| max.max(1)),
+        };
+        Self {
+            interval,
+            max_changes,
+            changes: 0,
+            flushed: now,
+        }
+    }
+
+    /// Records a change, returning whether to write the queue now
+    fn changed(&mut self, now: Instant) -> bool {
+        self.changes += 1;
+        self.max_changes.map_or(false, |max| self.changes >= max) || self.is_due(now)
+    }
+
+    /// Whether changes are pending and the flush interval has passed
+    fn is_due(&self, now: Instant) -> bool {
+        self.changes > 0
+            && self.interval.map_or(false, |interval| {
+                now.saturating_duration_since(self.flushed) >= interval
+            })
+    }
+
+    fn flushed(&mut self, now: Instant) {
+        self.changes = 0;
+        self.flushed = now;
+    }
+}
+
+/// Tracks consecutive route failures and when routing may be attempted again.
+#[derive(Debug, Default)]
+struct RouteAttempts {
+    failures: u32,
+    retry_at: Option<Instant>,
+}
+
+impl RouteAttempts {
+    fn is_ready(&self, now: Instant) -> bool {
+        self.retry_at.map_or(true, |retry_at| now >= retry_at)
+    }
+
+    /// The time until routing may be attempted again, if it is held
+    fn retry_wait(&self, now: Instant) -> Option<Duration> {
+        self.retry_at
+            .map(|retry_at| retry_at.saturating_duration_since(now))
+    }
+
+    /// Ends the hold on routing once it is time to retry, keeping the
+    /// failure count until a route succeeds
+    fn retry_due(&mut self) {
+        self.retry_at = None;
+    }
+
+    /// Records a failed route and returns how long to wait before the next
+    /// attempt
+    fn failed(&mut self, retry: &RetryPolicy, now: Instant) -> Duration {
+        self.failures += 1;
+        let wait = retry.delay_or_max(self.failures);
+        self.retry_at = Some(now + wait);
+        wait
+    }
+
+    /// Holds routing until at least the given time without counting a
+    /// failure
+    fn defer(&mut self, until: Instant) {
+        self.retry_at = Some(self.retry_at.map_or(until, |retry_at| retry_at.max(until)));
+    }
+
+    /// Clears all failure state and returns the number of failures that
+    /// preceded the successful route
+    fn succeeded(&mut self) -> u32 {
+        self.retry_at = None;
+        std::mem::take(&mut self.failures)
+    }
 }
 
 impl RouterClient {
-    pub async fn new(
+    pub async fn new<D: DownlinkSink + 'static>(
         oui: u32,
         region: Region,
         uri: KeyedUri,
-        downlinks: gateway::MessageSender,
+        downlinks: D,
         keypair: Arc<Keypair>,
-        settings: CacheSettings,
+        settings: &ClientSettings,
     ) -> Result<Self> {
-        let router = RouterService::new(uri)?;
-        let store = RouterStore::new(&settings);
+        let store_path = settings
+            .cache
+            .store
+            .as_ref()
+            .map(|dir| dir.join(format!("{oui}_{}.bin", uri.pubkey)));
+        let capture = settings.capture.as_ref().map(|dir| {
+            let path = dir.join(format!("{oui}_{}.capture", uri.pubkey));
+            Arc::new(Mutex::new(PacketCapture::new(
+                &path,
+                settings.capture_max_size,
+            )))
+        });
+        let router = RouterService::new(uri, &settings.transport)?;
+        let store = RouterStore::new(&settings.cache);
+        let queue_time = Histogram::new(&settings.cache.queue_time_buckets);
+        let signatures = SignatureCache::new(settings.cache.signature_cache);
+        let downlink_settings = settings.downlink.clone();
         Ok(Self {
             router,
             oui,
             region,
             keypair,
-            downlinks,
+            downlinks: Arc::new(downlinks),
             store,
+            store_path,
+            store_flush: StoreFlush::new(
+                settings.cache.flush_interval(),
+                settings.cache.flush_changes,
+                Instant::now(),
+            ),
+            downlink_settings,
+            queue_time,
+            route_attempts: RouteAttempts::default(),
+            route_retry: settings.route_retry,
+            sign_retry: settings.sign_retry,
+            signatures,
+            sc_metrics: StateChannelMetrics::default(),
+            replays: ReplayWindow::new(REPLAY_WINDOW),
+            chain_tip: settings.chain_tip.clone(),
+            max_block_age: settings.max_block_age,
+            clock_skew: settings.clock_skew,
+            degraded: false,
+            downlinks_closed: false,
+            capture,
+            writer: FileWriter::spawn(),
+            pacing: settings.send_pacing.map(|interval| {
+                SendPacing::new(interval).with_max_residence(settings.max_residence)
+            }),
+            next_send: None,
+            max_residence: settings.max_residence,
+            signing_tasks: settings.signing_tasks,
+            region_refresh: None,
+            region_refresh_pause: settings.region_refresh_pause,
+            log_limit: LogLimit::new(settings.log_repeat_interval),
+            status: router_status_channel().0,
         })
     }
 
+    /// The routing state of this client, updated as it runs
+    pub fn status(&self) -> RouterStatusReceiver {
+        self.status.subscribe()
+    }
+
+    /// Publishes the routing state if it changed
+    fn update_status(&self) {
+        let status = RouterStatus {
+            failing: self.route_attempts.failures > 0,
+            backpressure: self.store.backpressure(),
+        };
+        if *self.status.borrow() != status {
+            self.status.send_replace(status);
+        }
+    }
+
     pub async fn run(
         &mut self,
         mut messages: MessageReceiver,
@@ -93,66 +448,428 @@ impl RouterClient {
             "oui" => self.oui,
         ));
         info!(logger, "starting");
+        self.load_store(&logger);
 
         let mut store_gc_timer = time::interval(STORE_GC_INTERVAL);
         store_gc_timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        // Checks often enough to attempt packets close to their maximum
+        // residence, without spinning on a tiny one
+        let residence_check = self
+            .max_residence
+            .map_or(STORE_GC_INTERVAL, |max| (max / 4).max(RESIDENCE_CHECK_MIN));
+        let mut residence_timer = time::interval(residence_check);
+        residence_timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let flush_interval = self.store_flush.interval;
+        let mut flush_timer = time::interval(flush_interval.unwrap_or(STORE_GC_INTERVAL));
+        flush_timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let log_repeat_interval = self.log_limit.interval();
+        let mut log_repeat_timer = time::interval(log_repeat_interval.unwrap_or(STORE_GC_INTERVAL));
+        log_repeat_timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut chain_tip_open = true;
 
         loop {
+            // Without a gateway to transmit them, routing would only drop the
+            // downlinks of every routed packet. Failing lets the dispatcher
+            // shut down the rest.
+            if self.downlinks_closed {
+                warn!(logger, "downlinks channel closed, shutting down");
+                self.close_store(&logger).await;
+                return Err(Error::channel());
+            }
+            self.update_status();
+            let refresh_wait = self.region_refresh_wait(Instant::now());
+            let pacing_wait = self.pacing_wait(Instant::now());
+            // Queued packets are retried once the route backoff ends, without
+            // waiting for the next uplink
+            let retry_wait = self
+                .route_attempts
+                .retry_wait(Instant::now())
+                .filter(|_| self.store.waiting_packets_len() > 0);
             tokio::select! {
                 _ = shutdown.clone() => {
                     info!(logger, "shutting down");
+                    self.close_store(&logger).await;
                     return Ok(())
                 },
                 message = messages.recv() => match message {
-                    Some(Message::Uplink{packet, received}) => {
-                        self.handle_uplink(&logger, packet, received)
-                            .unwrap_or_else(|err| warn!(logger, "ignoring failed uplink {:?}", err))
-                            .await;
+                    Some(Message::Uplink{packet, region, received}) => {
+                        if let Err(err) = self.handle_uplink(&logger, packet, region, received).await {
+                            self.warn_limited(&logger, format!("ignoring failed uplink {err:?}"));
+                        }
+                        self.save_store(&logger);
                     },
                     Some(Message::RegionChanged(region)) => {
-                        self.region = region;
-                        info!(logger, "updated region";
-                            "region" => region);
+                        if self.handle_region_changed(&logger, region) {
+                            self.resume_waiting_packets(&logger).await;
+                            self.save_store(&logger);
+                        }
+                    },
+                    Some(Message::RegionRefresh) => {
+                        info!(logger, "region params refreshing, holding routing";
+                            "pause" => self.region_refresh_pause.as_millis());
+                        self.region_refresh = Some(Instant::now());
+                    },
+                    Some(Message::FlushDrop) => {
+                        let dropped = self.store.flush_waiting_packets();
+                        warn!(logger, "flushed queued packets";
+                            "dropped" => dropped,
+                            "total_dropped" => self.store.dropped_packets());
+                        self.save_store(&logger);
                     },
                     Some(Message::Stop) => {
                         info!(logger, "stop requested, shutting down");
+                        self.close_store(&logger).await;
                         return Ok(())
                     },
                     None => warn!(logger, "ignoring closed uplinks channel"),
                 },
+                changed = self.chain_tip.changed(), if chain_tip_open => match changed {
+                    // Resume sending queued packets if the tip freshened
+                    Ok(()) if self.degraded => {
+                        self.resume_waiting_packets(&logger).await;
+                        self.save_store(&logger);
+                    }
+                    Ok(()) => (),
+                    Err(_) => chain_tip_open = false,
+                },
+                _ = time::sleep(refresh_wait.unwrap_or_default()), if refresh_wait.is_some() => {
+                    self.resume_waiting_packets(&logger).await;
+                    self.save_store(&logger);
+                }
+                _ = time::sleep(pacing_wait.unwrap_or_default()), if pacing_wait.is_some() => {
+                    self.resume_waiting_packets(&logger).await;
+                    self.save_store(&logger);
+                }
+                _ = time::sleep(retry_wait.unwrap_or_default()), if retry_wait.is_some() => {
+                    self.route_attempts.retry_due();
+                    self.resume_waiting_packets(&logger).await;
+                    self.save_store(&logger);
+                }
+                _ = residence_timer.tick(), if self.max_residence.is_some() => {
+                    if self.is_overdue() {
+                        if let Err(err) = self.send_waiting_packets(&logger).await {
+                            self.warn_limited(&logger, format!("ignoring failed forced send {err:?}"));
+                        }
+                        self.save_store(&logger);
+                    }
+                }
+                _ = log_repeat_timer.tick(), if log_repeat_interval.is_some() => {
+                    for (message, repeats) in self.log_limit.summaries(Instant::now()) {
+                        warn!(logger, "{message}"; "repeats" => repeats);
+                    }
+                }
+                _ = flush_timer.tick(), if flush_interval.is_some() => {
+                    if self.store_flush.is_due(Instant::now()) {
+                        self.flush_store(&logger);
+                    }
+                }
                 _ = store_gc_timer.tick() => {
-                    let removed = self.store.gc_waiting_packets(STORE_GC_INTERVAL);
+                    debug!(logger, "queue time"; self.queue_time.counters());
+                    debug!(logger, "state channels"; self.sc_metrics.counters());
+                    let removed = self
+                        .store
+                        .gc_waiting_packets(STORE_GC_INTERVAL + self.clock_skew);
                     if removed > 0 {
                         info!(logger, "discarded {} queued packets", removed);
+                        self.save_store(&logger);
                     }
                 }
             }
         }
     }
 
+    fn load_store(&mut self, logger: &Logger) {
+        if let Some(path) = &self.store_path {
+            match self.store.load(path) {
+                Ok(0) => (),
+                Ok(loaded) => info!(logger, "loaded {loaded} queued packets"),
+                Err(err) => warn!(logger, "ignoring failed store load: {err:?}"),
+            }
+            match self.replays.load(&ReplayWindow::path(path)) {
+                Ok(0) => (),
+                Ok(loaded) => debug!(logger, "loaded {loaded} routed packet keys"),
+                Err(err) => warn!(logger, "ignoring failed replay window load: {err:?}"),
+            }
+        }
+    }
+
+    /// Records a change to the queue, writing the persisted queue if the
+    /// flush cadence is due
+    fn save_store(&mut self, logger: &Logger) {
+        if self.store_path.is_some() && self.store_flush.changed(Instant::now()) {
+            self.flush_store(logger);
+        }
+    }
+
+    /// Writes the persisted queue regardless of the flush cadence. The
+    /// queue is encoded right away and written by the file writer.
+    fn flush_store(&mut self, logger: &Logger) {
+        if let Some(path) = &self.store_path {
+            let store_data = self.store.to_bytes();
+            let replays_data = self.replays.to_bytes(Instant::now());
+            let (store_path, replays_path) = (path.clone(), ReplayWindow::path(path));
+            let logger = logger.clone();
+            self.writer.write(move || {
+                if let Err(err) = store_data.and_then(|data| store::write_file(&store_path, &data))
+                {
+                    warn!(logger, "failed to save store: {err:?}");
+                }
+                if let Err(err) = store::write_file(&replays_path, &replays_data) {
+                    warn!(logger, "failed to save replay window: {err:?}");
+                }
+            });
+            self.store_flush.flushed(Instant::now());
+        }
+    }
+
+    /// Writes the persisted queue and waits for all pending writes, so the
+    /// files are complete once the client stops
+    async fn close_store(&mut self, logger: &Logger) {
+        self.flush_store(logger);
+        self.writer.sync().await;
+    }
+
+    /// Switches to the given region. Packets signed for the previous region are
+    /// discarded so they are signed again. An unchanged region is ignored.
+    /// Ends a region params refresh either way, returning whether routing was
+    /// held for one.
+    fn handle_region_changed(&mut self, logger: &Logger, region: Region) -> bool {
+        let refreshed = self.region_refresh.take().is_some();
+        if refreshed {
+            info!(logger, "region params refreshed, resuming routing");
+        }
+        if region == self.region {
+            debug!(logger, "ignoring unchanged region";
+                "region" => region);
+            return refreshed;
+        }
+        self.region = region;
+        self.signatures.clear();
+        info!(logger, "updated region";
+            "region" => region);
+        refreshed
+    }
+
     async fn handle_uplink(
         &mut self,
         logger: &Logger,
         uplink: Packet,
+        region: Option<Region>,
         received: Instant,
     ) -> Result {
-        self.store.store_waiting_packet(uplink, received)?;
-        self.send_waiting_packets(logger).await
+        let backpressure = self.store.backpressure();
+        if let Err(err) = self.store.store_waiting_packet_in(uplink, region, received) {
+            let failures = self.store.failures();
+            debug!(logger, "packet not queued";
+                "capacity" => failures.capacity,
+                "rate_limited" => failures.rate_limited,
+                "filtered" => failures.filtered);
+            return Err(err);
+        }
+        let result = self.send_waiting_packets(logger).await;
+        match (backpressure, self.store.backpressure()) {
+            (false, true) => warn!(logger, "queue full, dropping oldest packets";
+                "queued" => self.store.waiting_packets_len()),
+            (true, false) => info!(logger, "queue below capacity"),
+            _ => (),
+        }
+        result
+    }
+
+    fn capture(&mut self, logger: &Logger, direction: CaptureDirection, packet: &Packet) {
+        if let Some(capture) = &self.capture {
+            let (capture, packet, logger) = (capture.clone(), packet.clone(), logger.clone());
+            self.writer.write(move || {
+                let mut capture = capture.lock().expect("packet capture");
+                if let Err(err) = capture.record(direction, &packet) {
+                    warn!(logger, "failed to capture packet: {err:?}");
+                }
+            });
+        }
     }
 
     async fn handle_downlink(&mut self, logger: &Logger, packet: Packet) {
-        let _ = self
-            .downlinks
-            .downlink(packet)
-            .inspect_err(|_| warn!(logger, "failed to push downlink"))
-            .await;
+        self.capture(logger, CaptureDirection::Downlink, &packet);
+        if let Err(err) = self.downlinks.downlink(packet).await {
+            warn!(logger, "failed to push downlink: {err}");
+            self.downlinks_closed |= err.is_channel_closed();
+        }
+    }
+
+    /// Checks the chain tip against the maximum block age, entering or leaving
+    /// degraded mode as needed. Returns whether packets may be sent.
+    fn check_chain_tip(&mut self, logger: &Logger, now: Instant) -> bool {
+        let max_block_age = match self.max_block_age {
+            Some(max_block_age) => max_block_age,
+            None => return true,
+        };
+        let result = ChainTip::check(
+            self.chain_tip.borrow().as_ref(),
+            max_block_age + self.clock_skew,
+            now,
+        );
+        match (self.degraded, result) {
+            (false, Err(err)) => {
+                warn!(logger, "chain tip stale, queueing packets: {err}");
+                self.degraded = true;
+            }
+            (true, Ok(())) => {
+                info!(logger, "chain tip fresh, resuming routing");
+                self.degraded = false;
+            }
+            _ => (),
+        }
+        !self.degraded
+    }
+
+    /// The time routing remains held for a region params refresh, if it is
+    fn region_refresh_wait(&self, now: Instant) -> Option<Duration> {
+        let started = self.region_refresh?;
+        let held = now.saturating_duration_since(started);
+        self.region_refresh_pause.checked_sub(held)
+    }
+
+    /// Checks whether routing is held for a region params refresh, ending the
+    /// hold once it reaches the region refresh pause. Returns whether packets
+    /// may be sent.
+    fn check_region_refresh(&mut self, logger: &Logger, now: Instant) -> bool {
+        if self.region_refresh.is_none() {
+            return true;
+        }
+        if self
+            .region_refresh_wait(now)
+            .map_or(false, |wait| !wait.is_zero())
+        {
+            return false;
+        }
+        warn!(logger, "region params refresh not done, resuming routing";
+            "pause" => self.region_refresh_pause.as_millis());
+        self.region_refresh = None;
+        true
+    }
+
+    /// The time until the next queued packet is sent when pacing sends, if it
+    /// has to wait. The oldest queued packet does not wait if it would reach
+    /// the maximum hold time by waiting.
+    fn pacing_wait(&self, now: Instant) -> Option<Duration> {
+        let pacing = self.pacing?;
+        let remaining = self.next_send?.saturating_duration_since(now);
+        let hold_time = self.store.oldest_hold_time()?;
+        Some(pacing.wait(hold_time, remaining)).filter(|wait| !wait.is_zero())
+    }
+
+    /// Whether the oldest queued packet has waited the maximum residence
+    fn is_overdue(&self) -> bool {
+        match (self.max_residence, self.store.oldest_hold_time()) {
+            (Some(max_residence), Some(hold_time)) => hold_time >= max_residence,
+            _ => false,
+        }
+    }
+
+    /// Logs a warning, unless it repeats a recent one. Repeats are summarized
+    /// in the run loop.
+    fn warn_limited(&mut self, logger: &Logger, message: String) {
+        if self.log_limit.record(&message, Instant::now()) {
+            warn!(logger, "{message}");
+        }
+    }
+
+    async fn resume_waiting_packets(&mut self, logger: &Logger) {
+        if let Err(err) = self.send_waiting_packets(logger).await {
+            self.warn_limited(logger, format!("ignoring failed resume {err:?}"));
+        }
     }
 
     async fn send_waiting_packets(&mut self, logger: &Logger) -> Result {
-        while let Some(packet) = self.store.pop_waiting_packet() {
-            if let Some(message) = self.send_packet(logger, &packet).await? {
+        let ready = self.route_attempts.is_ready(Instant::now());
+        let ready = self.check_chain_tip(logger, Instant::now()) && ready;
+        let ready = self.check_region_refresh(logger, Instant::now()) && ready;
+        // While paused only packets at their maximum residence are attempted,
+        // one at a time
+        let forced = !ready && self.is_overdue();
+        if !ready && !forced {
+            return Ok(());
+        }
+        if forced {
+            debug!(logger, "attempting packets at maximum residence while paused";
+                "queued" => self.store.waiting_packets_len());
+        }
+        let mut signing = SigningPool::new(if forced { 1 } else { self.signing_tasks });
+        loop {
+            if forced && !self.is_overdue() {
+                break;
+            }
+            // The run loop resumes once it is time for the next paced send
+            if !forced && self.next_send.map_or(false, |next| next > Instant::now()) {
+                // Requeue in reverse so the queue keeps its order
+                for (pending, _) in signing.cancel().into_iter().rev() {
+                    self.store.requeue_waiting_packet(pending);
+                }
+                if let Some(wait) = self.pacing_wait(Instant::now()) {
+                    debug!(logger, "pacing sends"; "wait" => wait.as_millis());
+                    break;
+                }
+            }
+            self.fill_signing_pool(logger, &mut signing);
+            let (packet, packet_key, signed) = match signing.next().await {
+                Some(((packet, packet_key), signed)) => (packet, packet_key, signed),
+                None => break,
+            };
+            // An earlier packet in the pool may have been a copy of this one
+            if self.replays.contains(&packet_key, Instant::now()) {
+                debug!(logger, "dropping replayed packet";
+                    "packet_hash" => packet.hash().to_b64());
+                continue;
+            }
+            let signed = match self.sign_packet(&packet, &packet_key, signed).await {
+                Ok(signed) => signed,
+                Err(err) => {
+                    // Requeue in reverse so the queue keeps its order
+                    for (pending, _) in signing.cancel().into_iter().rev() {
+                        self.store.requeue_waiting_packet(pending);
+                    }
+                    if self.retry_signing(logger, packet, &err) {
+                        return Err(err);
+                    }
+                    continue;
+                }
+            };
+            let message = match self.send_packet(logger, &packet, &packet_key, signed).await {
+                Ok(message) => {
+                    self.capture(logger, CaptureDirection::Uplink, packet.packet());
+                    self.replays.insert(packet_key, Instant::now());
+                    self.next_send = self
+                        .pacing
+                        .map(|pacing| Instant::now() + pacing.delay(&mut rand::thread_rng()));
+                    let failures = self.route_attempts.succeeded();
+                    if failures > 0 {
+                        info!(logger, "routing recovered after {failures} failures");
+                    }
+                    message
+                }
+                Err(err) => {
+                    let wait = self
+                        .route_attempts
+                        .failed(&self.route_retry, Instant::now());
+                    debug!(logger, "retrying routing in {}s", wait.as_secs());
+                    // Requeue in reverse so the queue keeps its order
+                    for (pending, _) in signing.cancel().into_iter().rev() {
+                        self.store.requeue_waiting_packet(pending);
+                    }
+                    self.store.requeue_waiting_packet(packet);
+                    return Err(err);
+                }
+            };
+            if let Some(message) = message {
                 match message.to_downlink() {
-                    Ok(Some(packet)) => self.handle_downlink(logger, packet).await,
+                    Ok(Some(mut downlink)) => {
+                        let region = packet.region().unwrap_or(self.region);
+                        if let Some(delay) = self.downlink_settings.rx1_delay(&region) {
+                            downlink.set_rx1_delay(packet.timestamp, delay);
+                        }
+                        downlink.advance_timestamps(self.downlink_settings.tx_compensation());
+                        self.handle_downlink(logger, downlink).await
+                    }
                     Ok(None) => (),
                     Err(err) => warn!(logger, "ignoring router response: {err:?}"),
                 }
@@ -161,21 +878,1025 @@ impl RouterClient {
         Ok(())
     }
 
+    /// Moves waiting packets into the signing pool until it is full. Packets
+    /// with a cached signature are added without signing them again.
+    fn fill_signing_pool(&mut self, logger: &Logger, pool: &mut SigningPool<(QuePacket, Vec<u8>)>) {
+        while !pool.is_full() {
+            let packet = match self.store.pop_waiting_packet() {
+                Some(packet) => packet,
+                None => break,
+            };
+            let packet_key = packet.key();
+            if self.replays.contains(&packet_key, Instant::now()) {
+                debug!(logger, "dropping replayed packet";
+                    "packet_hash" => packet.hash().to_b64());
+                continue;
+            }
+            if self.signatures.contains(&packet_key) {
+                pool.push_unsigned((packet, packet_key));
+                continue;
+            }
+            let sc_packet = packet.packet().clone();
+            let keypair = self.keypair.clone();
+            let region = packet.region().unwrap_or(self.region);
+            let hold_time = packet.hold_time().as_millis() as u64;
+            pool.push((packet, packet_key), async move {
+                StateChannelMessage::packet(sc_packet, keypair, &region, hold_time)
+                    .map_ok(BlockchainStateChannelPacketV1::from)
+                    .await
+            });
+        }
+    }
+
+    /// Requeues a packet that failed to sign at the front of the queue and
+    /// holds routing for the sign retry delay, unless the failure is not
+    /// retryable or the packet used up its signing attempts. Returns whether
+    /// the packet was requeued.
+    fn retry_signing(&mut self, logger: &Logger, mut packet: QuePacket, err: &Error) -> bool {
+        let failures = packet.sign_failed();
+        let wait = self
+            .sign_retry
+            .delay(failures)
+            .filter(|_| err.is_retryable_signing());
+        if let Some(wait) = wait {
+            warn!(logger, "failed to sign packet, requeueing: {err:?}";
+                "packet_hash" => packet.hash().to_b64(),
+                "failures" => failures,
+                "wait" => wait.as_millis());
+            self.route_attempts.defer(Instant::now() + wait);
+            self.store.requeue_waiting_packet(packet);
+            return true;
+        }
+        warn!(logger, "dropping packet that failed to sign: {err:?}";
+            "packet_hash" => packet.hash().to_b64(),
+            "failures" => failures);
+        false
+    }
+
+    /// Returns the signed packet, using the given signed packet if it was
+    /// signed ahead of time and not already cached
+    async fn sign_packet(
+        &mut self,
+        packet: &QuePacket,
+        packet_key: &[u8],
+        presigned: Option<Result<BlockchainStateChannelPacketV1>>,
+    ) -> Result<BlockchainStateChannelPacketV1> {
+        let keypair = self.keypair.clone();
+        let region = packet.region().unwrap_or(self.region);
+        let hold_time = packet.hold_time().as_millis() as u64;
+        self.signatures
+            .get_or_sign(packet_key, || async move {
+                match presigned {
+                    Some(signed) => signed,
+                    None => {
+                        StateChannelMessage::packet(
+                            packet.packet().clone(),
+                            keypair,
+                            &region,
+                            hold_time,
+                        )
+                        .map_ok(BlockchainStateChannelPacketV1::from)
+                        .await
+                    }
+                }
+            })
+            .await
+    }
+
     async fn send_packet(
         &mut self,
         logger: &Logger,
         packet: &QuePacket,
+        packet_key: &[u8],
+        signed: BlockchainStateChannelPacketV1,
     ) -> Result<Option<StateChannelMessage>> {
         debug!(logger, "sending packet";
-            "packet_hash" => packet.hash().to_b64());
-        StateChannelMessage::packet(
-            packet.packet().clone(),
-            self.keypair.clone(),
-            &self.region,
-            packet.hold_time().as_millis() as u64,
+            "packet_hash" => packet.hash().to_b64(),
+            "source" => packet.source().map(UplinkSource::to_string),
+            "time" => packet.time().map(|time| time.time),
+            "time_source" => packet.time().map(|time| time.source.to_string()));
+        self.queue_time.record(packet.hold_time());
+        let response = self
+            .router
+            .route(StateChannelMessage::from(signed).to_message())
+            .await?;
+        self.signatures.remove(packet_key);
+        let response = StateChannelMessage::from_message(response);
+        self.sc_metrics.record_response(response.as_ref());
+        let sc_id = response.as_ref().and_then(StateChannelMessage::sc_id);
+        if let Some(sc_id) = sc_id {
+            info!(logger, "packet sent";
+                "packet_hash" => packet.hash().to_b64(),
+                "source" => packet.source().map(UplinkSource::to_string),
+                "sc_id" => sc_id.to_b64());
+        }
+        // Invalid summaries are skipped, only counted
+        let summary = response
+            .as_ref()
+            .and_then(|response| response.summary(self.keypair.public_key()))
+            .filter(|summary| match validate_summary(summary) {
+                Ok(()) => true,
+                Err(err) => {
+                    self.sc_metrics.record_invalid_summary();
+                    warn!(logger, "skipping invalid state channel summary: {err:?}");
+                    false
+                }
+            });
+        if let Some(summary) = summary {
+            if self
+                .sc_metrics
+                .record_summary(sc_id.unwrap_or_default(), summary)
+            {
+                warn!(logger, "state channel summary conflicts with an earlier one";
+                    "num_packets" => summary.num_packets);
+            }
+        }
+        Ok(response)
+    }
+}
+
+/// Client settings with a small queue and no store, for tests
+#[cfg(test)]
+pub(crate) fn mk_test_client_settings(transport: RouterTransport) -> ClientSettings {
+    let router_settings = crate::RouterSettings::default();
+    ClientSettings {
+        cache: CacheSettings {
+            max_packets: 10,
+            store: None,
+            compress: false,
+            queue_time_buckets: vec![],
+            signature_cache: 4,
+            filter_duplicates: false,
+            hash_exclude: vec![],
+            max_rate: None,
+            flush_interval: None,
+            flush_changes: None,
+        },
+        downlink: DownlinkSettings::default(),
+        transport,
+        chain_tip: crate::router::chain_tip::chain_tip_channel().1,
+        max_block_age: None,
+        clock_skew: Duration::ZERO,
+        capture: None,
+        capture_max_size: 0,
+        send_pacing: None,
+        max_residence: None,
+        signing_tasks: 1,
+        route_retry: router_settings.route_retry,
+        sign_retry: router_settings.sign_retry,
+        region_refresh_pause: router_settings.region_refresh_pause(),
+        log_repeat_interval: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        gateway, keyed_uri::mk_test_uri, keypair::mk_test_keypair, RouterSettings, TimestampSource,
+        UplinkTime,
+    };
+    use helium_proto::Region as ProtoRegion;
+
+    fn mk_region(region: ProtoRegion) -> Region {
+        Region::from_i32(region.into()).expect("region")
+    }
+
+    async fn mk_client(region: Region, uri: &str, transport: RouterTransport) -> RouterClient {
+        mk_client_with(region, uri, &mk_test_client_settings(transport)).await
+    }
+
+    async fn mk_client_with(region: Region, uri: &str, settings: &ClientSettings) -> RouterClient {
+        let downlinks = gateway::MemoryDownlinkSink::default();
+        RouterClient::new(
+            0,
+            region,
+            mk_test_uri(uri),
+            downlinks,
+            Arc::new(mk_test_keypair().into()),
+            settings,
+        )
+        .await
+        .expect("router client")
+    }
+
+    /// Runs the given client until it handles a stop message, returning it
+    /// for inspection
+    async fn run_until_stopped(
+        mut client: RouterClient,
+        messages: MessageReceiver,
+    ) -> RouterClient {
+        let logger = Logger::root(slog::Discard, o!());
+        let (_trigger, shutdown) = triggered::trigger();
+        client
+            .run(messages, shutdown, &logger)
+            .await
+            .expect("client run");
+        client
+    }
+
+    #[tokio::test]
+    async fn duplicate_region_changed() {
+        let logger = Logger::root(slog::Discard, o!());
+        let (us915, eu868) = (mk_region(ProtoRegion::Us915), mk_region(ProtoRegion::Eu868));
+        let mut client =
+            mk_client(us915, "http://127.0.0.1:8080", RouterTransport::default()).await;
+        client
+            .signatures
+            .get_or_sign(b"packet", || async {
+                Ok(BlockchainStateChannelPacketV1::default())
+            })
+            .await
+            .expect("signed packet");
+
+        // The same region keeps packets signed for it
+        client.handle_region_changed(&logger, us915);
+        assert_eq!(us915, client.region);
+        assert_eq!(1, client.signatures.len());
+
+        client.handle_region_changed(&logger, eu868);
+        assert_eq!(eu868, client.region);
+        assert!(client.signatures.is_empty());
+    }
+
+    #[tokio::test]
+    async fn region_override() {
+        let logger = Logger::root(slog::Discard, o!());
+        let (us915, eu868) = (mk_region(ProtoRegion::Us915), mk_region(ProtoRegion::Eu868));
+        let mut client =
+            mk_client(us915, "http://127.0.0.1:8080", RouterTransport::default()).await;
+        for (payload, region) in [(1, Some(eu868)), (2, None)] {
+            let packet: Packet = helium_proto::Packet {
+                payload: vec![payload],
+                ..Default::default()
+            }
+            .into();
+            client
+                .store
+                .store_waiting_packet_in(packet, region, Instant::now())
+                .expect("queued packet");
+        }
+
+        let mut signing = SigningPool::new(2);
+        client.fill_signing_pool(&logger, &mut signing);
+        let mut regions = vec![];
+        while let Some((_, signed)) = signing.next().await {
+            let signed = signed.expect("pool signed").expect("signed packet");
+            regions.push(signed.region);
+        }
+        // The override signs for its own region, the client keeps its region
+        assert_eq!(vec![i32::from(eu868), i32::from(us915)], regions);
+        assert_eq!(us915, client.region);
+    }
+
+    #[tokio::test]
+    async fn signing_retries() {
+        let logger = Logger::root(slog::Discard, o!());
+        let region = mk_region(ProtoRegion::Us915);
+        let mut client =
+            mk_client(region, "http://127.0.0.1:8080", RouterTransport::default()).await;
+        let mk_packet = |payload: u8| -> Packet {
+            helium_proto::Packet {
+                payload: vec![payload],
+                ..Default::default()
+            }
+            .into()
+        };
+        client
+            .store
+            .store_waiting_packet(mk_packet(1), Instant::now())
+            .expect("queued packet");
+        let transient = || Error::from(helium_crypto::Error::from(signature::Error::new()));
+
+        // A transient failure requeues the packet, the next attempt succeeds
+        let packet = client.store.pop_waiting_packet().expect("queued packet");
+        let key = packet.key();
+        let err = client
+            .sign_packet(&packet, &key, Some(Err(transient())))
+            .await
+            .expect_err("signing failure");
+        assert!(client.retry_signing(&logger, packet, &err));
+        assert!(!client.route_attempts.is_ready(Instant::now()));
+        let packet = client.store.pop_waiting_packet().expect("requeued packet");
+        let signed = BlockchainStateChannelPacketV1 {
+            signature: vec![4, 5, 6],
+            ..Default::default()
+        };
+        assert_eq!(
+            signed,
+            client
+                .sign_packet(&packet, &key, Some(Ok(signed.clone())))
+                .await
+                .expect("signed packet")
+        );
+
+        // Failures beyond the retry budget drop the packet
+        let mut packet = packet;
+        for _ in 1..client.sign_retry.max_attempts {
+            packet.sign_failed();
+        }
+        assert!(!client.retry_signing(&logger, packet, &transient()));
+        assert_eq!(0, client.store.waiting_packets_len());
+
+        // As do failures that are not transient
+        client
+            .store
+            .store_waiting_packet(mk_packet(2), Instant::now())
+            .expect("queued packet");
+        let packet = client.store.pop_waiting_packet().expect("queued packet");
+        assert!(!client.retry_signing(&logger, packet, &Error::custom("encode")));
+    }
+
+    #[tokio::test]
+    async fn downlink_sink_captures() {
+        let logger = Logger::root(slog::Discard, o!());
+        let region = mk_region(ProtoRegion::Us915);
+        let mut client =
+            mk_client(region, "http://127.0.0.1:8080", RouterTransport::default()).await;
+        let sink = gateway::MemoryDownlinkSink::default();
+        client.downlinks = Arc::new(sink.clone());
+
+        let downlink: Packet = helium_proto::Packet {
+            payload: vec![1, 2, 3],
+            timestamp: 1_000_000,
+            ..Default::default()
+        }
+        .into();
+        client.handle_downlink(&logger, downlink).await;
+
+        let captured = sink.take();
+        assert_eq!(1, captured.len());
+        assert_eq!(&[1, 2, 3], captured[0].payload());
+        assert_eq!(1_000_000, captured[0].timestamp);
+        assert!(sink.take().is_empty());
+    }
+
+    #[tokio::test]
+    async fn downlinks_captured() {
+        let logger = Logger::root(slog::Discard, o!());
+        let region = mk_region(ProtoRegion::Us915);
+        let mut client =
+            mk_client(region, "http://127.0.0.1:8080", RouterTransport::default()).await;
+        let dir = std::env::temp_dir().join(format!("downlinks_captured_{}", std::process::id()));
+        let path = dir.join("router.capture");
+        let _ = std::fs::remove_dir_all(&dir);
+        client.capture = Some(Arc::new(Mutex::new(PacketCapture::new(&path, 1024))));
+
+        for payload in 0..2 {
+            let downlink: Packet = helium_proto::Packet {
+                payload: vec![payload],
+                ..Default::default()
+            }
+            .into();
+            client.handle_downlink(&logger, downlink).await;
+        }
+        client.writer.sync().await;
+        // Two records of a direction byte, a time and a 4 byte packet
+        let data = std::fs::read(&path).expect("capture file");
+        assert_eq!(2 * 13, data.len());
+        assert_eq!(CaptureDirection::Downlink as u8, data[0]);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn routed_uplinks_captured() {
+        use helium_proto::Message as _;
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("listener");
+        let addr = listener.local_addr().expect("listener address");
+        let (_router_trigger, router_shutdown) = triggered::trigger();
+        tokio::spawn(crate::router::LoopbackRouter.serve(listener, router_shutdown));
+
+        let dir =
+            std::env::temp_dir().join(format!("routed_uplinks_captured_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let mut settings = mk_test_client_settings(RouterTransport::default());
+        settings.capture = Some(dir.clone());
+        settings.capture_max_size = 1024;
+        let region = mk_region(ProtoRegion::Us915);
+        let client = mk_client_with(region, &format!("http://{addr}"), &settings).await;
+        let (messages, messages_rx) = message_channel(10);
+        let running = tokio::spawn(run_until_stopped(client, messages_rx));
+        let packet: Packet = helium_proto::Packet {
+            payload: vec![7],
+            timestamp: 1_000_000,
+            ..Default::default()
+        }
+        .into();
+        messages
+            .uplink(packet, Instant::now())
+            .await
+            .expect("uplink");
+        messages.stop().await;
+        time::timeout(Duration::from_secs(5), running)
+            .await
+            .expect("client stopped")
+            .expect("client task");
+
+        // The routed uplink is captured before the downlink the router
+        // echoed, and both are written by the time the client stops
+        let capture = std::fs::read_dir(&dir)
+            .expect("capture folder")
+            .next()
+            .expect("capture file")
+            .expect("capture entry");
+        let data = std::fs::read(capture.path()).expect("capture file");
+        let mut buf = &data[..];
+        let mut records = vec![];
+        while !buf.is_empty() {
+            let direction = buf[0];
+            buf = &buf[9..];
+            let packet =
+                helium_proto::Packet::decode_length_delimited(&mut buf).expect("captured packet");
+            records.push((direction, packet.payload));
+        }
+        assert_eq!(
+            vec![
+                (CaptureDirection::Uplink as u8, vec![7]),
+                (CaptureDirection::Downlink as u8, vec![7]),
+            ],
+            records
+        );
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    /// A router that pays for every packet from the same state channel
+    struct PurchaseRouter;
+
+    #[tonic::async_trait]
+    impl helium_proto::services::router::Router for PurchaseRouter {
+        async fn route(
+            &self,
+            _request: tonic::Request<helium_proto::BlockchainStateChannelMessageV1>,
+        ) -> std::result::Result<
+            tonic::Response<helium_proto::BlockchainStateChannelMessageV1>,
+            tonic::Status,
+        > {
+            use helium_proto::{
+                blockchain_state_channel_message_v1::Msg, BlockchainStateChannelPurchaseV1,
+                BlockchainStateChannelV1,
+            };
+            let purchase = BlockchainStateChannelPurchaseV1 {
+                sc: Some(BlockchainStateChannelV1 {
+                    id: b"channel_a".to_vec(),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            };
+            Ok(tonic::Response::new(
+                helium_proto::BlockchainStateChannelMessageV1 {
+                    msg: Some(Msg::Purchase(purchase)),
+                },
+            ))
+        }
+    }
+
+    #[tokio::test]
+    async fn sc_id_logged() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("listener");
+        let addr = listener.local_addr().expect("listener address");
+        let incoming = futures::stream::unfold(listener, |listener| async move {
+            let stream = listener.accept().await.map(|(stream, _)| stream);
+            Some((stream, listener))
+        });
+        let (_router_trigger, router_shutdown) = triggered::trigger();
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(helium_proto::services::router::Server::new(PurchaseRouter))
+                .serve_with_incoming_shutdown(incoming, router_shutdown),
+        );
+
+        let region = mk_region(ProtoRegion::Us915);
+        let mut client = mk_client(
+            region,
+            &format!("http://{addr}"),
+            RouterTransport::default(),
+        )
+        .await;
+        let (messages, messages_rx) = message_channel(10);
+        let packet: Packet = helium_proto::Packet {
+            payload: vec![7],
+            ..Default::default()
+        }
+        .into();
+        let packet_hash = packet.hash().to_b64();
+        messages
+            .uplink(packet, Instant::now())
+            .await
+            .expect("uplink");
+        messages.stop().await;
+        let capture = crate::metrics::KvCapture::default();
+        let (_trigger, shutdown) = triggered::trigger();
+        time::timeout(
+            Duration::from_secs(5),
+            client.run(messages_rx, shutdown, &capture.logger()),
+        )
+        .await
+        .expect("client stopped")
+        .expect("client run");
+
+        // The sent packet is logged with the state channel that paid for it
+        let logged = capture.logged();
+        assert!(logged.contains(&format!("sc_id={}", b"channel_a"[..].to_b64())));
+        assert!(logged.contains(&format!("packet_hash={packet_hash}")));
+    }
+
+    #[test]
+    fn send_pacing_jitter() {
+        use rand::{rngs::StdRng, SeedableRng};
+        let mut rng = StdRng::seed_from_u64(0x5eed);
+        let pacing = SendPacing::new(Duration::from_millis(100));
+
+        // Draining a full queue spaces sends by roughly the pacing interval
+        let delays: Vec<Duration> = (0..100).map(|_| pacing.delay(&mut rng)).collect();
+        assert!(delays
+            .iter()
+            .all(|delay| (50..150).contains(&delay.as_millis())));
+        let average = delays.iter().sum::<Duration>() / delays.len() as u32;
+        assert!((80..120).contains(&average.as_millis()));
+
+        // Packets about to age out are not delayed
+        let hold_time = STORE_GC_INTERVAL - Duration::from_millis(10);
+        assert_eq!(Duration::ZERO, pacing.wait(hold_time, delays[0]));
+        assert_eq!(delays[0], pacing.wait(Duration::ZERO, delays[0]));
+    }
+
+    #[test]
+    fn max_residence_skips_pacing() {
+        use rand::{rngs::StdRng, SeedableRng};
+        let mut rng = StdRng::seed_from_u64(0x5eed);
+        let pacing = SendPacing::new(Duration::from_secs(1))
+            .with_max_residence(Some(Duration::from_secs(10)));
+
+        let delay = pacing.delay(&mut rng);
+        assert!(delay > Duration::ZERO);
+        assert_eq!(delay, pacing.wait(Duration::ZERO, delay));
+        // A packet that would pass its maximum residence by waiting is sent
+        // right away, long before it would age out
+        let hold_time = Duration::from_millis(9_900);
+        assert_eq!(Duration::ZERO, pacing.wait(hold_time, delay));
+
+        // A residence beyond the queue expiry does not delay expiring packets
+        let pacing =
+            SendPacing::new(Duration::from_secs(1)).with_max_residence(Some(STORE_GC_INTERVAL * 2));
+        let hold_time = STORE_GC_INTERVAL - Duration::from_millis(10);
+        assert_eq!(
+            Duration::ZERO,
+            pacing.wait(hold_time, pacing.delay(&mut rng))
+        );
+    }
+
+    #[tokio::test]
+    async fn send_pacing_keeps_handling_messages() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("listener");
+        let addr = listener.local_addr().expect("listener address");
+        let (_router_trigger, router_shutdown) = triggered::trigger();
+        tokio::spawn(crate::router::LoopbackRouter.serve(listener, router_shutdown));
+
+        let mut settings = mk_test_client_settings(RouterTransport::default());
+        settings.send_pacing = Some(Duration::from_secs(20));
+        let region = mk_region(ProtoRegion::Us915);
+        let client = mk_client_with(region, &format!("http://{addr}"), &settings).await;
+        let (messages, messages_rx) = message_channel(10);
+        let running = tokio::spawn(run_until_stopped(client, messages_rx));
+        for payload in [1, 2] {
+            let packet: Packet = helium_proto::Packet {
+                payload: vec![payload],
+                ..Default::default()
+            }
+            .into();
+            messages
+                .uplink(packet, Instant::now())
+                .await
+                .expect("uplink");
+        }
+        // The stop is handled while the second packet waits for its turn
+        messages.stop().await;
+        let client = time::timeout(Duration::from_secs(5), running)
+            .await
+            .expect("client not blocked by pacing")
+            .expect("client task");
+        assert_eq!(1, client.store.waiting_packets_len());
+        assert!(client.pacing_wait(Instant::now()).is_some());
+    }
+
+    #[test]
+    fn route_success_resets_attempts() {
+        let retry = RouterSettings::default().route_retry;
+        let now = Instant::now();
+        let mut attempts = RouteAttempts::default();
+        assert!(attempts.is_ready(now));
+
+        for _ in 0..3 {
+            attempts.failed(&retry, now);
+        }
+        assert_eq!(3, attempts.failures);
+        assert!(!attempts.is_ready(now));
+
+        assert_eq!(3, attempts.succeeded());
+        assert_eq!(0, attempts.failures);
+        assert!(attempts.is_ready(now));
+        assert_eq!(0, attempts.succeeded());
+    }
+
+    #[tokio::test]
+    async fn route_timeout_requeues() {
+        // A router that accepts connections but never responds
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("listener");
+        let addr = listener.local_addr().expect("listener address");
+        tokio::spawn(async move {
+            let mut connections = vec![];
+            while let Ok((stream, _)) = listener.accept().await {
+                connections.push(stream);
+            }
+        });
+
+        let logger = Logger::root(slog::Discard, o!());
+        let transport = RouterTransport::new(&crate::RouterSettings {
+            route_timeout: 100,
+            ..Default::default()
+        });
+        let region = mk_region(ProtoRegion::Us915);
+        let mut client = mk_client(region, &format!("http://{addr}"), transport).await;
+        let packet: Packet = helium_proto::Packet {
+            payload: vec![1, 2, 3],
+            ..Default::default()
+        }
+        .into();
+        let result = client
+            .handle_uplink(&logger, packet, None, Instant::now())
+            .await;
+        assert!(matches!(
+            result,
+            Err(Error::Service(crate::error::ServiceError::Timeout(_)))
+        ));
+        assert_eq!(1, client.store.waiting_packets_len());
+    }
+
+    #[tokio::test]
+    async fn route_retried_after_backoff() {
+        // A router that accepts connections but never responds
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("listener");
+        let addr = listener.local_addr().expect("listener address");
+        tokio::spawn(async move {
+            let mut connections = vec![];
+            while let Ok((stream, _)) = listener.accept().await {
+                connections.push(stream);
+            }
+        });
+
+        let mut settings = mk_test_client_settings(RouterTransport::new(&crate::RouterSettings {
+            route_timeout: 100,
+            ..Default::default()
+        }));
+        settings.route_retry = RetryPolicy {
+            max_attempts: 10,
+            base_delay: 50,
+            max_delay: 50,
+            jitter: 0.0,
+        };
+        let region = mk_region(ProtoRegion::Us915);
+        let client = mk_client_with(region, &format!("http://{addr}"), &settings).await;
+        let (messages, messages_rx) = message_channel(10);
+        let running = tokio::spawn(run_until_stopped(client, messages_rx));
+        let packet: Packet = helium_proto::Packet {
+            payload: vec![1, 2, 3],
+            ..Default::default()
+        }
+        .into();
+        messages
+            .uplink(packet, Instant::now())
+            .await
+            .expect("uplink");
+
+        // The queued packet is attempted again once the backoff ends, with no
+        // further uplinks
+        time::sleep(Duration::from_millis(500)).await;
+        messages.stop().await;
+        let client = running.await.expect("client task");
+        assert!(client.route_attempts.failures >= 2);
+        assert_eq!(1, client.store.waiting_packets_len());
+        // Selection learns that the router is failing
+        assert!(client.status().borrow().failing);
+    }
+
+    #[tokio::test]
+    async fn backpressure_reported() {
+        let mut settings = mk_test_client_settings(RouterTransport::new(&crate::RouterSettings {
+            route_timeout: 100,
+            ..Default::default()
+        }));
+        settings.cache.max_packets = 2;
+        let region = mk_region(ProtoRegion::Us915);
+        // Nothing listens on port 1, so packets stay queued
+        let client = mk_client_with(region, "http://127.0.0.1:1", &settings).await;
+        let status = client.status();
+        assert!(!status.borrow().backpressure);
+
+        let (messages, messages_rx) = message_channel(10);
+        let running = tokio::spawn(run_until_stopped(client, messages_rx));
+        for payload in 0..3 {
+            let packet = Packet::from(helium_proto::Packet {
+                payload: vec![payload],
+                ..Default::default()
+            });
+            messages
+                .uplink(packet, Instant::now())
+                .await
+                .expect("uplink");
+        }
+        messages.stop().await;
+        let mut client = running.await.expect("client task");
+        // Dropping the oldest packet to stay at capacity is reported
+        assert!(status.borrow().backpressure);
+        assert_eq!(2, client.store.waiting_packets_len());
+
+        // And cleared once the queue drops below capacity
+        client.store.pop_waiting_packet().expect("queued packet");
+        client.update_status();
+        assert!(!status.borrow().backpressure);
+    }
+
+    #[tokio::test]
+    async fn uplink_source_tag() {
+        let logger = Logger::root(slog::Discard, o!());
+        let region = mk_region(ProtoRegion::Us915);
+        let transport = RouterTransport::new(&crate::RouterSettings {
+            route_timeout: 100,
+            ..Default::default()
+        });
+        // Nothing listens on port 1, so routing fails and requeues the packet
+        let mut client = mk_client(region, "http://127.0.0.1:1", transport).await;
+        let source = UplinkSource {
+            listener: "127.0.0.1:1681".to_string(),
+            mac: "aa555a0000000000".to_string(),
+        };
+        let time = UplinkTime {
+            source: TimestampSource::Gps,
+            time: 1_364_746_877_528_002,
+        };
+        let packet = Packet::from(helium_proto::Packet {
+            payload: vec![1, 2, 3],
+            ..Default::default()
+        })
+        .with_source(source.clone())
+        .with_time(Some(time));
+
+        let (sender, mut messages) = message_channel(1);
+        sender.uplink(packet, Instant::now()).await.expect("uplink");
+        let (packet, region, received) = match messages.recv().await {
+            Some(Message::Uplink {
+                packet,
+                region,
+                received,
+            }) => (packet, region, received),
+            _ => panic!("expected uplink message"),
+        };
+        assert!(client
+            .handle_uplink(&logger, packet, region, received)
+            .await
+            .is_err());
+        let queued = client.store.pop_waiting_packet().expect("requeued packet");
+        assert_eq!(Some(&source), queued.source());
+        assert_eq!(Some(time), queued.time());
+        assert_eq!("aa555a0000000000@127.0.0.1:1681", source.to_string());
+    }
+
+    #[tokio::test]
+    async fn closed_downlinks_shut_down() {
+        let logger = Logger::root(slog::Discard, o!());
+        let region = mk_region(ProtoRegion::Us915);
+        let mut client = mk_client(region, "http://127.0.0.1:8080", Default::default()).await;
+        let (downlinks, downlinks_rx) = gateway::message_channel(1);
+        drop(downlinks_rx);
+        client.downlinks = Arc::new(downlinks);
+
+        let downlink: Packet = helium_proto::Packet::default().into();
+        client.handle_downlink(&logger, downlink).await;
+        assert!(client.downlinks_closed);
+
+        // The client fails without a shutdown trigger or stop message
+        let (_messages, messages_rx) = message_channel(1);
+        let (_trigger, shutdown) = triggered::trigger();
+        assert!(time::timeout(
+            Duration::from_secs(1),
+            client.run(messages_rx, shutdown, &logger),
         )
-        .and_then(|message| self.router.route(message.to_message()))
-        .map_ok(StateChannelMessage::from_message)
         .await
+        .expect("client shut down")
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn stale_chain_tip_pauses_routing() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("listener");
+        let addr = listener.local_addr().expect("listener address");
+        tokio::spawn(async move {
+            let mut connections = vec![];
+            while let Ok((stream, _)) = listener.accept().await {
+                connections.push(stream);
+            }
+        });
+
+        let logger = Logger::root(slog::Discard, o!());
+        let transport = RouterTransport::new(&crate::RouterSettings {
+            route_timeout: 100,
+            ..Default::default()
+        });
+        let region = mk_region(ProtoRegion::Us915);
+        let mut client = mk_client(region, &format!("http://{addr}"), transport).await;
+        let (tip_tx, tip_rx) = crate::router::chain_tip::chain_tip_channel();
+        client.chain_tip = tip_rx;
+        client.max_block_age = Some(Duration::from_secs(1800));
+
+        // A stale tip queues without routing
+        tip_tx.send_replace(Some(ChainTip::new(3600, Instant::now())));
+        let packet: Packet = helium_proto::Packet {
+            payload: vec![1, 2, 3],
+            ..Default::default()
+        }
+        .into();
+        client
+            .handle_uplink(&logger, packet, None, Instant::now())
+            .await
+            .expect("queued uplink");
+        assert!(client.degraded);
+        assert_eq!(1, client.store.waiting_packets_len());
+
+        // A fresh tip resumes routing, which times out on the silent router
+        tip_tx.send_replace(Some(ChainTip::new(60, Instant::now())));
+        assert!(client.send_waiting_packets(&logger).await.is_err());
+        assert!(!client.degraded);
+        assert_eq!(1, client.store.waiting_packets_len());
+
+        // A packet at its maximum residence is attempted while paused by
+        // both a stale tip and the route backoff
+        tip_tx.send_replace(Some(ChainTip::new(3600, Instant::now())));
+        assert!(client.send_waiting_packets(&logger).await.is_ok());
+        client.max_residence = Some(Duration::ZERO);
+        assert!(client.send_waiting_packets(&logger).await.is_err());
+        assert!(client.degraded);
+        assert_eq!(1, client.store.waiting_packets_len());
+    }
+
+    #[tokio::test]
+    async fn region_refresh_holds_routing() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("listener");
+        let addr = listener.local_addr().expect("listener address");
+        tokio::spawn(async move {
+            let mut connections = vec![];
+            while let Ok((stream, _)) = listener.accept().await {
+                connections.push(stream);
+            }
+        });
+
+        let uri = format!("http://{addr}");
+        let mut settings = mk_test_client_settings(RouterTransport::new(&crate::RouterSettings {
+            route_timeout: 100,
+            ..Default::default()
+        }));
+        settings.region_refresh_pause = Duration::from_secs(60);
+        let (us915, eu868) = (mk_region(ProtoRegion::Us915), mk_region(ProtoRegion::Eu868));
+        let mk_packet = |payload: u8| -> Packet {
+            helium_proto::Packet {
+                payload: vec![payload],
+                ..Default::default()
+            }
+            .into()
+        };
+
+        // Packets are queued without routing while region params refresh
+        let client = mk_client_with(us915, &uri, &settings).await;
+        let (messages, messages_rx) = message_channel(10);
+        let running = tokio::spawn(run_until_stopped(client, messages_rx));
+        messages.region_refresh().await;
+        for payload in [1, 2] {
+            messages
+                .uplink(mk_packet(payload), Instant::now())
+                .await
+                .expect("uplink");
+        }
+        messages.stop().await;
+        let client = running.await.expect("client task");
+        assert_eq!(2, client.store.waiting_packets_len());
+        assert_eq!(0, client.route_attempts.failures);
+        assert!(client.region_refresh_wait(Instant::now()).is_some());
+
+        // The region change ends the hold, and routing the queued packets
+        // times out on the silent router
+        let (messages, messages_rx) = message_channel(10);
+        let running = tokio::spawn(run_until_stopped(client, messages_rx));
+        messages.region_changed(eu868).await;
+        messages.stop().await;
+        let client = running.await.expect("client task");
+        assert_eq!(None, client.region_refresh_wait(Instant::now()));
+        assert_eq!(1, client.route_attempts.failures);
+
+        // A refresh that does not finish holds no longer than the pause
+        settings.region_refresh_pause = Duration::ZERO;
+        let client = mk_client_with(us915, &uri, &settings).await;
+        let (messages, messages_rx) = message_channel(10);
+        let running = tokio::spawn(run_until_stopped(client, messages_rx));
+        messages.region_refresh().await;
+        messages
+            .uplink(mk_packet(3), Instant::now())
+            .await
+            .expect("uplink");
+        messages.stop().await;
+        let client = running.await.expect("client task");
+        assert_eq!(None, client.region_refresh_wait(Instant::now()));
+        assert_eq!(1, client.route_attempts.failures);
+    }
+
+    #[test]
+    fn store_flush_cadence() {
+        let now = Instant::now();
+        // Without a cadence every change is written
+        let mut every = StoreFlush::new(None, None, now);
+        assert!(every.changed(now));
+
+        let mut flush = StoreFlush::new(Some(Duration::from_secs(30)), Some(3), now);
+        assert!(!flush.is_due(now + Duration::from_secs(60)));
+        assert!(!flush.changed(now));
+        assert!(!flush.changed(now + Duration::from_secs(1)));
+        // The change count triggers a write before the interval
+        assert!(flush.changed(now + Duration::from_secs(2)));
+        flush.flushed(now + Duration::from_secs(2));
+
+        // The interval triggers a write of fewer changes
+        assert!(!flush.changed(now + Duration::from_secs(3)));
+        assert!(!flush.is_due(now + Duration::from_secs(31)));
+        assert!(flush.is_due(now + Duration::from_secs(32)));
+        flush.flushed(now + Duration::from_secs(32));
+        assert!(!flush.is_due(now + Duration::from_secs(90)));
+    }
+
+    #[tokio::test]
+    async fn store_flushed_on_shutdown() {
+        let logger = Logger::root(slog::Discard, o!());
+        let region = mk_region(ProtoRegion::Us915);
+        let mut client = mk_client(region, "http://127.0.0.1:8080", Default::default()).await;
+        let path = std::env::temp_dir().join(format!(
+            "store_flushed_on_shutdown_{}.bin",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        client.store_path = Some(path.clone());
+        let flush_interval = Some(Duration::from_secs(3600));
+        client.store_flush = StoreFlush::new(flush_interval, Some(2), Instant::now());
+        let mk_packet = |payload: u8| -> Packet {
+            helium_proto::Packet {
+                payload: vec![payload],
+                ..Default::default()
+            }
+            .into()
+        };
+
+        // Writes once the second change reaches the change count
+        for payload in [1, 2] {
+            assert!(!path.exists());
+            client
+                .store
+                .store_waiting_packet(mk_packet(payload), Instant::now())
+                .expect("queued packet");
+            client.save_store(&logger);
+            client.writer.sync().await;
+        }
+        assert!(path.exists());
+        std::fs::remove_file(&path).expect("remove store");
+
+        // A pending change is written once more on shutdown
+        client.store.pop_waiting_packet().expect("queued packet");
+        client.save_store(&logger);
+        client.writer.sync().await;
+        assert!(!path.exists());
+        let (trigger, shutdown) = triggered::trigger();
+        let (_messages, messages_rx) = message_channel(1);
+        trigger.trigger();
+        client
+            .run(messages_rx, shutdown, &logger)
+            .await
+            .expect("client run");
+        assert_eq!(
+            client.store.to_bytes().expect("encode store"),
+            std::fs::read(&path).expect("saved store")
+        );
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(ReplayWindow::path(&path));
     }
 }
@@ -0,0 +1,84 @@
+use crate::KeyedUri;
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+/// Caps the data credits spent per router within a fixed window, so a single
+/// router can not consume the whole budget. A router's window starts with the
+/// first packet sent to it and resets once it has passed.
+#[derive(Debug)]
+pub struct DcCap {
+    cap: u64,
+    window: Duration,
+    spent: HashMap<KeyedUri, (Instant, u64)>,
+}
+
+impl DcCap {
+    pub fn new(cap: u64, window: Duration) -> Self {
+        Self {
+            cap,
+            window,
+            spent: HashMap::new(),
+        }
+    }
+
+    /// Whether sending a packet costing `dc` to the given router stays within
+    /// its cap
+    pub fn allows(&self, router: &KeyedUri, dc: u64, now: Instant) -> bool {
+        let spent = match self.spent.get(router) {
+            Some((start, spent)) if now.duration_since(*start) < self.window => *spent,
+            _ => 0,
+        };
+        spent.saturating_add(dc) <= self.cap
+    }
+
+    /// Records `dc` spent on the given router
+    pub fn record(&mut self, router: &KeyedUri, dc: u64, now: Instant) {
+        let (start, spent) = self.spent.entry(router.clone()).or_insert((now, 0));
+        if now.duration_since(*start) >= self.window {
+            *start = now;
+            *spent = 0;
+        }
+        *spent = spent.saturating_add(dc);
+    }
+
+    /// Forgets the spend of routers the given predicate rejects, used to
+    /// drop routers that were removed
+    pub fn retain<F>(&mut self, mut keep: F)
+    where
+        F: FnMut(&KeyedUri) -> bool,
+    {
+        self.spent.retain(|router, _| keep(router));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keyed_uri::mk_test_uri;
+
+    fn mk_router(index: usize) -> KeyedUri {
+        mk_test_uri(&format!("http://router{index}.local:8080"))
+    }
+
+    #[test]
+    fn cap_per_window() {
+        let routers = [mk_router(0), mk_router(1)];
+        let window = Duration::from_secs(60);
+        let mut cap = DcCap::new(2, window);
+        let now = Instant::now();
+
+        cap.record(&routers[0], 2, now);
+        assert!(!cap.allows(&routers[0], 1, now));
+        // Routers are capped on their own
+        assert!(cap.allows(&routers[1], 2, now));
+        // A new window lifts the cap
+        assert!(cap.allows(&routers[0], 2, now + window));
+
+        // Removed routers are forgotten
+        cap.retain(|router| router != &routers[0]);
+        assert!(cap.allows(&routers[0], 2, now));
+        assert!(cap.spent.is_empty());
+    }
+}
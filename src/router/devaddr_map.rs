@@ -0,0 +1,147 @@
+use crate::metrics::Counters;
+use std::collections::{BTreeMap, HashMap};
+
+/// A size bounded map of per DevAddr state.
+///
+/// Per device state would otherwise grow with every device heard. Once the
+/// map holds `capacity` entries, inserting a new DevAddr evicts the least
+/// recently used one and counts the eviction.
+#[derive(Debug)]
+pub struct DevAddrMap<V> {
+    capacity: usize,
+    entries: HashMap<u32, (V, u64)>,
+    // Last use tick to DevAddr, oldest first
+    recency: BTreeMap<u64, u32>,
+    tick: u64,
+    evictions: u64,
+}
+
+impl<V> DevAddrMap<V> {
+    /// Construct a map holding at most `capacity` entries. A capacity of zero
+    /// is treated as one.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            tick: 0,
+            evictions: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The number of entries evicted to stay within the capacity
+    pub fn evictions(&self) -> u64 {
+        self.evictions
+    }
+
+    /// The current size and evictions, for logging
+    pub fn counters(&self) -> Counters {
+        Counters(vec![
+            ("devaddrs", self.entries.len() as u64),
+            ("evictions", self.evictions),
+        ])
+    }
+
+    pub fn contains(&self, devaddr: u32) -> bool {
+        self.entries.contains_key(&devaddr)
+    }
+
+    /// Returns the state of the given DevAddr, marking it most recently used
+    pub fn get_mut(&mut self, devaddr: u32) -> Option<&mut V> {
+        let tick = self.next_tick();
+        let (value, last_used) = self.entries.get_mut(&devaddr)?;
+        self.recency.remove(last_used);
+        self.recency.insert(tick, devaddr);
+        *last_used = tick;
+        Some(value)
+    }
+
+    /// Returns the state of the given DevAddr, inserting the result of
+    /// `default` if it has none. The entry is marked most recently used.
+    pub fn get_or_insert_with<F: FnOnce() -> V>(&mut self, devaddr: u32, default: F) -> &mut V {
+        if !self.entries.contains_key(&devaddr) {
+            self.insert(devaddr, default());
+        }
+        self.get_mut(devaddr).expect("devaddr entry")
+    }
+
+    /// Inserts the state of the given DevAddr, returning its previous state.
+    /// Evicts the least recently used entry if the map is full.
+    pub fn insert(&mut self, devaddr: u32, value: V) -> Option<V> {
+        let tick = self.next_tick();
+        if let Some((previous, last_used)) = self.entries.remove(&devaddr) {
+            self.recency.remove(&last_used);
+            self.recency.insert(tick, devaddr);
+            self.entries.insert(devaddr, (value, tick));
+            return Some(previous);
+        }
+        if self.entries.len() >= self.capacity {
+            let oldest = self.recency.keys().next().copied();
+            if let Some(evicted) = oldest.and_then(|tick| self.recency.remove(&tick)) {
+                self.entries.remove(&evicted);
+                self.evictions += 1;
+            }
+        }
+        self.recency.insert(tick, devaddr);
+        self.entries.insert(devaddr, (value, tick));
+        None
+    }
+
+    pub fn remove(&mut self, devaddr: u32) -> Option<V> {
+        let (value, last_used) = self.entries.remove(&devaddr)?;
+        self.recency.remove(&last_used);
+        Some(value)
+    }
+
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let mut map = DevAddrMap::new(3);
+        map.insert(1, "a");
+        map.insert(2, "b");
+        map.insert(3, "c");
+        assert_eq!(0, map.evictions());
+
+        // Using 1 makes 2 the least recently used
+        assert_eq!(Some(&mut "a"), map.get_mut(1));
+        map.insert(4, "d");
+        assert_eq!(1, map.evictions());
+        assert!(!map.contains(2));
+        assert_eq!(3, map.len());
+
+        // Replacing an entry does not evict
+        assert_eq!(Some("c"), map.insert(3, "C"));
+        assert_eq!(1, map.evictions());
+
+        *map.get_or_insert_with(5, || "e") = "E";
+        assert_eq!(2, map.evictions());
+        assert!(!map.contains(1));
+        assert_eq!(Some("E"), map.remove(5));
+        assert_eq!(
+            Counters(vec![("devaddrs", 2), ("evictions", 2)]),
+            map.counters()
+        );
+        assert_eq!(vec![3, 4], {
+            let mut keys: Vec<u32> = map.entries.keys().copied().collect();
+            keys.sort_unstable();
+            keys
+        });
+    }
+}
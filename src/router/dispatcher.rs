@@ -1,19 +1,32 @@
 use crate::{
+    error::RegionError,
     gateway,
-    router::{self, RouterClient, Routing},
+    health::HealthSender,
+    metrics::{RateMeter, METRICS_INTERVAL},
+    region_params::{
+        FileRegionParams, GatewayRegionParams, RegionParamsSource, SharedRegionParams,
+    },
+    router::{
+        self,
+        chain_tip::{chain_tip_channel, ChainTipSender},
+        client::ClientSettings,
+        ChainTip, DcCap, ReloadFile, RouterClient, RouterSelection, Routing,
+    },
     service::{self, gateway::GatewayService},
-    sync, CacheSettings, Error, KeyedUri, Keypair, Packet, Region, RegionParams, Result, Settings,
+    sync, ChannelPlan, Error, KeyedUri, Keypair, Packet, Region, RegionInference, RegionParams,
+    Result, RetryPolicy, RouterSettings, Settings,
 };
-use exponential_backoff::Backoff;
 use futures::{
+    future,
     task::{Context, Poll},
-    TryFutureExt,
+    FutureExt, TryFutureExt,
 };
 use helium_proto::BlockchainVarV1;
+use serde::{Deserialize, Serialize};
 use slog::{debug, info, o, warn, Logger};
 use slog_scope;
 use std::{
-    collections::HashMap,
+    collections::{HashMap, VecDeque},
     pin::Pin,
     sync::Arc,
     time::{Duration, Instant},
@@ -25,6 +38,9 @@ use tokio_stream::{self, StreamExt, StreamMap};
 pub enum Message {
     Uplink {
         packet: Packet,
+        /// Optional region to route the packet with instead of the current
+        /// region
+        region: Option<Region>,
         received_time: Instant,
     },
     Config {
@@ -37,6 +53,10 @@ pub enum Message {
     Region {
         response: sync::ResponseSender<Result<Region>>,
     },
+    ChannelPlan {
+        response: sync::ResponseSender<Result<ChannelPlan>>,
+    },
+    FlushDrop,
 }
 
 #[derive(Debug)]
@@ -67,10 +87,19 @@ impl MessageSender {
         rx.recv().await?
     }
 
-    pub async fn uplink(&self, packet: Packet, received_time: Instant) -> Result {
+    /// Sends an uplink to route with the given region instead of the current
+    /// region, if one is given. Intended for testbeds where one gateway
+    /// simulates several regions.
+    pub async fn uplink(
+        &self,
+        packet: Packet,
+        region: Option<Region>,
+        received_time: Instant,
+    ) -> Result {
         self.0
             .send(Message::Uplink {
                 packet,
+                region,
                 received_time,
             })
             .map_err(|_| Error::channel())
@@ -88,23 +117,56 @@ impl MessageSender {
         let _ = self.0.send(Message::Region { response: tx }).await;
         rx.recv().await?
     }
+
+    /// The channel plan of the active region params
+    pub async fn channel_plan(&self) -> Result<ChannelPlan> {
+        let (tx, rx) = sync::response_channel();
+        let _ = self.0.send(Message::ChannelPlan { response: tx }).await;
+        rx.recv().await?
+    }
+
+    /// Drops all packets queued for all routers
+    pub async fn flush_drop(&self) -> Result {
+        self.0
+            .send(Message::FlushDrop)
+            .map_err(|_| Error::channel())
+            .await
+    }
 }
 
 pub struct Dispatcher {
     keypair: Arc<Keypair>,
     region: Region,
     messages: MessageReceiver,
+    overflow_grown: u64,
     downlinks: gateway::MessageSender,
+    health: HealthSender,
     seed_gateways: Vec<KeyedUri>,
     routing_height: u64,
     region_height: u64,
-    cache_settings: CacheSettings,
+    client_settings: ClientSettings,
+    chain_tip: ChainTipSender,
+    router_settings: RouterSettings,
     gateway_retry: u32,
+    uplink_rate: Option<RateMeter>,
+    region_inference: Option<RegionInference>,
+    selection: Box<dyn RouterSelection>,
+    reload_file: Option<ReloadFile>,
+    dc_cap: Option<DcCap>,
     routers: HashMap<RouterKey, RouterEntry>,
     default_routers: Option<Vec<KeyedUri>>,
+    region_params_file: Option<SharedRegionParams<FileRegionParams>>,
+    // Region params of the connected gateway service, if any
+    region_params_gateway: Option<SharedRegionParams<GatewayRegionParams>>,
+    region_params_fetches: usize,
+    region_params_fetch: Option<JoinHandle<Result<RegionParams>>>,
+    region_params: Option<RegionParams>,
+    // Set while router clients hold routing for new region params
+    region_refresh: bool,
+    unknown_region: UnknownRegionUplinks,
 }
 
-#[derive(PartialEq, Eq, Hash)]
+#[derive(Clone, PartialEq, Eq, Hash)]
 struct RouterKey {
     oui: u32,
     uri: KeyedUri,
@@ -114,15 +176,22 @@ struct RouterKey {
 struct RouterEntry {
     routing: Routing,
     dispatch: router::client::MessageSender,
+    status: router::client::RouterStatusReceiver,
     join_handle: JoinHandle<Result>,
 }
 
-const GATEWAY_BACKOFF_RETRIES: u32 = 10;
-const GATEWAY_BACKOFF_MIN_WAIT: Duration = Duration::from_secs(5);
-const GATEWAY_BACKOFF_MAX_WAIT: Duration = Duration::from_secs(1800); // 30 minutes
-
 const GATEWAY_CHECK_INTERVAL: Duration = Duration::from_secs(900); // 15 minutes
 const GATEWAY_MAX_BLOCK_AGE: Duration = Duration::from_secs(1800); // 30 minutes
+const GATEWAY_MIN_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// The interval to check the gateway at. With a maximum block age the chain
+/// tip is refreshed every quarter of it, so a tip that only aged since the
+/// last check does not pause routing.
+fn gateway_check_interval(max_block_age: Option<Duration>) -> Duration {
+    max_block_age.map_or(GATEWAY_CHECK_INTERVAL, |max_block_age| {
+        (max_block_age / 4).clamp(GATEWAY_MIN_CHECK_INTERVAL, GATEWAY_CHECK_INTERVAL)
+    })
+}
 
 #[derive(Debug, Hash, PartialEq, Eq, Clone)]
 enum GatewayStream {
@@ -138,29 +207,132 @@ impl Dispatcher {
     pub fn new(
         messages: MessageReceiver,
         downlinks: gateway::MessageSender,
+        health: HealthSender,
         settings: &Settings,
     ) -> Result<Self> {
         let seed_gateways = settings.gateways.clone();
         let routers = HashMap::with_capacity(5);
         let default_routers = settings.routers.clone();
-        let cache_settings = settings.cache.clone();
+        let router_settings = settings.router.clone();
+        let uplink_rate = router_settings.uplink_rate_alert.map(RateMeter::new);
+        let selection = router_settings.selection.selection(&router_settings);
+        let reload_file = router_settings.reload_file.as_deref().map(ReloadFile::new);
+        let dc_cap = router_settings
+            .dc_cap
+            .map(|cap| DcCap::new(cap, router_settings.dc_cap_window()));
+        let unknown_region = UnknownRegionUplinks::new(
+            router_settings.unknown_region,
+            router_settings.unknown_region_hold,
+        );
+        let (chain_tip, chain_tip_rx) = chain_tip_channel();
+        let client_settings = ClientSettings::new(settings, chain_tip_rx);
+        let region_params_file = settings.region_params.as_ref().map(|path| {
+            SharedRegionParams::new(FileRegionParams::new(path), settings.region_params_fetches)
+        });
         Ok(Self {
             keypair: settings.keypair.clone(),
             region: settings.region,
-            messages,
+            messages: messages.with_overflow(settings.channel_overflow),
+            overflow_grown: 0,
             downlinks,
+            health,
             seed_gateways,
             routers,
             routing_height: 0,
             region_height: 0,
             default_routers,
-            cache_settings,
+            client_settings,
+            chain_tip,
+            router_settings,
             gateway_retry: 0,
+            uplink_rate,
+            region_inference: settings.infer_region.map(RegionInference::new),
+            selection,
+            reload_file,
+            dc_cap,
+            region_params_file,
+            region_params_gateway: None,
+            region_params_fetches: settings.region_params_fetches,
+            region_params_fetch: None,
+            region_params: None,
+            region_refresh: false,
+            unknown_region,
         })
     }
 
     pub async fn run(&mut self, shutdown: triggered::Listener, logger: &Logger) -> Result {
         let logger = logger.new(o!("module" => "dispatcher"));
+        self.reload_routers(&logger).await;
+        let result = self.run_routing(shutdown, &logger).await;
+        self.stop_routers(&logger).await;
+        result
+    }
+
+    /// Routes uplinks to the given router alone until shutdown, without a
+    /// gateway service to learn routing or region params from. Uplinks are
+    /// routed with the configured region and downlinks go to the gateway
+    /// channel as usual. Queued packets are not persisted or captured. Used
+    /// by the router self-test.
+    pub async fn run_selftest(
+        &mut self,
+        router: KeyedUri,
+        shutdown: triggered::Listener,
+        logger: &Logger,
+    ) -> Result {
+        let logger = logger.new(o!("module" => "dispatcher", "selftest" => true));
+        self.client_settings.cache.store = None;
+        self.client_settings.capture = None;
+        self.client_settings.send_pacing = None;
+        self.region_inference = None;
+        self.reload_file = None;
+        self.dc_cap = None;
+        self.unknown_region = UnknownRegionUplinks::new(UnknownRegionPolicy::Route, 0);
+        self.default_routers = Some(vec![router.clone()]);
+        let routing = Routing::new(0, vec![router.clone()]);
+        let router_entry = self
+            .start_router(shutdown.clone(), routing, router.clone())
+            .await?;
+        self.routers.insert(
+            RouterKey {
+                oui: 0,
+                uri: router,
+            },
+            router_entry,
+        );
+        loop {
+            tokio::select! {
+                _ = shutdown.clone() => break,
+                message = self.messages.recv() => match message {
+                    Some(message) => self.handle_message(message, None, &logger).await,
+                    None => break,
+                }
+            }
+        }
+        self.stop_routers(&logger).await;
+        Ok(())
+    }
+
+    /// Stops all router clients and waits for them to finish, so they save
+    /// their queues before the runtime goes away
+    async fn stop_routers(&mut self, logger: &Logger) {
+        let routers: Vec<RouterEntry> = self.routers.drain().map(|(_, entry)| entry).collect();
+        if routers.is_empty() {
+            return;
+        }
+        info!(logger, "stopping routers"; "routers" => routers.len());
+        for router_entry in &routers {
+            router_entry.dispatch.stop().await;
+        }
+        for result in future::join_all(routers).await {
+            match result {
+                Ok(Ok(())) => (),
+                Ok(Err(err)) => warn!(logger, "router client error: {err:?}"),
+                Err(err) => warn!(logger, "router client task error: {err:?}"),
+            }
+        }
+    }
+
+    async fn run_routing(&mut self, shutdown: triggered::Listener, logger: &Logger) -> Result {
         info!(logger, "starting"; 
             "region" => self.region);
 
@@ -172,11 +344,22 @@ impl Dispatcher {
             }
         }
 
-        let gateway_backoff = Backoff::new(
-            GATEWAY_BACKOFF_RETRIES,
-            GATEWAY_BACKOFF_MIN_WAIT,
-            GATEWAY_BACKOFF_MAX_WAIT,
-        );
+        // Static region params do not need a gateway service
+        if let Some(source) = self.region_params_file.as_mut() {
+            match source.region_params(&self.region).await {
+                Ok(region_params) => {
+                    info!(logger, "using static region params";
+                        "region" => region_params.region);
+                    self.region = region_params.region;
+                    self.region_params = Some(region_params.clone());
+                    self.downlinks.region_params_changed(region_params).await;
+                    self.health.set_region(true);
+                }
+                Err(err) => warn!(logger, "failed to load static region params: {err:?}"),
+            }
+        }
+
+        let gateway_backoff = self.router_settings.reconnect_retry;
         loop {
             if shutdown.is_triggered() {
                 // Prevent unneeded seed reselection
@@ -194,13 +377,24 @@ impl Dispatcher {
                         return Ok(())
                     },
                 // Try to select a random validator from the seed and fetch the needed streams
-                gateway = Self::select_gateway(seed_gateway, &shutdown, &logger)
-                    .and_then(|service | self.setup_gateway_streams(service, &logger))
+                gateway = Self::select_gateway(seed_gateway, &shutdown, logger)
+                    .and_then(|service | self.setup_gateway_streams(service, logger))
                      => match gateway {
                         Ok(Some((service, gateway_streams, default_region_params))) => {
+                            self.region_params = Some(default_region_params.clone());
                             self.downlinks.region_params_changed(default_region_params).await;
-                            self.run_with_gateway(service, gateway_streams,  shutdown.clone(), &logger)
-                                .await?;
+                            self.end_region_refresh().await;
+                            self.health.set_region(true);
+                            self.health.set_connected(true);
+                            self.release_held_uplinks(logger).await;
+                            let result = self.run_with_gateway(service, gateway_streams,  shutdown.clone(), logger)
+                                .await;
+                            self.health.set_connected(false);
+                            self.region_params_gateway = None;
+                            if let Some(fetch) = self.region_params_fetch.take() {
+                                fetch.abort();
+                            }
+                            result?;
                             },
                         Ok(None) =>
                             return Ok(()),
@@ -208,7 +402,7 @@ impl Dispatcher {
                     }
             }
 
-            self.prepare_gateway_change(&gateway_backoff, shutdown.clone(), &logger)
+            self.prepare_gateway_change(&gateway_backoff, shutdown.clone(), logger)
                 .await;
         }
     }
@@ -240,9 +434,18 @@ impl Dispatcher {
         let mut gateway = gateway.unwrap();
         let mut routing_gateway = gateway.clone();
         let routing = routing_gateway.routing(self.routing_height);
-        let default_region_params = gateway
-            .region_params_for(&self.region, self.keypair.clone())
-            .await?;
+        let default_region_params = match self.region_params_file.as_mut() {
+            Some(source) => source.region_params(&self.region).await?,
+            None => {
+                let source = SharedRegionParams::new(
+                    GatewayRegionParams::new(gateway.clone(), self.keypair.clone()),
+                    self.region_params_fetches,
+                );
+                let region_params = source.fetch(&self.region).await?;
+                self.region_params_gateway = Some(source);
+                region_params
+            }
+        };
         let region_params = gateway.region_params(self.keypair.clone());
         match tokio::try_join!(routing, region_params) {
             Ok((routing, region_params)) => {
@@ -273,7 +476,13 @@ impl Dispatcher {
             "uri" => gateway.uri.uri.to_string());
 
         // Initialize liveness check for gateway
-        let mut gateway_check = time::interval(GATEWAY_CHECK_INTERVAL);
+        let mut gateway_check =
+            time::interval(gateway_check_interval(self.router_settings.max_block_age()));
+        let mut metrics_timer = time::interval(METRICS_INTERVAL);
+        let mut empty_messages = self
+            .router_settings
+            .empty_message_limit
+            .map(|limit| EmptyMessages::new(limit, self.router_settings.empty_message_window()));
         loop {
             tokio::select! {
                 _ = shutdown.clone() => {
@@ -281,9 +490,22 @@ impl Dispatcher {
                     return Ok(())
                 },
                 gateway_message = streams.next() => match gateway_message {
-                    Some((gateway_stream, Ok(gateway_message))) => match gateway_stream {
-                        GatewayStream::Routing => self.handle_routing_update(&gateway_message, &shutdown, logger).await,
-                        GatewayStream::RegionParams => self.handle_region_params_update(&gateway_message, logger).await,
+                    Some((_, Ok(gateway_message)))
+                        if gateway_message.msg.is_none() && empty_messages.is_some() => {
+                        if empty_messages.as_mut().map_or(false, |empty| empty.record(Instant::now())) {
+                            warn!(logger, "reconnecting after repeated empty gateway messages");
+                            return Ok(())
+                        }
+                        debug!(logger, "ignoring empty gateway message");
+                    },
+                    Some((gateway_stream, Ok(gateway_message))) => {
+                        if let Some(empty) = empty_messages.as_mut() {
+                            empty.reset();
+                        }
+                        match gateway_stream {
+                            GatewayStream::Routing => self.handle_routing_update(&gateway_message, &shutdown, logger).await,
+                            GatewayStream::RegionParams => self.handle_region_params_update(&gateway_message, logger).await,
+                        }
                     },
                     Some((gateway_stream, Err(err))) =>  {
                         match gateway_stream {
@@ -297,6 +519,14 @@ impl Dispatcher {
                         return Ok(());
                 }
                 },
+                (router_key, result) = router_finished(&mut self.routers) => {
+                    self.handle_router_finished(router_key, result, logger)?
+                },
+                fetched = region_params_fetched(&mut self.region_params_fetch) => match fetched {
+                    Ok(region_params) => self.update_region_params(region_params, logger).await,
+                    Err(err) => warn!(logger, "region params refresh failed: {err:?}"),
+                },
+                _ = metrics_timer.tick() => self.log_metrics(logger),
                 _ = gateway_check.tick() => match self.check_gateway(&mut gateway, logger).await {
                     Ok(()) => {
                         self.gateway_retry = 0
@@ -317,8 +547,17 @@ impl Dispatcher {
         }
     }
 
+    /// Logs the current buffering of the auto-tuned message channel
+    fn log_metrics(&self, logger: &Logger) {
+        if let Some(stats) = self.messages.overflow_stats() {
+            debug!(logger, "message channel"; stats.counters());
+        }
+    }
+
     async fn check_gateway(&mut self, gateway: &mut GatewayService, logger: &Logger) -> Result {
         let (_, block_age) = gateway.height().await?;
+        self.chain_tip
+            .send_replace(Some(ChainTip::new(block_age, Instant::now())));
         info!(logger, "checking gateway"; 
             "pubkey" => gateway.uri.pubkey.to_string(),
             "block_age" => block_age);
@@ -333,7 +572,7 @@ impl Dispatcher {
 
     async fn prepare_gateway_change(
         &mut self,
-        backoff: &Backoff,
+        backoff: &RetryPolicy,
         shutdown: triggered::Listener,
         logger: &Logger,
     ) {
@@ -348,9 +587,7 @@ impl Dispatcher {
 
         // Use backof to sleep exponentially longer
         self.gateway_retry += 1;
-        let sleep = backoff
-            .next(self.gateway_retry)
-            .unwrap_or(GATEWAY_BACKOFF_MAX_WAIT);
+        let sleep = backoff.delay_or_max(self.gateway_retry);
 
         // Select over either shutdown or sleep, and handle messages that don't
         // require a gateway
@@ -366,16 +603,30 @@ impl Dispatcher {
     }
 
     async fn handle_message(
-        &self,
+        &mut self,
         message: Message,
         gateway: Option<&mut GatewayService>,
         logger: &Logger,
     ) {
+        if let Some(stats) = self.messages.overflow_stats() {
+            if stats.grown > self.overflow_grown {
+                self.overflow_grown = stats.grown;
+                info!(logger, "message channel buffering grown";
+                    "buffered" => stats.buffered,
+                    "limit" => stats.limit,
+                    "max" => stats.max,
+                    "grown" => stats.grown);
+            }
+        }
         match message {
             Message::Uplink {
                 packet,
+                region,
                 received_time,
-            } => self.handle_uplink(&packet, received_time, logger).await,
+            } => {
+                self.handle_uplink(packet, region, received_time, logger)
+                    .await
+            }
             Message::Config { keys, response } => {
                 let reply = if let Some(gateway) = gateway {
                     gateway.config(keys).await
@@ -402,28 +653,230 @@ impl Dispatcher {
                 response.send(reply, logger)
             }
             Message::Region { response } => response.send(Ok(self.region), logger),
+            Message::ChannelPlan { response } => {
+                let reply = self
+                    .region_params
+                    .as_ref()
+                    .map(RegionParams::channel_plan)
+                    .ok_or_else(RegionError::no_region_params);
+                response.send(reply, logger)
+            }
+            Message::FlushDrop => {
+                info!(logger, "flushing queued packets"; "routers" => self.routers.len());
+                for router_entry in self.routers.values() {
+                    router_entry.dispatch.flush_drop().await;
+                }
+            }
+        }
+    }
+
+    /// Handles an uplink with an optional region override. Overridden uplinks
+    /// are routed with their own region and bypass region inference and the
+    /// unknown region policy.
+    async fn handle_uplink(
+        &mut self,
+        packet: Packet,
+        region: Option<Region>,
+        received: Instant,
+        logger: &Logger,
+    ) {
+        if let Some(alert) = self
+            .uplink_rate
+            .as_mut()
+            .and_then(|meter| meter.record(received))
+        {
+            warn!(logger, "uplink rate above threshold";
+                "rate" => alert.rate,
+                "threshold" => alert.threshold);
+        }
+        if region.is_some() {
+            return self.route_uplink(&packet, region, received, logger).await;
+        }
+        if let Some(inference) = self.region_inference.as_mut() {
+            if let Some(region) = inference.observe(packet.frequency) {
+                info!(logger, "inferred region from uplinks";
+                    "region" => region,
+                    "configured" => self.region);
+                let region_changed = self.region != region;
+                self.region = region;
+                self.region_inference = None;
+                // Static region params are not refreshed
+                let stale_params = matches!(
+                    &self.region_params,
+                    Some(params) if params.region != region
+                );
+                if stale_params && self.region_params_file.is_none() {
+                    // Routers learn the region when the refresh ends
+                    self.begin_region_refresh(logger).await;
+                } else if region_changed {
+                    self.notify_region_changed().await;
+                }
+            }
+        }
+        if self.region_params.is_none() {
+            match self.unknown_region.admit(packet, received) {
+                Some((packet, received)) => {
+                    self.route_uplink(&packet, None, received, logger).await
+                }
+                None => debug!(logger, "region unknown, not routing uplink";
+                    "policy" => format!("{:?}", self.unknown_region.policy)),
+            }
+            return;
+        }
+        self.route_uplink(&packet, None, received, logger).await
+    }
+
+    /// Holds routing in all router clients while region params for a changed
+    /// region are refreshed, so queued packets are not routed with region
+    /// params that no longer apply
+    async fn begin_region_refresh(&mut self, logger: &Logger) {
+        info!(logger, "refreshing region params, holding routing";
+            "region" => self.region);
+        self.region_refresh = true;
+        for router_entry in self.routers.values() {
+            router_entry.dispatch.region_refresh().await;
+        }
+        self.fetch_region_params(logger);
+    }
+
+    /// Starts fetching the region params of the current region from the
+    /// connected gateway service, replacing a fetch in flight. Without a
+    /// connection the params are fetched when the next one is set up.
+    fn fetch_region_params(&mut self, logger: &Logger) {
+        let source = match self.region_params_gateway.clone() {
+            Some(source) => source,
+            None => {
+                debug!(
+                    logger,
+                    "no gateway connected, fetching region params on connect"
+                );
+                return;
+            }
+        };
+        if let Some(fetch) = self.region_params_fetch.take() {
+            fetch.abort();
         }
+        let region = self.region;
+        self.region_params_fetch = Some(tokio::spawn(async move { source.fetch(&region).await }));
     }
 
-    async fn handle_uplink(&self, packet: &Packet, received: Instant, logger: &Logger) {
-        let mut handled = false;
+    /// Resumes routing in all router clients if they are held for a region
+    /// params refresh
+    async fn end_region_refresh(&mut self) {
+        if !std::mem::take(&mut self.region_refresh) {
+            return;
+        }
+        self.notify_region_changed().await;
+    }
+
+    /// Tells all router clients the current region, which also ends any
+    /// region params refresh they hold routing for
+    async fn notify_region_changed(&self) {
         for router_entry in self.routers.values() {
-            if router_entry.routing.matches_routing_info(packet.routing()) {
-                match router_entry.dispatch.uplink(packet.clone(), received).await {
-                    Ok(()) => (),
-                    Err(err) => warn!(logger, "ignoring router dispatch error: {err:?}"),
+            router_entry.dispatch.region_changed(self.region).await;
+        }
+    }
+
+    /// Routes uplinks held while the region was unknown, once it is known
+    async fn release_held_uplinks(&mut self, logger: &Logger) {
+        let held = self.unknown_region.release();
+        if held.is_empty() {
+            return;
+        }
+        info!(logger, "routing uplinks held for region"; "uplinks" => held.len());
+        for (packet, received) in held {
+            self.route_uplink(&packet, None, received, logger).await;
+        }
+    }
+
+    /// Applies the router reload file if it changed. Weights go to the router
+    /// selection and auth tokens to all router services.
+    async fn reload_routers(&mut self, logger: &Logger) {
+        let reloaded = match self.reload_file.as_mut() {
+            Some(reload_file) => reload_file.poll(Instant::now()).await,
+            None => return,
+        };
+        match reloaded {
+            Some(Ok(reload)) => {
+                if let Some(weights) = &reload.weights {
+                    self.selection.reload_weights(weights);
                 }
-                handled = true;
+                if let Some(auth_tokens) = &reload.auth_tokens {
+                    self.client_settings
+                        .transport
+                        .auth_tokens()
+                        .reload(auth_tokens);
+                }
+                info!(logger, "reloaded router settings";
+                    "weights" => reload.weights.as_ref().map(Vec::len),
+                    "auth_tokens" => reload.auth_tokens.as_ref().map(Vec::len));
             }
+            Some(Err(err)) => warn!(logger, "ignoring failed router settings reload: {err:?}"),
+            None => (),
         }
-        if !handled {
+    }
+
+    /// Routes an uplink to the matching routers, with the given region
+    /// overriding the router region if set
+    async fn route_uplink(
+        &mut self,
+        packet: &Packet,
+        region: Option<Region>,
+        received: Instant,
+        logger: &Logger,
+    ) {
+        self.reload_routers(logger).await;
+        let mut candidates: Vec<&RouterKey> = self
+            .routers
+            .iter()
+            .filter(|(_, router_entry)| router_entry.routing.matches_routing_info(packet.routing()))
+            .map(|(router_key, _)| router_key)
+            .collect();
+        candidates.sort_by_key(|router_key| (router_key.oui, router_key.uri.uri.to_string()));
+        if candidates.is_empty() {
             if let Some(default_routers) = &self.default_routers {
-                for (router_key, router_entry) in &self.routers {
-                    if default_routers.contains(&router_key.uri) {
-                        debug!(logger, "sending to default router");
-                        let _ = router_entry.dispatch.uplink(packet.clone(), received).await;
+                debug!(logger, "sending to default router");
+                candidates = default_routers
+                    .iter()
+                    .flat_map(|uri| self.routers.keys().filter(move |key| &key.uri == uri))
+                    .collect();
+            }
+        }
+        let (dc, now) = (packet.dc_payload(), Instant::now());
+        if let Some(dc_cap) = &self.dc_cap {
+            let matched = candidates.len();
+            candidates.retain(|router_key| dc_cap.allows(&router_key.uri, dc, now));
+            if candidates.len() < matched {
+                debug!(logger, "skipping routers at dc cap";
+                    "skipped" => matched - candidates.len());
+            }
+        }
+        // Routers whose clients fail to route or drop queued packets count
+        // as unhealthy for selection
+        for router_key in &candidates {
+            let healthy = self.routers[*router_key].status.borrow().is_healthy();
+            self.selection.report(&router_key.uri, healthy);
+        }
+        let uris: Vec<&KeyedUri> = candidates
+            .iter()
+            .map(|router_key| &router_key.uri)
+            .collect();
+        for index in self.selection.select(&uris, packet) {
+            let router_key = candidates[index];
+            let result = self.routers[router_key]
+                .dispatch
+                .uplink_in(packet.clone(), region, received)
+                .await;
+            match result {
+                Ok(()) => {
+                    if let Some(dc_cap) = self.dc_cap.as_mut() {
+                        dc_cap.record(&router_key.uri, dc, now);
                     }
                 }
+                Err(err) => {
+                    self.selection.report(&router_key.uri, false);
+                    warn!(logger, "ignoring router dispatch error: {err:?}")
+                }
             }
         }
     }
@@ -433,6 +886,13 @@ impl Dispatcher {
         response: &R,
         logger: &Logger,
     ) {
+        if self.region_params_file.is_some() {
+            debug!(
+                logger,
+                "ignoring region_params update with static region params"
+            );
+            return;
+        }
         let update_height = response.height();
         let current_height = self.region_height;
         if update_height <= self.region_height {
@@ -445,20 +905,8 @@ impl Dispatcher {
         match response.region_params() {
             Ok(region_params) => {
                 self.region_height = update_height;
-                self.region = region_params.region;
-                info!(
-                    logger, "updated region";
-                    "region" => self.region,
-                    "height" => update_height
-                );
-                // Tell downlink handler
-                self.downlinks
-                    .region_params_changed(region_params.clone())
-                    .await;
-                // Tell routers about it
-                for router_entry in self.routers.values() {
-                    router_entry.dispatch.region_changed(self.region).await;
-                }
+                debug!(logger, "region params update"; "height" => update_height);
+                self.update_region_params(region_params, logger).await;
             }
             Err(err) => {
                 warn!(logger, "error decoding region: {err:?}");
@@ -466,6 +914,25 @@ impl Dispatcher {
         }
     }
 
+    /// Switches to the given region params, ending any region params refresh
+    async fn update_region_params(&mut self, region_params: RegionParams, logger: &Logger) {
+        let region_changed = self.region != region_params.region;
+        self.region = region_params.region;
+        self.region_params = Some(region_params.clone());
+        self.health.set_region(true);
+        info!(logger, "updated region";
+            "region" => self.region);
+        // Tell downlink handler
+        self.downlinks.region_params_changed(region_params).await;
+        // Tell routers about an actual change, which also ends a refresh
+        if region_changed {
+            self.region_refresh = false;
+            self.notify_region_changed().await;
+        }
+        self.end_region_refresh().await;
+        self.release_held_uplinks(logger).await;
+    }
+
     async fn handle_routing_update<R: service::gateway::Response>(
         &mut self,
         response: &R,
@@ -547,11 +1014,51 @@ impl Dispatcher {
             }
             true
         });
+        self.prune_dc_cap();
         for removable in removables {
             removable.stop().await;
         }
     }
 
+    /// Forgets the dc spent on routers that are no longer running, so the
+    /// cap does not track routers that were removed
+    fn prune_dc_cap(&mut self) {
+        if let Some(dc_cap) = self.dc_cap.as_mut() {
+            let routers = &self.routers;
+            dc_cap.retain(|uri| routers.keys().any(|router_key| &router_key.uri == uri));
+        }
+    }
+
+    /// Removes a router client that finished on its own. A failed client
+    /// fails the dispatcher, which stops the other clients and shuts down the
+    /// server.
+    fn handle_router_finished(
+        &mut self,
+        router_key: RouterKey,
+        result: RouterResult,
+        logger: &Logger,
+    ) -> Result {
+        self.routers.remove(&router_key);
+        self.prune_dc_cap();
+        let logger = logger.new(o!(
+            "oui" => router_key.oui,
+            "uri" => router_key.uri.uri.to_string()));
+        match result {
+            Ok(Ok(())) => {
+                info!(logger, "router stopped");
+                Ok(())
+            }
+            Ok(Err(err)) => {
+                warn!(logger, "router failed: {err:?}");
+                Err(err)
+            }
+            Err(err) => {
+                warn!(logger, "router task failed: {err:?}");
+                Ok(())
+            }
+        }
+    }
+
     async fn start_router(
         &self,
         shutdown: triggered::Listener,
@@ -568,21 +1075,151 @@ impl Dispatcher {
             uri,
             self.downlinks.clone(),
             self.keypair.clone(),
-            self.cache_settings.clone(),
+            &self.client_settings,
         )
         .await?;
+        let status = client.status();
         let join_handle =
             tokio::spawn(async move { client.run(client_rx, shutdown, &logger).await });
         Ok(RouterEntry {
             routing,
             dispatch: client_tx,
+            status,
             join_handle,
         })
     }
 }
 
+/// How uplinks are handled that arrive before region params are known
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum UnknownRegionPolicy {
+    /// Route with the configured region
+    #[default]
+    Route,
+    /// Hold uplinks until region params are known
+    Hold,
+    /// Drop uplinks
+    Drop,
+}
+
+/// Applies the unknown region policy to uplinks that arrive before region
+/// params are known
+#[derive(Debug)]
+struct UnknownRegionUplinks {
+    policy: UnknownRegionPolicy,
+    max_held: usize,
+    held: VecDeque<(Packet, Instant)>,
+}
+
+impl UnknownRegionUplinks {
+    fn new(policy: UnknownRegionPolicy, max_held: usize) -> Self {
+        Self {
+            policy,
+            max_held,
+            held: VecDeque::new(),
+        }
+    }
+
+    /// Returns the uplink if it is to be routed now. Held uplinks beyond the
+    /// maximum drop the oldest held one.
+    fn admit(&mut self, packet: Packet, received: Instant) -> Option<(Packet, Instant)> {
+        match self.policy {
+            UnknownRegionPolicy::Route => Some((packet, received)),
+            UnknownRegionPolicy::Drop => None,
+            UnknownRegionPolicy::Hold => {
+                if self.max_held > 0 {
+                    if self.held.len() >= self.max_held {
+                        self.held.pop_front();
+                    }
+                    self.held.push_back((packet, received));
+                }
+                None
+            }
+        }
+    }
+
+    /// Removes and returns all held uplinks, oldest first
+    fn release(&mut self) -> Vec<(Packet, Instant)> {
+        self.held.drain(..).collect()
+    }
+}
+
+/// Tracks consecutive empty gateway stream messages. A burst of them within a
+/// short window indicates a broken stream rather than an idle one.
+#[derive(Debug)]
+struct EmptyMessages {
+    limit: u32,
+    window: Duration,
+    received: VecDeque<Instant>,
+}
+
+impl EmptyMessages {
+    fn new(limit: u32, window: Duration) -> Self {
+        Self {
+            limit,
+            window,
+            received: VecDeque::new(),
+        }
+    }
+
+    /// Records an empty message at the given time. Returns whether the limit
+    /// of consecutive empty messages within the window was reached, which
+    /// also starts a new count.
+    fn record(&mut self, now: Instant) -> bool {
+        while let Some(first) = self.received.front() {
+            if now.saturating_duration_since(*first) <= self.window {
+                break;
+            }
+            self.received.pop_front();
+        }
+        self.received.push_back(now);
+        if self.received.len() >= self.limit as usize {
+            self.received.clear();
+            return true;
+        }
+        false
+    }
+
+    /// Resets the count on a non-empty message
+    fn reset(&mut self) {
+        self.received.clear();
+    }
+}
+
+/// Returns the time left to wait before the next connect attempt is allowed,
+/// if any, given the time of the last attempt.
+type RouterResult = std::result::Result<Result, tokio::task::JoinError>;
+
+/// Waits for the first of the given router clients to finish. Never completes
+/// without router clients.
+async fn router_finished(
+    routers: &mut HashMap<RouterKey, RouterEntry>,
+) -> (RouterKey, RouterResult) {
+    if routers.is_empty() {
+        return future::pending().await;
+    }
+    let finished = routers.iter_mut().map(|(router_key, router_entry)| {
+        async move { (router_key.clone(), router_entry.await) }.boxed()
+    });
+    future::select_all(finished).await.0
+}
+
+/// Waits for the given region params fetch to finish, clearing it. Never
+/// completes without a fetch.
+async fn region_params_fetched(
+    fetch: &mut Option<JoinHandle<Result<RegionParams>>>,
+) -> Result<RegionParams> {
+    let result = match fetch.as_mut() {
+        Some(fetch) => fetch.await,
+        None => return future::pending().await,
+    };
+    *fetch = None;
+    result.map_err(|err| Error::custom(format!("region params fetch: {err}")))?
+}
+
 impl std::future::Future for RouterEntry {
-    type Output = std::result::Result<Result, tokio::task::JoinError>;
+    type Output = RouterResult;
 
     fn poll(
         mut self: Pin<&mut Self>,
@@ -591,3 +1228,381 @@ impl std::future::Future for RouterEntry {
         Pin::new(&mut self.join_handle).poll(cxt)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{health, settings::mk_test_settings};
+    use helium_proto::Region as ProtoRegion;
+
+    /// A dispatcher with a stand-in router client as its only, default,
+    /// router. Returns the dispatcher and the messages sent to the router
+    /// client.
+    fn mk_dispatcher(settings: &mut Settings) -> (Dispatcher, router::client::MessageReceiver) {
+        let logger = Logger::root(slog::Discard, o!());
+        let address = helium_proto::RoutingAddress {
+            pub_key: settings.keypair.public_key().to_vec(),
+            uri: b"http://127.0.0.1:1".to_vec(),
+        };
+        let uri = KeyedUri::try_from(address.clone()).expect("router uri");
+        settings.routers = Some(vec![uri.clone()]);
+        let (_, messages) = message_channel(10);
+        let (downlinks, _) = gateway::message_channel(10);
+        let (health, _) = health::health_channel();
+        let mut dispatcher =
+            Dispatcher::new(messages, downlinks, health, settings).expect("dispatcher");
+        let routing = Routing::from_proto(
+            &logger,
+            &helium_proto::Routing {
+                oui: 1,
+                addresses: vec![address],
+                ..Default::default()
+            },
+        )
+        .expect("routing");
+        let (dispatch, client_messages) = router::client::message_channel(10);
+        dispatcher.routers.insert(
+            RouterKey { oui: 1, uri },
+            RouterEntry {
+                routing,
+                dispatch,
+                status: router::client::router_status_channel().1,
+                join_handle: tokio::spawn(async { Ok(()) }),
+            },
+        );
+        (dispatcher, client_messages)
+    }
+
+    fn mk_uplink(payload: u8) -> Packet {
+        helium_proto::Packet {
+            payload: vec![payload],
+            ..Default::default()
+        }
+        .into()
+    }
+
+    fn payloads(uplinks: &[(Packet, Instant)]) -> Vec<u8> {
+        uplinks
+            .iter()
+            .map(|(packet, _)| packet.payload[0])
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn reload_file_applied() {
+        let logger = Logger::root(slog::Discard, o!());
+        let path = std::env::temp_dir().join(format!(
+            "gateway-rs-dispatcher-reload-{}.toml",
+            std::process::id()
+        ));
+        let mut settings = mk_test_settings();
+        settings.router.reload_file = Some(path.clone());
+        let (mut dispatcher, _client_messages) = mk_dispatcher(&mut settings);
+        let auth_tokens = dispatcher.client_settings.transport.auth_tokens().clone();
+        assert_eq!("{}", format!("{auth_tokens:?}"));
+
+        // Reloaded tokens reach the transport shared by all router services
+        let pubkey = settings.keypair.public_key().to_string();
+        std::fs::write(
+            &path,
+            format!("[[auth_tokens]]\npubkey = \"{pubkey}\"\ntoken = \"rotated\"\n"),
+        )
+        .expect("write reload file");
+        dispatcher.reload_routers(&logger).await;
+        assert_eq!(format!("{{\"{pubkey}\"}}"), format!("{auth_tokens:?}"));
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn gateway_check_follows_max_block_age() {
+        assert_eq!(GATEWAY_CHECK_INTERVAL, gateway_check_interval(None));
+        // The tip is refreshed well before it could age past the maximum
+        assert_eq!(
+            Duration::from_secs(150),
+            gateway_check_interval(Some(Duration::from_secs(600)))
+        );
+        assert_eq!(
+            GATEWAY_MIN_CHECK_INTERVAL,
+            gateway_check_interval(Some(Duration::from_secs(60)))
+        );
+        assert_eq!(
+            GATEWAY_CHECK_INTERVAL,
+            gateway_check_interval(Some(Duration::from_secs(7200)))
+        );
+    }
+
+    #[test]
+    fn unknown_region_policies() {
+        let now = Instant::now();
+
+        // Route passes uplinks straight through
+        let mut route = UnknownRegionUplinks::new(UnknownRegionPolicy::Route, 2);
+        let routed = route.admit(mk_uplink(1), now).expect("routed uplink");
+        assert_eq!(vec![1], payloads(&[routed]));
+        assert!(route.release().is_empty());
+
+        // Drop routes nothing, now or later
+        let mut drop = UnknownRegionUplinks::new(UnknownRegionPolicy::Drop, 2);
+        assert!(drop.admit(mk_uplink(1), now).is_none());
+        assert!(drop.release().is_empty());
+
+        // Hold keeps the newest uplinks until released
+        let mut hold = UnknownRegionUplinks::new(UnknownRegionPolicy::Hold, 2);
+        for payload in 1..=3 {
+            assert!(hold.admit(mk_uplink(payload), now).is_none());
+        }
+        assert_eq!(vec![2, 3], payloads(&hold.release()));
+        assert!(hold.release().is_empty());
+    }
+
+    /// Adds a stand-in backup router after the default router of the
+    /// dispatcher. Returns the backup and the messages sent to it.
+    fn add_backup(dispatcher: &mut Dispatcher) -> (RouterKey, router::client::MessageReceiver) {
+        let (primary, primary_entry) = dispatcher.routers.iter().next().expect("primary");
+        let backup = RouterKey {
+            oui: 2,
+            uri: KeyedUri {
+                uri: "http://127.0.0.1:2".parse().expect("backup uri"),
+                ..primary.uri.clone()
+            },
+        };
+        let routing = primary_entry.routing.clone();
+        dispatcher.default_routers = Some(vec![primary.uri.clone(), backup.uri.clone()]);
+        let (dispatch, backup_messages) = router::client::message_channel(10);
+        dispatcher.routers.insert(
+            backup.clone(),
+            RouterEntry {
+                routing,
+                dispatch,
+                status: router::client::router_status_channel().1,
+                join_handle: tokio::spawn(async { Ok(()) }),
+            },
+        );
+        (backup, backup_messages)
+    }
+
+    fn is_uplink(
+        message: std::result::Result<
+            router::client::Message,
+            tokio::sync::mpsc::error::TryRecvError,
+        >,
+    ) -> bool {
+        matches!(message, Ok(router::client::Message::Uplink { .. }))
+    }
+
+    #[tokio::test]
+    async fn failover_follows_router_status() {
+        use router::client::{router_status_channel, RouterStatus};
+        let logger = Logger::root(slog::Discard, o!());
+        let mut settings = mk_test_settings();
+        settings.router.selection = crate::router::SelectionPolicy::Failover;
+        let (mut dispatcher, mut primary_messages) = mk_dispatcher(&mut settings);
+        let primary = dispatcher.routers.keys().next().cloned().expect("primary");
+        let (primary_status, status) = router_status_channel();
+        dispatcher
+            .routers
+            .get_mut(&primary)
+            .expect("primary")
+            .status = status;
+        let (_, mut backup_messages) = add_backup(&mut dispatcher);
+
+        dispatcher
+            .route_uplink(&mk_uplink(1), None, Instant::now(), &logger)
+            .await;
+        assert!(is_uplink(primary_messages.try_recv()));
+        assert!(backup_messages.try_recv().is_err());
+
+        // Failing routes and a full queue both move traffic to the backup
+        for unhealthy in [
+            RouterStatus {
+                failing: true,
+                backpressure: false,
+            },
+            RouterStatus {
+                failing: false,
+                backpressure: true,
+            },
+        ] {
+            primary_status.send_replace(unhealthy);
+            dispatcher
+                .route_uplink(&mk_uplink(2), None, Instant::now(), &logger)
+                .await;
+            assert!(primary_messages.try_recv().is_err());
+            assert!(is_uplink(backup_messages.try_recv()));
+        }
+
+        // Traffic returns once the primary routes again
+        primary_status.send_replace(RouterStatus::default());
+        dispatcher
+            .route_uplink(&mk_uplink(3), None, Instant::now(), &logger)
+            .await;
+        assert!(is_uplink(primary_messages.try_recv()));
+        assert!(backup_messages.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn dc_cap_diverts_to_backup() {
+        let logger = Logger::root(slog::Discard, o!());
+        let mut settings = mk_test_settings();
+        settings.router.selection = crate::router::SelectionPolicy::Failover;
+        settings.router.dc_cap = Some(2);
+        let (mut dispatcher, mut primary_messages) = mk_dispatcher(&mut settings);
+        let (backup, mut backup_messages) = add_backup(&mut dispatcher);
+        async fn route(dispatcher: &mut Dispatcher, payload: u8) {
+            let logger = Logger::root(slog::Discard, o!());
+            dispatcher
+                .route_uplink(&mk_uplink(payload), None, Instant::now(), &logger)
+                .await
+        }
+
+        // The primary takes uplinks until its cap is reached, then the backup
+        for payload in 1..=2 {
+            route(&mut dispatcher, payload).await;
+            assert!(is_uplink(primary_messages.try_recv()));
+        }
+        for payload in 3..=4 {
+            route(&mut dispatcher, payload).await;
+            assert!(primary_messages.try_recv().is_err());
+            assert!(is_uplink(backup_messages.try_recv()));
+        }
+        // With every router capped the uplink goes nowhere
+        route(&mut dispatcher, 5).await;
+        assert!(primary_messages.try_recv().is_err());
+        assert!(backup_messages.try_recv().is_err());
+
+        // A removed router is forgotten, so it starts over when it returns
+        dispatcher
+            .handle_router_finished(backup.clone(), Ok(Ok(())), &logger)
+            .expect("router stopped");
+        let (_, mut backup_messages) = add_backup(&mut dispatcher);
+        route(&mut dispatcher, 6).await;
+        assert!(primary_messages.try_recv().is_err());
+        assert!(is_uplink(backup_messages.try_recv()));
+    }
+
+    #[tokio::test]
+    async fn inferred_region_notifies_routers() {
+        let logger = Logger::root(slog::Discard, o!());
+        let mut settings = mk_test_settings();
+        settings.infer_region = Some(1);
+        let (mut dispatcher, mut client_messages) = mk_dispatcher(&mut settings);
+        let eu868 = Region::from_i32(ProtoRegion::Eu868.into()).expect("region");
+        assert_ne!(eu868, dispatcher.region);
+
+        let uplink: Packet = helium_proto::Packet {
+            payload: vec![1],
+            frequency: 868.1,
+            ..Default::default()
+        }
+        .into();
+        dispatcher
+            .handle_message(
+                Message::Uplink {
+                    packet: uplink,
+                    region: None,
+                    received_time: Instant::now(),
+                },
+                None,
+                &logger,
+            )
+            .await;
+        assert_eq!(eu868, dispatcher.region);
+        // Routers learn the inferred region before the uplink routed with it
+        assert!(matches!(
+            client_messages.try_recv(),
+            Ok(router::client::Message::RegionChanged(region)) if region == eu868
+        ));
+        assert!(matches!(
+            client_messages.try_recv(),
+            Ok(router::client::Message::Uplink { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn failed_router_fails_dispatcher() {
+        let logger = Logger::root(slog::Discard, o!());
+        let mut settings = mk_test_settings();
+        let (mut dispatcher, _) = mk_dispatcher(&mut settings);
+        for router_entry in dispatcher.routers.values_mut() {
+            router_entry.join_handle = tokio::spawn(async { Err(Error::channel()) });
+        }
+
+        let (router_key, result) = router_finished(&mut dispatcher.routers).await;
+        assert!(dispatcher
+            .handle_router_finished(router_key, result, &logger)
+            .is_err());
+        // The finished router is reaped
+        assert!(dispatcher.routers.is_empty());
+        assert!(time::timeout(
+            Duration::from_millis(10),
+            router_finished(&mut dispatcher.routers)
+        )
+        .await
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn shutdown_saves_router_queues() {
+        let logger = Logger::root(slog::Discard, o!());
+        let store = std::env::temp_dir().join(format!(
+            "gateway-rs-dispatcher-shutdown-{}",
+            std::process::id()
+        ));
+        let mut settings = mk_test_settings();
+        settings.cache.store = Some(store.clone());
+        settings.cache.flush_interval = Some(3600);
+        let (mut dispatcher, _) = mk_dispatcher(&mut settings);
+
+        // A router client that only stops when told to, holding a packet it
+        // failed to route
+        let (key, stand_in) = dispatcher.routers.drain().next().expect("router");
+        let (_client_trigger, client_shutdown) = triggered::trigger();
+        let router_entry = dispatcher
+            .start_router(client_shutdown, stand_in.routing, key.uri.clone())
+            .await
+            .expect("router client");
+        router_entry
+            .dispatch
+            .uplink(mk_uplink(1), Instant::now())
+            .await
+            .expect("uplink");
+        let store_path = store.join(format!("{}_{}.bin", key.oui, key.uri.pubkey));
+        dispatcher.routers.insert(key, router_entry);
+
+        let (trigger, shutdown) = triggered::trigger();
+        trigger.trigger();
+        dispatcher
+            .run(shutdown, &logger)
+            .await
+            .expect("dispatcher run");
+        assert!(dispatcher.routers.is_empty());
+        let mut saved = router::RouterStore::new(&settings.cache);
+        assert_eq!(1, saved.load(&store_path).expect("saved store"));
+        let _ = std::fs::remove_dir_all(&store);
+    }
+
+    #[test]
+    fn empty_messages_reconnect() {
+        let start = Instant::now();
+        let at = |millis| start + Duration::from_millis(millis);
+        let mut empty = EmptyMessages::new(3, Duration::from_secs(1));
+
+        // Three rapid empty messages trigger a single reconnect
+        let reconnects = (0..5).filter(|i| empty.record(at(i * 10))).count();
+        assert_eq!(1, reconnects);
+
+        // Empty messages spread beyond the window do not
+        let mut empty = EmptyMessages::new(3, Duration::from_secs(1));
+        assert!(!(0..5).any(|i| empty.record(at(i * 2000))));
+
+        // A regular message resets the count
+        let mut empty = EmptyMessages::new(3, Duration::from_secs(1));
+        assert!(!empty.record(at(0)));
+        assert!(!empty.record(at(10)));
+        empty.reset();
+        assert!(!empty.record(at(20)));
+        assert!(!empty.record(at(30)));
+        assert!(empty.record(at(40)));
+    }
+}
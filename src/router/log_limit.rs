@@ -0,0 +1,138 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+/// Limits how often identical log messages are logged.
+///
+/// The first occurrence of a message is logged. Repeats within the interval
+/// that follows are only counted, and reported as a summary once the interval
+/// is over. While the message keeps repeating a summary follows every
+/// interval. A message that was not repeated in an interval is forgotten, so
+/// its next occurrence is logged again. Without an interval every occurrence
+/// is logged.
+#[derive(Debug, Default)]
+pub struct LogLimit {
+    interval: Option<Duration>,
+    // Message to the start of its interval and the repeats counted in it
+    messages: HashMap<String, (Instant, u64)>,
+}
+
+impl LogLimit {
+    pub fn new(interval: Option<Duration>) -> Self {
+        Self {
+            interval,
+            messages: HashMap::new(),
+        }
+    }
+
+    pub fn interval(&self) -> Option<Duration> {
+        self.interval
+    }
+
+    /// Records an occurrence of the given message at the given time. Returns
+    /// whether the occurrence should be logged.
+    pub fn record(&mut self, message: &str, now: Instant) -> bool {
+        if self.interval.is_none() {
+            return true;
+        }
+        match self.messages.get_mut(message) {
+            Some((_, repeats)) => {
+                *repeats += 1;
+                false
+            }
+            None => {
+                self.messages.insert(message.to_string(), (now, 0));
+                true
+            }
+        }
+    }
+
+    /// Returns the messages whose interval is over at the given time with
+    /// the number of times they were repeated in it. Messages that were
+    /// repeated start a new interval, the others are forgotten.
+    pub fn summaries(&mut self, now: Instant) -> Vec<(String, u64)> {
+        let interval = match self.interval {
+            Some(interval) => interval,
+            None => return vec![],
+        };
+        let mut summaries = vec![];
+        self.messages.retain(|message, (start, repeats)| {
+            if now.saturating_duration_since(*start) < interval {
+                return true;
+            }
+            if *repeats == 0 {
+                return false;
+            }
+            summaries.push((message.clone(), *repeats));
+            *start = now;
+            *repeats = 0;
+            true
+        });
+        summaries
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repeats_summarized() {
+        let interval = Duration::from_secs(60);
+        let mut limit = LogLimit::new(Some(interval));
+        let start = Instant::now();
+        let mut logged = 0;
+        let mut summaries = vec![];
+        // The same error every second for three intervals, with summaries
+        // checked every ten seconds
+        for second in 0..180 {
+            let now = start + Duration::from_secs(second);
+            if limit.record("router unavailable", now) {
+                logged += 1;
+            }
+            if second % 10 == 0 {
+                summaries.extend(limit.summaries(now));
+            }
+        }
+        assert_eq!(1, logged);
+        assert_eq!(
+            vec![
+                ("router unavailable".to_string(), 60),
+                ("router unavailable".to_string(), 60),
+            ],
+            summaries
+        );
+
+        // Other messages are limited on their own
+        let now = start + Duration::from_secs(180);
+        assert!(limit.record("queue full", now));
+        assert!(!limit.record("queue full", now));
+
+        // Once the repeats stop a last summary is reported, after which the
+        // message is logged again
+        let now = start + Duration::from_secs(240);
+        assert_eq!(
+            vec![
+                ("queue full".to_string(), 1),
+                ("router unavailable".to_string(), 59),
+            ],
+            {
+                let mut summaries = limit.summaries(now);
+                summaries.sort();
+                summaries
+            }
+        );
+        let now = start + Duration::from_secs(300);
+        assert!(limit.summaries(now).is_empty());
+        assert!(limit.record("router unavailable", now));
+    }
+
+    #[test]
+    fn unlimited() {
+        let mut limit = LogLimit::new(None);
+        let now = Instant::now();
+        assert!((0..10).all(|_| limit.record("router unavailable", now)));
+        assert!(limit.summaries(now + Duration::from_secs(3600)).is_empty());
+    }
+}
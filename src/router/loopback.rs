@@ -0,0 +1,185 @@
+use crate::{
+    gateway, health,
+    router::{dispatcher, Dispatcher},
+    Error, KeyedUri, Packet, Result, Settings,
+};
+use futures::{stream, TryFutureExt};
+use helium_proto::{
+    blockchain_state_channel_message_v1::Msg,
+    services::router::{Router, Server},
+    BlockchainStateChannelMessageV1, BlockchainStateChannelResponseV1,
+};
+use serde::Serialize;
+use slog::{info, warn, Logger};
+use std::time::{Duration, Instant};
+use tokio::{net::TcpListener, time};
+use tonic::{transport::Server as TransportServer, Request, Response, Status};
+
+/// Payload of the synthetic self-test uplink. Marks the echoed downlink.
+const LOOPBACK_PAYLOAD: &[u8] = b"gateway-rs loopback";
+
+/// A router that answers every routed packet with a downlink carrying the
+/// packet payload back. The far end of the router client self-test.
+#[derive(Debug, Default, Clone)]
+pub struct LoopbackRouter;
+
+#[tonic::async_trait]
+impl Router for LoopbackRouter {
+    async fn route(
+        &self,
+        request: Request<BlockchainStateChannelMessageV1>,
+    ) -> std::result::Result<Response<BlockchainStateChannelMessageV1>, Status> {
+        let packet = match request.into_inner().msg {
+            Some(Msg::Packet(packet)) => packet.packet,
+            _ => return Err(Status::invalid_argument("not a state channel packet")),
+        };
+        let downlink = packet.map(|uplink| helium_proto::Packet {
+            // Respond in the rx1 window a second after the uplink
+            timestamp: uplink.timestamp + 1_000_000,
+            rx2_window: None,
+            ..uplink
+        });
+        let response = BlockchainStateChannelResponseV1 {
+            accepted: true,
+            downlink,
+            ..Default::default()
+        };
+        Ok(Response::new(BlockchainStateChannelMessageV1 {
+            msg: Some(Msg::Response(response)),
+        }))
+    }
+}
+
+impl LoopbackRouter {
+    /// Serves the loopback router on the given listener until shutdown
+    pub async fn serve(self, listener: TcpListener, shutdown: triggered::Listener) -> Result {
+        let incoming = stream::unfold(listener, |listener| async move {
+            let stream = listener.accept().await.map(|(stream, _)| stream);
+            Some((stream, listener))
+        });
+        TransportServer::builder()
+            .add_service(Server::new(self))
+            .serve_with_incoming_shutdown(incoming, shutdown)
+            .map_err(Error::from)
+            .await
+    }
+}
+
+/// The outcome of a successful router client self-test
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct RouterSelftestReport {
+    /// Time in milliseconds from handing the uplink to the dispatcher to
+    /// receiving the echoed downlink on the gateway channel
+    pub round_trip: u64,
+}
+
+/// Self-tests routing to a router. A synthetic uplink is handed to a
+/// dispatcher, which routes it with a router client to the given router as
+/// its only router. The router is expected to echo it back as a downlink,
+/// like the loopback router does. Succeeds once the downlink reaches the
+/// gateway channel of the dispatcher within the timeout. No gateway service
+/// or packet forwarder is involved.
+///
+/// Queued packets are not persisted or captured during the self-test.
+pub async fn run_router_selftest(
+    router: KeyedUri,
+    settings: &Settings,
+    timeout: Duration,
+    logger: &Logger,
+) -> Result<RouterSelftestReport> {
+    let (uplinks, dispatcher_rx) = dispatcher::message_channel(1);
+    let (downlinks, mut downlinks_rx) = gateway::message_channel(1);
+    let (health, _) = health::health_channel();
+    let mut dispatcher = Dispatcher::new(dispatcher_rx, downlinks, health, settings)?;
+    let (trigger, shutdown) = triggered::trigger();
+    let dispatcher_logger = logger.clone();
+    let dispatcher = tokio::spawn(async move {
+        dispatcher
+            .run_selftest(router, shutdown, &dispatcher_logger)
+            .await
+    });
+
+    let uplink: Packet = helium_proto::Packet {
+        payload: LOOPBACK_PAYLOAD.to_vec(),
+        timestamp: 1_000_000,
+        frequency: 868.1,
+        datarate: "SF7BW125".to_string(),
+        ..Default::default()
+    }
+    .into();
+    let start = Instant::now();
+    let echoed = async {
+        uplinks.uplink(uplink, None, start).await?;
+        while let Some(message) = downlinks_rx.recv().await {
+            match message {
+                gateway::Message::Downlink(downlink) if downlink.payload == LOOPBACK_PAYLOAD => {
+                    return Ok(());
+                }
+                _ => continue,
+            }
+        }
+        Err(Error::channel())
+    };
+    let result = match time::timeout(timeout, echoed).await {
+        Ok(result) => result,
+        Err(_) => Err(Error::timeout(timeout)),
+    };
+    trigger.trigger();
+    match dispatcher.await {
+        Ok(Ok(())) => (),
+        Ok(Err(err)) => warn!(logger, "self-test dispatcher failed: {err:?}"),
+        Err(err) => warn!(logger, "self-test dispatcher panicked: {err:?}"),
+    }
+    result?;
+    let round_trip = start.elapsed().as_millis() as u64;
+    info!(logger, "router self-test complete"; "round_trip" => round_trip);
+    Ok(RouterSelftestReport { round_trip })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::mk_test_settings;
+    use slog::o;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn router_selftest() {
+        let logger = Logger::root(slog::Discard, o!());
+        let mut settings = mk_test_settings();
+        settings.router.route_timeout = 200;
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("listener");
+        let addr = listener.local_addr().expect("listener address");
+        let (trigger, shutdown) = triggered::trigger();
+        let server = tokio::spawn(LoopbackRouter.serve(listener, shutdown));
+        let router = KeyedUri {
+            uri: format!("http://{addr}").parse().expect("router uri"),
+            pubkey: Arc::new(settings.keypair.public_key().clone()),
+        };
+        let report =
+            run_router_selftest(router.clone(), &settings, Duration::from_secs(5), &logger)
+                .await
+                .expect("self-test");
+        assert!(report.round_trip < 5_000);
+        trigger.trigger();
+        server.await.expect("server task").expect("server");
+
+        // A router that never answers fails the self-test within its timeout
+        let silent = TcpListener::bind("127.0.0.1:0").await.expect("listener");
+        let addr = silent.local_addr().expect("listener address");
+        tokio::spawn(async move {
+            let mut connections = vec![];
+            while let Ok((stream, _)) = silent.accept().await {
+                connections.push(stream);
+            }
+        });
+        let router = KeyedUri {
+            uri: format!("http://{addr}").parse().expect("router uri"),
+            ..router
+        };
+        let start = Instant::now();
+        let result = run_router_selftest(router, &settings, Duration::from_secs(1), &logger).await;
+        assert!(result.is_err());
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
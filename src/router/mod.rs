@@ -1,11 +1,29 @@
+pub mod capture;
+pub mod chain_tip;
 pub mod client;
+pub mod dc_cap;
+pub mod devaddr_map;
 pub mod dispatcher;
 pub mod filter;
+pub mod log_limit;
+pub mod loopback;
+pub mod reload;
 pub mod routing;
+pub mod selection;
 pub mod store;
+pub mod writer;
 
+pub use capture::{CaptureDirection, PacketCapture};
+pub use chain_tip::ChainTip;
 pub use client::RouterClient;
-pub use dispatcher::Dispatcher;
+pub use dc_cap::DcCap;
+pub use devaddr_map::DevAddrMap;
+pub use dispatcher::{Dispatcher, UnknownRegionPolicy};
 pub use filter::{DevAddrFilter, EuiFilter};
+pub use log_limit::LogLimit;
+pub use loopback::{run_router_selftest, LoopbackRouter, RouterSelftestReport};
+pub use reload::{ReloadFile, RouterReload};
 pub use routing::Routing;
-pub use store::{QuePacket, RouterStore};
+pub use selection::{RouterSelection, SelectionPolicy};
+pub use store::{QuePacket, ReplayWindow, RouterStore, StoreFailures};
+pub use writer::FileWriter;
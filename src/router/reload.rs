@@ -0,0 +1,123 @@
+use crate::{
+    settings::{RouterAuthToken, RouterWeight},
+    Error, Result,
+};
+use config::{Config, File, FileFormat};
+use serde::Deserialize;
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+/// Minimum time between checks of the reload file for changes
+const RELOAD_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Router settings that can change without a restart. A section missing from
+/// the reload file keeps the settings in use.
+#[derive(Debug, Default, Deserialize)]
+pub struct RouterReload {
+    #[serde(default)]
+    pub weights: Option<Vec<RouterWeight>>,
+    #[serde(default)]
+    pub auth_tokens: Option<Vec<RouterAuthToken>>,
+}
+
+/// Watches the router reload file and loads it again whenever its
+/// modification time changes. A file that fails to load is not retried until
+/// it changes again.
+#[derive(Debug)]
+pub struct ReloadFile {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    last_check: Option<Instant>,
+}
+
+impl ReloadFile {
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            modified: None,
+            last_check: None,
+        }
+    }
+
+    /// Loads the file if it changed since it was last checked. Checks at most
+    /// once every second. Returns `None` if the file did not change or is
+    /// missing.
+    pub async fn poll(&mut self, now: Instant) -> Option<Result<RouterReload>> {
+        if self.last_check.map_or(false, |last_check| {
+            now.saturating_duration_since(last_check) < RELOAD_CHECK_INTERVAL
+        }) {
+            return None;
+        }
+        self.last_check = Some(now);
+        let modified = tokio::fs::metadata(&self.path)
+            .await
+            .and_then(|metadata| metadata.modified())
+            .ok()?;
+        if self.modified == Some(modified) {
+            return None;
+        }
+        self.modified = Some(modified);
+        Some(self.load().await)
+    }
+
+    async fn load(&self) -> Result<RouterReload> {
+        let data = tokio::fs::read_to_string(&self.path).await?;
+        Config::builder()
+            .add_source(File::from_str(&data, FileFormat::Toml))
+            .build()
+            .and_then(|config| config.try_deserialize())
+            .map_err(Error::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn reload_on_change() {
+        let path = std::env::temp_dir().join(format!(
+            "gateway-rs-router-reload-{}.toml",
+            std::process::id()
+        ));
+        let pubkey = "11w77YQLhgUt8HUJrMtntGGr97RyXmot1ofs5Ct2ELTmbFoYsQa";
+        let entry =
+            |section: &str, field: &str| format!("[[{section}]]\npubkey = \"{pubkey}\"\n{field}\n");
+        let now = Instant::now();
+        let mut reload = ReloadFile::new(&path);
+        let _ = std::fs::remove_file(&path);
+        assert!(reload.poll(now).await.is_none());
+
+        // Sections missing from the file are left alone
+        std::fs::write(&path, entry("weights", "weight = 5")).expect("write reload file");
+        let now = now + RELOAD_CHECK_INTERVAL;
+        let loaded = reload.poll(now).await.expect("changed").expect("loaded");
+        assert_eq!(5, loaded.weights.expect("weights")[0].weight);
+        assert!(loaded.auth_tokens.is_none());
+
+        // Unchanged files and checks within the interval are skipped
+        assert!(reload.poll(now + RELOAD_CHECK_INTERVAL).await.is_none());
+        reload.modified = None;
+        assert!(reload.poll(now + RELOAD_CHECK_INTERVAL / 2).await.is_none());
+
+        std::fs::write(&path, entry("auth_tokens", "token = \"rotated\"")).expect("write");
+        let now = now + RELOAD_CHECK_INTERVAL * 2;
+        let loaded = reload.poll(now).await.expect("changed").expect("loaded");
+        assert_eq!(
+            "rotated",
+            loaded.auth_tokens.expect("tokens")[0].token.expose()
+        );
+        assert!(loaded.weights.is_none());
+
+        // A broken file is reported once
+        std::fs::write(&path, "weights = 1").expect("write reload file");
+        reload.modified = None;
+        let now = now + RELOAD_CHECK_INTERVAL;
+        assert!(reload.poll(now).await.expect("changed").is_err());
+        assert!(reload.poll(now + RELOAD_CHECK_INTERVAL).await.is_none());
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
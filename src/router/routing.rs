@@ -13,6 +13,17 @@ pub struct Routing {
 }
 
 impl Routing {
+    /// Routing of an oui to the given routers without filters or subnets, so
+    /// packets only reach them as default routers
+    pub fn new(oui: u32, uris: Vec<KeyedUri>) -> Self {
+        Self {
+            oui,
+            uris,
+            filters: vec![],
+            subnets: vec![],
+        }
+    }
+
     pub fn contains_uri(&self, uri: &KeyedUri) -> bool {
         self.uris.iter().any(|keyed_uri| keyed_uri == uri)
     }
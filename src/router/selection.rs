@@ -0,0 +1,373 @@
+use crate::{settings::RouterWeight, KeyedUri, Packet, PublicKey, RouterSettings};
+use rand::{
+    distributions::{Distribution, WeightedIndex},
+    Rng,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+
+/// Picks which of the candidate routers for a packet the packet is sent to.
+///
+/// Candidates are passed in a stable order, with configured default routers in
+/// their configured order. Policies return indices into the candidate list.
+pub trait RouterSelection: Send {
+    fn select(&mut self, routers: &[&KeyedUri], packet: &Packet) -> Vec<usize>;
+
+    /// Reports the health of a router. Candidates are reported before each
+    /// selection, from the routing state of their clients, and again when a
+    /// packet can not be handed to them. Policies that track router health
+    /// use this, the default ignores it.
+    fn report(&mut self, _router: &KeyedUri, _success: bool) {}
+
+    /// Replaces the router weights with reloaded ones. Policies that weigh
+    /// routers use this, the default ignores it.
+    fn reload_weights(&mut self, _weights: &[RouterWeight]) {}
+}
+
+/// The built-in router selection policies
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SelectionPolicy {
+    /// Send to every candidate router
+    #[default]
+    FanOut,
+    /// Send to the first candidate router that has not failed
+    Failover,
+    /// Rotate through the candidate routers
+    RoundRobin,
+    /// Send packets with the same payload to the same router
+    ConsistentHash,
+    /// Send to a random router, in proportion to the router weights
+    Weighted,
+}
+
+impl SelectionPolicy {
+    pub fn selection(&self, settings: &RouterSettings) -> Box<dyn RouterSelection> {
+        match self {
+            Self::FanOut => Box::new(FanOut),
+            Self::Failover => Box::<Failover>::default(),
+            Self::RoundRobin => Box::<RoundRobin>::default(),
+            Self::ConsistentHash => Box::new(ConsistentHash),
+            Self::Weighted => Box::new(Weighted::new(RouterWeights::new(settings))),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct FanOut;
+
+impl RouterSelection for FanOut {
+    fn select(&mut self, routers: &[&KeyedUri], _packet: &Packet) -> Vec<usize> {
+        (0..routers.len()).collect()
+    }
+}
+
+/// Sends to the first healthy candidate. A router is marked failed while its
+/// routes fail or time out, while its queue is full, or when a packet can not
+/// be handed to it. If all candidates have failed the first one is used.
+#[derive(Debug, Default)]
+pub struct Failover {
+    failed: HashSet<KeyedUri>,
+}
+
+impl RouterSelection for Failover {
+    fn select(&mut self, routers: &[&KeyedUri], _packet: &Packet) -> Vec<usize> {
+        routers
+            .iter()
+            .position(|router| !self.failed.contains(*router))
+            .or_else(|| (!routers.is_empty()).then_some(0))
+            .into_iter()
+            .collect()
+    }
+
+    fn report(&mut self, router: &KeyedUri, success: bool) {
+        if success {
+            self.failed.remove(router);
+        } else {
+            self.failed.insert(router.clone());
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct RoundRobin {
+    next: usize,
+}
+
+impl RouterSelection for RoundRobin {
+    fn select(&mut self, routers: &[&KeyedUri], _packet: &Packet) -> Vec<usize> {
+        if routers.is_empty() {
+            return vec![];
+        }
+        let index = self.next % routers.len();
+        self.next = self.next.wrapping_add(1);
+        vec![index]
+    }
+}
+
+/// Rendezvous hashing of the packet payload hash against each router, so a
+/// change in the set of routers only moves the packets of the changed router.
+#[derive(Debug, Default)]
+pub struct ConsistentHash;
+
+impl ConsistentHash {
+    fn score(router: &KeyedUri, packet_hash: &[u8]) -> [u8; 32] {
+        Sha256::new()
+            .chain_update(router.pubkey.to_vec())
+            .chain_update(packet_hash)
+            .finalize()
+            .into()
+    }
+}
+
+impl RouterSelection for ConsistentHash {
+    fn select(&mut self, routers: &[&KeyedUri], packet: &Packet) -> Vec<usize> {
+        let packet_hash = packet.hash();
+        routers
+            .iter()
+            .enumerate()
+            .max_by_key(|(_, router)| Self::score(router, &packet_hash))
+            .map(|(index, _)| index)
+            .into_iter()
+            .collect()
+    }
+}
+
+/// Weights of routers by public key, for weighted selection. The configured
+/// weights are replaced whenever the router reload file has weights.
+#[derive(Debug, Default)]
+pub struct RouterWeights {
+    weights: HashMap<PublicKey, u32>,
+}
+
+impl RouterWeights {
+    /// The weight of routers without a configured weight
+    pub const DEFAULT_WEIGHT: u32 = 1;
+
+    pub fn new(settings: &RouterSettings) -> Self {
+        Self {
+            weights: Self::collect(&settings.weights),
+        }
+    }
+
+    pub fn weight(&self, pubkey: &PublicKey) -> u32 {
+        self.weights
+            .get(pubkey)
+            .copied()
+            .unwrap_or(Self::DEFAULT_WEIGHT)
+    }
+
+    /// Replaces the weights with the given ones
+    pub fn replace(&mut self, weights: &[RouterWeight]) {
+        self.weights = Self::collect(weights);
+    }
+
+    fn collect(weights: &[RouterWeight]) -> HashMap<PublicKey, u32> {
+        weights
+            .iter()
+            .map(|entry| (entry.pubkey.as_ref().clone(), entry.weight))
+            .collect()
+    }
+}
+
+/// Sends to a random candidate, picked in proportion to the candidate
+/// weights. A router with weight zero is not picked, unless all candidates
+/// have weight zero, in which case all are equally likely.
+#[derive(Debug, Default)]
+pub struct Weighted {
+    weights: RouterWeights,
+}
+
+impl Weighted {
+    pub fn new(weights: RouterWeights) -> Self {
+        Self { weights }
+    }
+}
+
+impl RouterSelection for Weighted {
+    fn select(&mut self, routers: &[&KeyedUri], _packet: &Packet) -> Vec<usize> {
+        if routers.is_empty() {
+            return vec![];
+        }
+        let mut rng = rand::thread_rng();
+        let weights = routers
+            .iter()
+            .map(|router| u64::from(self.weights.weight(&router.pubkey)));
+        let index = match WeightedIndex::new(weights) {
+            Ok(distribution) => distribution.sample(&mut rng),
+            Err(_) => rng.gen_range(0..routers.len()),
+        };
+        vec![index]
+    }
+
+    fn reload_weights(&mut self, weights: &[RouterWeight]) {
+        self.weights.replace(weights);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keyed_uri::mk_test_uri;
+
+    fn mk_routers(count: usize) -> Vec<KeyedUri> {
+        (0..count)
+            .map(|index| mk_test_uri(&format!("http://router{index}.local:8080")))
+            .collect()
+    }
+
+    fn mk_packet(payload: &[u8]) -> Packet {
+        helium_proto::Packet {
+            payload: payload.to_vec(),
+            ..Default::default()
+        }
+        .into()
+    }
+
+    #[test]
+    fn fan_out() {
+        let routers = mk_routers(3);
+        let candidates: Vec<&KeyedUri> = routers.iter().collect();
+        let mut selection = SelectionPolicy::FanOut.selection(&RouterSettings::default());
+        assert_eq!(
+            vec![0, 1, 2],
+            selection.select(&candidates, &mk_packet(&[1]))
+        );
+        assert!(selection.select(&[], &mk_packet(&[1])).is_empty());
+    }
+
+    #[test]
+    fn failover() {
+        let routers = mk_routers(3);
+        let candidates: Vec<&KeyedUri> = routers.iter().collect();
+        let packet = mk_packet(&[1]);
+        let mut selection = SelectionPolicy::Failover.selection(&RouterSettings::default());
+        assert_eq!(vec![0], selection.select(&candidates, &packet));
+
+        selection.report(&routers[0], false);
+        assert_eq!(vec![1], selection.select(&candidates, &packet));
+        selection.report(&routers[1], false);
+        selection.report(&routers[2], false);
+        // All failed falls back to the primary
+        assert_eq!(vec![0], selection.select(&candidates, &packet));
+
+        selection.report(&routers[0], true);
+        assert_eq!(vec![0], selection.select(&candidates, &packet));
+    }
+
+    #[test]
+    fn round_robin() {
+        let routers = mk_routers(3);
+        let candidates: Vec<&KeyedUri> = routers.iter().collect();
+        let packet = mk_packet(&[1]);
+        let mut selection = SelectionPolicy::RoundRobin.selection(&RouterSettings::default());
+        let selected: Vec<usize> = (0..4)
+            .flat_map(|_| selection.select(&candidates, &packet))
+            .collect();
+        assert_eq!(vec![0, 1, 2, 0], selected);
+    }
+
+    #[test]
+    fn consistent_hash() {
+        let routers = mk_routers(4);
+        let candidates: Vec<&KeyedUri> = routers.iter().collect();
+        let mut selection = SelectionPolicy::ConsistentHash.selection(&RouterSettings::default());
+        for payload in 0..16u8 {
+            let packet = mk_packet(&[payload]);
+            let selected = selection.select(&candidates, &packet);
+            assert_eq!(1, selected.len());
+            // Stable for the same packet
+            assert_eq!(selected, selection.select(&candidates, &packet));
+
+            // Removing a router that was not selected keeps the selection
+            let chosen = candidates[selected[0]];
+            let removed = (selected[0] + 1) % candidates.len();
+            let remaining: Vec<&KeyedUri> = candidates
+                .iter()
+                .enumerate()
+                .filter(|(index, _)| *index != removed)
+                .map(|(_, router)| *router)
+                .collect();
+            let reselected = selection.select(&remaining, &packet);
+            assert_eq!(chosen, remaining[reselected[0]]);
+        }
+    }
+
+    fn mk_weights(routers: &[KeyedUri], weights: &[u32]) -> Vec<RouterWeight> {
+        routers
+            .iter()
+            .zip(weights)
+            .map(|(router, weight)| RouterWeight {
+                pubkey: router.pubkey.clone(),
+                weight: *weight,
+            })
+            .collect()
+    }
+
+    fn shares(selection: &mut dyn RouterSelection, candidates: &[&KeyedUri]) -> Vec<f64> {
+        const PACKETS: usize = 20_000;
+        let packet = mk_packet(&[1]);
+        let mut counts = vec![0usize; candidates.len()];
+        for _ in 0..PACKETS {
+            let selected = selection.select(candidates, &packet);
+            assert_eq!(1, selected.len());
+            counts[selected[0]] += 1;
+        }
+        counts
+            .into_iter()
+            .map(|count| count as f64 / PACKETS as f64)
+            .collect()
+    }
+
+    #[test]
+    fn weighted() {
+        let routers = mk_routers(4);
+        let candidates: Vec<&KeyedUri> = routers.iter().collect();
+        // The last router has no configured weight and defaults to 1
+        let settings = RouterSettings {
+            weights: mk_weights(&routers, &[10, 30, 59]),
+            ..Default::default()
+        };
+        let mut selection = SelectionPolicy::Weighted.selection(&settings);
+        let shares = shares(selection.as_mut(), &candidates);
+        for (share, expected) in shares.iter().zip([0.10, 0.30, 0.59, 0.01]) {
+            assert!(
+                (share - expected).abs() < 0.02,
+                "share {share} expected {expected}"
+            );
+        }
+        assert!(selection.select(&[], &mk_packet(&[1])).is_empty());
+
+        // All zero weights spread evenly rather than dropping packets
+        let settings = RouterSettings {
+            weights: mk_weights(&routers, &[0, 0, 0, 0]),
+            ..Default::default()
+        };
+        let mut selection = SelectionPolicy::Weighted.selection(&settings);
+        for share in shares(selection.as_mut(), &candidates) {
+            assert!((share - 0.25).abs() < 0.02, "share {share}");
+        }
+    }
+
+    #[test]
+    fn reload_weights() {
+        let routers = mk_routers(2);
+        let candidates: Vec<&KeyedUri> = routers.iter().collect();
+        let settings = RouterSettings {
+            weights: mk_weights(&routers, &[1, 0]),
+            ..Default::default()
+        };
+        let mut selection = SelectionPolicy::Weighted.selection(&settings);
+        assert_eq!(vec![1.0, 0.0], shares(selection.as_mut(), &candidates));
+
+        // Ramp the second router up to all traffic
+        selection.reload_weights(&mk_weights(&routers, &[0, 1]));
+        assert_eq!(vec![0.0, 1.0], shares(selection.as_mut(), &candidates));
+
+        // Policies without weights ignore them
+        let mut selection = SelectionPolicy::RoundRobin.selection(&settings);
+        selection.reload_weights(&mk_weights(&routers, &[0, 1]));
+        assert_eq!(vec![0.5, 0.5], shares(selection.as_mut(), &candidates));
+    }
+}
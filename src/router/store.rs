@@ -1,29 +1,213 @@
-use crate::{CacheSettings, Packet, Result};
+use crate::{
+    error::{DecodeError, EncodeError, StoreError},
+    metrics::RateLimiter,
+    CacheSettings, Error, Packet, PacketField, Region, Result, UplinkSource, UplinkTime,
+};
+use bytes::{Buf, BufMut};
+use helium_proto::Message;
 use std::{
     collections::VecDeque,
+    fs,
     ops::Deref,
+    path::{Path, PathBuf},
     time::{Duration, Instant},
 };
 
+/// The magic bytes at the start of every zstd frame. Used to detect compressed
+/// store files on load regardless of the current compression setting.
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// Writes encoded store or replay window data to the given file, replacing
+/// any previous content. This blocks, so clients run it through their
+/// `FileWriter`.
+pub fn write_file(path: &Path, data: &[u8]) -> Result {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, data)?;
+    Ok(())
+}
+
 pub struct RouterStore {
     waiting_packets: VecDeque<QuePacket>,
     max_packets: u16,
+    compress: bool,
+    dropped: u64,
+    backpressure: bool,
+    filter_duplicates: bool,
+    hash_exclude: Vec<PacketField>,
+    limiter: Option<RateLimiter>,
+    failures: StoreFailures,
+}
+
+/// Counts of packets the store did not keep, by reason
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct StoreFailures {
+    /// Packets rejected or dropped because the queue was at capacity
+    pub capacity: u64,
+    /// Packets rejected because they arrived above the store rate limit
+    pub rate_limited: u64,
+    /// Packets rejected because the same packet was already queued, if
+    /// duplicates are filtered
+    pub filtered: u64,
+}
+
+impl StoreFailures {
+    fn record(&mut self, err: &StoreError) {
+        match err {
+            StoreError::Capacity(_) => self.capacity += 1,
+            StoreError::RateLimited(_) => self.rate_limited += 1,
+            StoreError::Filtered => self.filtered += 1,
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct QuePacket {
     received: Instant,
     packet: Packet,
+    region: Option<Region>,
+    sign_failures: u32,
+    // The normalized hash duplicates are filtered by, computed once when
+    // queued if duplicates are filtered
+    dedup_hash: Option<Vec<u8>>,
 }
 
 impl QuePacket {
     pub fn hold_time(&self) -> Duration {
-        self.received.elapsed()
+        self.hold_time_at(Instant::now())
+    }
+
+    /// The time the packet has been held as of the given instant
+    pub fn hold_time_at(&self, now: Instant) -> Duration {
+        now.saturating_duration_since(self.received)
     }
 
     pub fn packet(&self) -> &Packet {
         &self.packet
     }
+
+    /// The region to route this packet with instead of the router region,
+    /// if overridden
+    pub fn region(&self) -> Option<Region> {
+        self.region
+    }
+
+    /// Where the packet was received, if tagged. Not persisted.
+    pub fn source(&self) -> Option<&UplinkSource> {
+        self.packet.source()
+    }
+
+    /// When the packet was received, if stamped. Not persisted.
+    pub fn time(&self) -> Option<UplinkTime> {
+        self.packet.time()
+    }
+
+    /// Records a failure to sign the packet and returns the number of
+    /// failures so far
+    pub fn sign_failed(&mut self) -> u32 {
+        self.sign_failures += 1;
+        self.sign_failures
+    }
+
+    /// A hash of the full encoded packet. Unlike the payload hash this tells
+    /// apart different receptions of the same payload.
+    pub fn key(&self) -> Vec<u8> {
+        self.packet.normalized_hash(&[])
+    }
+}
+
+/// Remembers the keys of recently routed packets for a fixed window so exact
+/// replays, for example of packets reloaded from a stale persisted store, are
+/// not routed again. The window is persisted next to the store so replays
+/// are also caught across restarts.
+#[derive(Debug)]
+pub struct ReplayWindow {
+    window: Duration,
+    routed: VecDeque<(Instant, Vec<u8>)>,
+}
+
+impl ReplayWindow {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            routed: VecDeque::new(),
+        }
+    }
+
+    /// Records a successfully routed packet key at the given time
+    pub fn insert(&mut self, key: Vec<u8>, now: Instant) {
+        self.expire(now);
+        self.routed.push_back((now, key));
+    }
+
+    /// Whether the given packet key was routed within the window
+    pub fn contains(&mut self, key: &[u8], now: Instant) -> bool {
+        self.expire(now);
+        self.routed.iter().any(|(_, routed)| routed == key)
+    }
+
+    fn expire(&mut self, now: Instant) {
+        while let Some((routed_at, _)) = self.routed.front() {
+            if now.saturating_duration_since(*routed_at) <= self.window {
+                break;
+            }
+            self.routed.pop_front();
+        }
+    }
+
+    /// The file the replay window of the store at the given path is
+    /// persisted to
+    pub fn path(store: &Path) -> PathBuf {
+        let mut path = store.as_os_str().to_owned();
+        path.push(".replays");
+        PathBuf::from(path)
+    }
+
+    /// Loads routed packet keys from the given file. A missing file is
+    /// treated as an empty window. Returns the number of keys still in the
+    /// window.
+    pub fn load(&mut self, path: &Path) -> Result<usize> {
+        let data = match fs::read(path) {
+            Ok(data) => data,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(err) => return Err(err.into()),
+        };
+        self.load_bytes(&data, Instant::now())
+    }
+
+    /// Encodes the routed packet keys as a sequence of age (in millis) and
+    /// length prefixed key pairs
+    pub fn to_bytes(&mut self, now: Instant) -> Vec<u8> {
+        self.expire(now);
+        let mut buf = vec![];
+        for (routed_at, key) in &self.routed {
+            buf.put_u64(now.saturating_duration_since(*routed_at).as_millis() as u64);
+            buf.put_u16(key.len() as u16);
+            buf.put_slice(key);
+        }
+        buf
+    }
+
+    /// Decodes routed packet keys encoded by `to_bytes`, in routing order
+    pub fn load_bytes(&mut self, mut data: &[u8], now: Instant) -> Result<usize> {
+        while data.has_remaining() {
+            if data.remaining() < 10 {
+                return Err(DecodeError::prost_decode("truncated replay key header"));
+            }
+            let age = Duration::from_millis(data.get_u64());
+            let len = data.get_u16() as usize;
+            if data.remaining() < len {
+                return Err(DecodeError::prost_decode("truncated replay key"));
+            }
+            let key = data[..len].to_vec();
+            data.advance(len);
+            let routed_at = now.checked_sub(age).unwrap_or(now);
+            self.routed.push_back((routed_at, key));
+        }
+        self.expire(now);
+        Ok(self.routed.len())
+    }
 }
 
 impl Deref for QuePacket {
@@ -41,20 +225,144 @@ impl RouterStore {
         Self {
             waiting_packets,
             max_packets,
+            compress: settings.compress,
+            dropped: 0,
+            backpressure: false,
+            filter_duplicates: settings.filter_duplicates,
+            hash_exclude: settings.hash_exclude.clone(),
+            limiter: settings.max_rate.map(RateLimiter::new),
+            failures: StoreFailures::default(),
         }
     }
 
+    /// Queues a packet unless the queue has no capacity. A full queue drops
+    /// its oldest packet to make room. If enabled, packets above the store
+    /// rate limit and duplicates of a queued packet are not admitted. Rejected
+    /// and dropped packets are counted by reason in `failures`.
     pub fn store_waiting_packet(&mut self, packet: Packet, received: Instant) -> Result {
-        self.waiting_packets
-            .push_back(QuePacket { packet, received });
+        self.store_waiting_packet_in(packet, None, received)
+    }
+
+    /// Queues a packet like `store_waiting_packet` with an optional region to
+    /// route it with instead of the router region. Region overrides are not
+    /// persisted.
+    pub fn store_waiting_packet_in(
+        &mut self,
+        packet: Packet,
+        region: Option<Region>,
+        received: Instant,
+    ) -> Result {
+        let dedup_hash = self.dedup_hash(&packet);
+        self.check_capacity()
+            .and_then(|()| self.admit_waiting_packet(dedup_hash.as_deref(), received))
+            .map_err(|err| {
+                self.failures.record(&err);
+                Error::from(err)
+            })?;
+        self.push_waiting_packet(packet, region, received, dedup_hash);
+        Ok(())
+    }
+
+    /// The hash duplicates of the given packet are detected by, if duplicates
+    /// are filtered
+    fn dedup_hash(&self, packet: &Packet) -> Option<Vec<u8>> {
+        self.filter_duplicates
+            .then(|| packet.normalized_hash(&self.hash_exclude))
+    }
+
+    fn check_capacity(&self) -> std::result::Result<(), StoreError> {
+        if self.max_packets == 0 {
+            return Err(StoreError::Capacity(self.max_packets));
+        }
+        Ok(())
+    }
+
+    /// Applies the optional admission policies, the duplicate filter and the
+    /// store rate limit
+    fn admit_waiting_packet(
+        &mut self,
+        dedup_hash: Option<&[u8]>,
+        received: Instant,
+    ) -> std::result::Result<(), StoreError> {
+        if let Some(dedup_hash) = dedup_hash {
+            if self
+                .waiting_packets
+                .iter()
+                .any(|waiting| waiting.dedup_hash.as_deref() == Some(dedup_hash))
+            {
+                return Err(StoreError::Filtered);
+            }
+        }
+        if let Some(limiter) = &mut self.limiter {
+            if !limiter.check(received) {
+                return Err(StoreError::RateLimited(limiter.limit()));
+            }
+        }
+        Ok(())
+    }
+
+    fn push_waiting_packet(
+        &mut self,
+        packet: Packet,
+        region: Option<Region>,
+        received: Instant,
+        dedup_hash: Option<Vec<u8>>,
+    ) {
+        self.waiting_packets.push_back(QuePacket {
+            packet,
+            received,
+            region,
+            sign_failures: 0,
+            dedup_hash,
+        });
         if self.waiting_packets_len() > self.max_packets as usize {
             self.waiting_packets.pop_front();
+            self.backpressure = true;
+            self.failures
+                .record(&StoreError::Capacity(self.max_packets));
         }
-        Ok(())
+    }
+
+    /// The packets the store did not keep, by reason
+    pub fn failures(&self) -> StoreFailures {
+        self.failures
     }
 
     pub fn pop_waiting_packet(&mut self) -> Option<QuePacket> {
-        self.waiting_packets.pop_front()
+        let packet = self.waiting_packets.pop_front();
+        self.update_backpressure();
+        packet
+    }
+
+    /// Whether the store has reached capacity and is dropping the oldest
+    /// packets to make room. Cleared once the queue drops below capacity.
+    pub fn backpressure(&self) -> bool {
+        self.backpressure
+    }
+
+    fn update_backpressure(&mut self) {
+        if self.waiting_packets.len() < self.max_packets as usize {
+            self.backpressure = false;
+        }
+    }
+
+    /// Puts a previously popped packet back at the front of the queue, for
+    /// example after a failed send. If the queue filled up in the meantime
+    /// the packet, being the oldest, is dropped instead and counted like any
+    /// packet dropped at capacity.
+    pub fn requeue_waiting_packet(&mut self, packet: QuePacket) {
+        if self.waiting_packets_len() >= self.max_packets as usize {
+            self.backpressure = true;
+            self.failures
+                .record(&StoreError::Capacity(self.max_packets));
+            return;
+        }
+        self.waiting_packets.push_front(packet);
+    }
+
+    /// How long the oldest waiting packet has been queued
+    pub fn oldest_hold_time(&self) -> Option<Duration> {
+        self.waiting_packets.front().map(QuePacket::hold_time)
     }
 
     pub fn waiting_packets_len(&self) -> usize {
@@ -64,9 +372,403 @@ impl RouterStore {
     /// Removes waiting packets older than the given duration. Returns the number
     /// of packets that were removed.
     pub fn gc_waiting_packets(&mut self, duration: Duration) -> usize {
+        self.gc_waiting_packets_at(duration, Instant::now())
+    }
+
+    /// Removes waiting packets that are older than the given duration as of
+    /// the given instant. Returns the number of packets that were removed.
+    pub fn gc_waiting_packets_at(&mut self, duration: Duration, now: Instant) -> usize {
         let before_len = self.waiting_packets.len();
         self.waiting_packets
-            .retain(|packet| packet.received.elapsed() <= duration);
+            .retain(|packet| packet.hold_time_at(now) <= duration);
+        self.update_backpressure();
         before_len - self.waiting_packets.len()
     }
+
+    /// Drops all waiting packets without sending them. Returns the number of
+    /// packets that were dropped.
+    pub fn flush_waiting_packets(&mut self) -> usize {
+        let dropped = self.waiting_packets.len();
+        self.waiting_packets.clear();
+        self.update_backpressure();
+        self.dropped += dropped as u64;
+        dropped
+    }
+
+    /// The total number of packets dropped by flushes
+    pub fn dropped_packets(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Loads waiting packets from the given file into the store. A missing
+    /// file is treated as an empty store. Returns the number of packets loaded.
+    pub fn load(&mut self, path: &Path) -> Result<usize> {
+        let data = match fs::read(path) {
+            Ok(data) => data,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(err) => return Err(err.into()),
+        };
+        self.load_bytes(&data)
+    }
+
+    /// Encodes waiting packets as a sequence of hold time (in millis) and
+    /// length delimited packet pairs, compressed with zstd if enabled.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut buf = vec![];
+        for packet in &self.waiting_packets {
+            buf.put_u64(packet.hold_time().as_millis() as u64);
+            packet.encode_length_delimited(&mut buf)?;
+        }
+        if self.compress {
+            zstd::stream::encode_all(&buf[..], 0).map_err(EncodeError::compress)
+        } else {
+            Ok(buf)
+        }
+    }
+
+    /// Decodes packets encoded by `to_bytes` into the store. Compressed data
+    /// is detected and decompressed transparently.
+    pub fn load_bytes(&mut self, data: &[u8]) -> Result<usize> {
+        let decompressed;
+        let mut buf = if data.starts_with(&ZSTD_MAGIC) {
+            decompressed = zstd::stream::decode_all(data).map_err(DecodeError::decompress)?;
+            &decompressed[..]
+        } else {
+            data
+        };
+        let now = Instant::now();
+        let mut loaded = 0;
+        while buf.has_remaining() {
+            if buf.remaining() < 8 {
+                return Err(DecodeError::prost_decode("truncated store hold time"));
+            }
+            let hold_time = Duration::from_millis(buf.get_u64());
+            let packet =
+                helium_proto::Packet::decode_length_delimited(&mut buf).map_err(Error::from)?;
+            let received = now.checked_sub(hold_time).unwrap_or(now);
+            let packet = Packet::from(packet);
+            let dedup_hash = self.dedup_hash(&packet);
+            self.push_waiting_packet(packet, None, received, dedup_hash);
+            loaded += 1;
+        }
+        Ok(loaded)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mk_store_settings() -> CacheSettings {
+        CacheSettings {
+            max_packets: 10,
+            store: None,
+            compress: false,
+            queue_time_buckets: vec![],
+            signature_cache: 0,
+            filter_duplicates: false,
+            hash_exclude: vec![],
+            max_rate: None,
+            flush_interval: None,
+            flush_changes: None,
+        }
+    }
+
+    fn mk_store(compress: bool) -> RouterStore {
+        RouterStore::new(&CacheSettings {
+            compress,
+            ..mk_store_settings()
+        })
+    }
+
+    fn mk_packet(payload: &[u8]) -> Packet {
+        helium_proto::Packet {
+            payload: payload.to_vec(),
+            timestamp: 42,
+            ..Default::default()
+        }
+        .into()
+    }
+
+    fn roundtrip(compress: bool) {
+        let mut store = mk_store(compress);
+        store
+            .store_waiting_packet(mk_packet(&[1, 2, 3]), Instant::now())
+            .expect("store packet");
+        store
+            .store_waiting_packet(mk_packet(&[4, 5, 6, 7]), Instant::now())
+            .expect("store packet");
+        let data = store.to_bytes().expect("encode store");
+        assert_eq!(compress, data.starts_with(&ZSTD_MAGIC));
+
+        // Load with the opposite setting to verify transparent decompression
+        let mut loaded = mk_store(!compress);
+        assert_eq!(2, loaded.load_bytes(&data).expect("decode store"));
+        let first = loaded.pop_waiting_packet().expect("first packet");
+        assert_eq!(&[1, 2, 3], first.payload());
+        assert_eq!(42, first.timestamp);
+        let second = loaded.pop_waiting_packet().expect("second packet");
+        assert_eq!(&[4, 5, 6, 7], second.payload());
+        assert!(loaded.pop_waiting_packet().is_none());
+    }
+
+    #[test]
+    fn roundtrip_uncompressed() {
+        roundtrip(false)
+    }
+
+    #[test]
+    fn roundtrip_compressed() {
+        roundtrip(true)
+    }
+
+    #[test]
+    fn gc_explicit_time() {
+        let mut store = mk_store(false);
+        let received = Instant::now();
+        store
+            .store_waiting_packet(mk_packet(&[1]), received)
+            .expect("store packet");
+        store
+            .store_waiting_packet(mk_packet(&[2]), received + Duration::from_secs(30))
+            .expect("store packet");
+
+        let now = received + Duration::from_secs(61);
+        let first = store.waiting_packets.front().expect("first packet");
+        assert_eq!(Duration::from_secs(61), first.hold_time_at(now));
+
+        assert_eq!(1, store.gc_waiting_packets_at(Duration::from_secs(60), now));
+        assert_eq!(1, store.waiting_packets_len());
+        let remaining = store.pop_waiting_packet().expect("remaining packet");
+        assert_eq!(&[2], remaining.payload());
+    }
+
+    #[test]
+    fn flush_drops_all() {
+        let mut store = mk_store(false);
+        for payload in 0..3 {
+            store
+                .store_waiting_packet(mk_packet(&[payload]), Instant::now())
+                .expect("store packet");
+        }
+        assert_eq!(3, store.flush_waiting_packets());
+        assert_eq!(0, store.waiting_packets_len());
+        assert_eq!(3, store.dropped_packets());
+
+        store
+            .store_waiting_packet(mk_packet(&[3]), Instant::now())
+            .expect("store packet");
+        assert_eq!(1, store.flush_waiting_packets());
+        assert_eq!(0, store.flush_waiting_packets());
+        assert_eq!(4, store.dropped_packets());
+    }
+
+    #[test]
+    fn backpressure_at_capacity() {
+        let mut store = mk_store(false);
+        for payload in 0..10 {
+            store
+                .store_waiting_packet(mk_packet(&[payload]), Instant::now())
+                .expect("store packet");
+        }
+        // At capacity without dropping yet
+        assert!(!store.backpressure());
+
+        store
+            .store_waiting_packet(mk_packet(&[10]), Instant::now())
+            .expect("store packet");
+        assert!(store.backpressure());
+        assert_eq!(10, store.waiting_packets_len());
+
+        store.flush_waiting_packets();
+        assert!(!store.backpressure());
+    }
+
+    #[test]
+    fn requeue_at_capacity() {
+        let mut store = RouterStore::new(&CacheSettings {
+            max_packets: 2,
+            ..mk_store_settings()
+        });
+        for payload in 0..2 {
+            store
+                .store_waiting_packet(mk_packet(&[payload]), Instant::now())
+                .expect("store packet");
+        }
+        let popped = store.pop_waiting_packet().expect("queued packet");
+        store.requeue_waiting_packet(popped);
+        assert_eq!(2, store.waiting_packets_len());
+        assert_eq!(0, store.failures().capacity);
+
+        // A packet queued while the oldest was out leaves no room for it
+        let popped = store.pop_waiting_packet().expect("queued packet");
+        store
+            .store_waiting_packet(mk_packet(&[2]), Instant::now())
+            .expect("store packet");
+        store.requeue_waiting_packet(popped);
+        assert_eq!(2, store.waiting_packets_len());
+        assert_eq!(1, store.failures().capacity);
+        assert!(store.backpressure());
+        let remaining: Vec<u8> = std::iter::from_fn(|| store.pop_waiting_packet())
+            .map(|packet| packet.payload[0])
+            .collect();
+        assert_eq!(vec![1, 2], remaining);
+    }
+
+    #[test]
+    fn failure_accounting() {
+        let now = Instant::now();
+        let mut store = RouterStore::new(&CacheSettings {
+            max_packets: 2,
+            max_rate: Some(3),
+            filter_duplicates: true,
+            ..mk_store_settings()
+        });
+        let failure = |result: Result| match result {
+            Err(Error::Store(err)) => err,
+            other => panic!("expected store error, got {other:?}"),
+        };
+
+        // Filtered: the same packet is already queued
+        store
+            .store_waiting_packet(mk_packet(&[1]), now)
+            .expect("store packet");
+        assert!(matches!(
+            failure(store.store_waiting_packet(mk_packet(&[1]), now)),
+            StoreError::Filtered
+        ));
+        assert_eq!(
+            StoreFailures {
+                filtered: 1,
+                ..Default::default()
+            },
+            store.failures()
+        );
+
+        // Capacity: a full queue drops its oldest packet
+        for payload in 2..4 {
+            store
+                .store_waiting_packet(mk_packet(&[payload]), now)
+                .expect("store packet");
+        }
+        assert_eq!(
+            StoreFailures {
+                filtered: 1,
+                capacity: 1,
+                ..Default::default()
+            },
+            store.failures()
+        );
+
+        // Rate limited: three packets were stored within the second
+        assert!(matches!(
+            failure(store.store_waiting_packet(mk_packet(&[4]), now)),
+            StoreError::RateLimited(3)
+        ));
+        assert_eq!(
+            StoreFailures {
+                filtered: 1,
+                capacity: 1,
+                rate_limited: 1,
+            },
+            store.failures()
+        );
+
+        // Capacity: a store without room rejects packets outright
+        let mut store = mk_store(false);
+        store.max_packets = 0;
+        assert!(matches!(
+            failure(store.store_waiting_packet(mk_packet(&[5]), now)),
+            StoreError::Capacity(0)
+        ));
+        assert_eq!(1, store.failures().capacity);
+        assert_eq!(0, store.waiting_packets_len());
+    }
+
+    #[test]
+    fn reception_keys() {
+        let now = Instant::now();
+        let mut store = RouterStore::new(&CacheSettings {
+            filter_duplicates: true,
+            hash_exclude: vec![PacketField::SignalStrength],
+            ..mk_store_settings()
+        });
+        let mk_reception = |signal_strength| -> Packet {
+            helium_proto::Packet {
+                payload: vec![1, 2, 3],
+                signal_strength,
+                ..Default::default()
+            }
+            .into()
+        };
+        store
+            .store_waiting_packet(mk_reception(-80.0), now)
+            .expect("store packet");
+        // Another reception differing only in an excluded field is the same
+        // packet to the router
+        assert!(store
+            .store_waiting_packet(mk_reception(-95.0), now)
+            .is_err());
+
+        // Replays and signatures are keyed by the full packet
+        let queued = store.pop_waiting_packet().expect("queued packet");
+        store
+            .store_waiting_packet(mk_reception(-95.0), now)
+            .expect("store packet");
+        let other = store.pop_waiting_packet().expect("other packet");
+        assert_ne!(queued.key(), other.key());
+
+        // Without the duplicate filter every reception is queued
+        let mut store = mk_store(false);
+        for signal_strength in [-80.0, -80.0] {
+            store
+                .store_waiting_packet(mk_reception(signal_strength), now)
+                .expect("store packet");
+        }
+        assert_eq!(2, store.waiting_packets_len());
+    }
+
+    #[test]
+    fn replay_after_reload() {
+        let now = Instant::now();
+        let mut replays = ReplayWindow::new(Duration::from_secs(60));
+        let mut store = mk_store(false);
+        store
+            .store_waiting_packet(mk_packet(&[1, 2, 3]), now)
+            .expect("store packet");
+        // Persisted before the packet was routed
+        let data = store.to_bytes().expect("encode store");
+        let routed = store.pop_waiting_packet().expect("routed packet");
+        replays.insert(routed.key(), now);
+
+        // A stale reload brings the routed packet back
+        let mut reloaded = mk_store(false);
+        reloaded.load_bytes(&data).expect("decode store");
+        let replayed = reloaded.pop_waiting_packet().expect("replayed packet");
+        assert!(replays.contains(&replayed.key(), now));
+
+        // A different reception of the same payload is not a replay
+        let mut other = mk_packet(&[1, 2, 3]).to_packet();
+        other.timestamp += 1;
+        reloaded
+            .store_waiting_packet(other.into(), now)
+            .expect("store packet");
+        let other = reloaded.pop_waiting_packet().expect("other packet");
+        assert!(!replays.contains(&other.key(), now));
+
+        // The window survives a restart
+        let mut restarted = ReplayWindow::new(Duration::from_secs(60));
+        let later = now + Duration::from_secs(30);
+        let data = replays.to_bytes(later);
+        assert_eq!(
+            1,
+            restarted.load_bytes(&data, later).expect("decode replays")
+        );
+        assert!(restarted.contains(&replayed.key(), later));
+        assert!(!restarted.contains(&other.key(), later));
+
+        // Replays are only remembered for the window
+        assert!(!replays.contains(&replayed.key(), now + Duration::from_secs(61)));
+        assert!(!restarted.contains(&replayed.key(), later + Duration::from_secs(31)));
+    }
 }
@@ -0,0 +1,74 @@
+use tokio::{
+    sync::{mpsc, oneshot},
+    task,
+};
+
+type Write = Box<dyn FnOnce() + Send>;
+
+/// Runs file writes off the async runtime. Writes run one at a time on the
+/// blocking thread pool, in the order they were requested, so a later store
+/// snapshot never gets overwritten by an earlier one.
+#[derive(Debug, Clone)]
+pub struct FileWriter {
+    writes: mpsc::UnboundedSender<Write>,
+}
+
+impl FileWriter {
+    /// Creates a writer and spawns its task. The task ends once all clones
+    /// of the writer are dropped and the requested writes are done.
+    pub fn spawn() -> Self {
+        let (writes, mut writes_rx) = mpsc::unbounded_channel::<Write>();
+        tokio::spawn(async move {
+            while let Some(write) = writes_rx.recv().await {
+                // Writes log their own failures, a panicked one has nothing
+                // left to report
+                let _ = task::spawn_blocking(write).await;
+            }
+        });
+        Self { writes }
+    }
+
+    /// Queues the given blocking write. The write is expected to report its
+    /// own errors.
+    pub fn write<F>(&self, write: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        // The task only ends once every sender is gone
+        let _ = self.writes.send(Box::new(write));
+    }
+
+    /// Waits for all writes requested so far to finish
+    pub async fn sync(&self) {
+        let (done, done_rx) = oneshot::channel();
+        self.write(move || {
+            let _ = done.send(());
+        });
+        let _ = done_rx.await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn writes_in_order() {
+        let writer = FileWriter::spawn();
+        let written = Arc::new(Mutex::new(vec![]));
+        for n in 0..10 {
+            let written = written.clone();
+            writer.write(move || {
+                // Earlier writes taking longer must not be overtaken
+                std::thread::sleep(std::time::Duration::from_millis(10 - n));
+                written.lock().expect("written").push(n);
+            });
+        }
+        writer.sync().await;
+        assert_eq!(
+            (0..10).collect::<Vec<_>>(),
+            *written.lock().expect("written")
+        );
+    }
+}
@@ -1,6 +1,6 @@
 use crate::{
     api::LocalServer,
-    beaconer, gateway,
+    beaconer, gateway, health,
     router::{dispatcher, Dispatcher},
     settings::{self, Settings},
     updater::Updater,
@@ -12,16 +12,29 @@ pub async fn run(shutdown: &triggered::Listener, settings: &Settings, logger: &L
     let (gateway_tx, gateway_rx) = gateway::message_channel(10);
     let (dispatcher_tx, dispatcher_rx) = dispatcher::message_channel(20);
     let (beaconing_tx, beaconing_rx) = beaconer::message_channel(10);
+    let (health_tx, health_rx) = health::health_channel();
     let mut beaconer = beaconer::Beaconer::new(settings, gateway_tx.clone(), beaconing_rx);
-    let mut dispatcher = Dispatcher::new(dispatcher_rx, gateway_tx, settings)?;
-    let mut gateway =
-        gateway::Gateway::new(dispatcher_tx.clone(), gateway_rx, beaconing_tx, settings).await?;
+    let mut dispatcher = Dispatcher::new(dispatcher_rx, gateway_tx, health_tx, settings)?;
+    let mut gateway = gateway::Gateway::new(
+        dispatcher_tx.clone(),
+        gateway_rx,
+        beaconing_tx,
+        settings,
+        shutdown,
+        logger,
+    )
+    .await?;
     let updater = Updater::new(settings)?;
-    let api = LocalServer::new(dispatcher_tx, settings)?;
+    let api = LocalServer::new(
+        dispatcher_tx,
+        health_rx,
+        gateway.downlink_schedule(),
+        settings,
+    )?;
     info!(logger,
         "starting server";
         "version" => settings::version().to_string(),
-        "key" => settings.keypair.public_key().to_string(),
+        settings.summary(),
     );
     tokio::try_join!(
         beaconer.run(shutdown.clone(), logger),
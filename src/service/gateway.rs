@@ -1,10 +1,10 @@
 use crate::{
-    service::{CONNECT_TIMEOUT, RPC_TIMEOUT},
+    service::{endpoint, CONNECT_TIMEOUT, RPC_TIMEOUT},
     Error, KeyedUri, Keypair, MsgSign, MsgVerify, PublicKey, Region, RegionParams, Result,
 };
 use helium_proto::{
     gateway_resp_v1,
-    services::{self, Channel, Endpoint},
+    services::{self, Channel},
     BlockchainVarV1, GatewayConfigReqV1, GatewayConfigRespV1, GatewayRegionParamsReqV1,
     GatewayRegionParamsUpdateReqV1, GatewayRespV1, GatewayRoutingReqV1, GatewayScIsActiveReqV1,
     GatewayScIsActiveRespV1, GatewayValidatorsReqV1, GatewayValidatorsRespV1, GatewayVersionReqV1,
@@ -85,7 +85,7 @@ pub struct GatewayService {
 
 impl GatewayService {
     pub fn new(keyed_uri: &KeyedUri) -> Result<Self> {
-        let channel = Endpoint::from(keyed_uri.uri.clone())
+        let channel = endpoint(&keyed_uri.uri)?
             .connect_timeout(CONNECT_TIMEOUT)
             .timeout(RPC_TIMEOUT)
             .connect_lazy();
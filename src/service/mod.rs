@@ -1,8 +1,24 @@
+use crate::Result;
+use helium_proto::services::Endpoint;
+use http::Uri;
 use std::time::Duration;
 
 pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
 pub const RPC_TIMEOUT: Duration = Duration::from_secs(5);
 
+/// The endpoint for the given uri. Connections to https uris use TLS when
+/// built with the tls feature.
+pub fn endpoint(uri: &Uri) -> Result<Endpoint> {
+    let endpoint = Endpoint::from(uri.clone());
+    #[cfg(feature = "tls")]
+    if uri.scheme() == Some(&http::uri::Scheme::HTTPS) {
+        return endpoint
+            .tls_config(tonic::transport::ClientTlsConfig::new())
+            .map_err(|err| crate::Error::custom(format!("invalid tls config for {uri}: {err}")));
+    }
+    Ok(endpoint)
+}
+
 pub mod entropy;
 pub mod gateway;
 pub mod poc;
@@ -1,36 +1,716 @@
 use crate::{
-    service::{CONNECT_TIMEOUT, RPC_TIMEOUT},
-    KeyedUri, Result,
+    service::{endpoint, CONNECT_TIMEOUT, RPC_TIMEOUT},
+    settings::RouterAuthToken,
+    Error, KeyedUri, Result, RouterSettings,
 };
+use futures::TryFutureExt;
+use helium_crypto::PublicKey;
 use helium_proto::{
     services::{self, Channel, Endpoint},
     BlockchainStateChannelMessageV1,
 };
+use http::Uri;
+use hyper::client::HttpConnector;
+use std::{
+    collections::HashMap,
+    fmt,
+    path::PathBuf,
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpStream, UnixStream},
+    sync::{OwnedSemaphorePermit, Semaphore},
+    time,
+};
+use tonic::metadata::{Ascii, MetadataValue};
+use tower::{service_fn, ServiceExt};
 
 type RouterClient = services::router::RouterClient<Channel>;
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// The uri scheme used to route over a unix domain socket. The socket path is
+/// taken from the uri path, for example `unix://localhost/var/run/router.sock`
+pub const UNIX_SCHEME: &str = "unix";
+
+/// Maximum size in bytes of the response header of a proxy to a CONNECT
+/// request
+const MAX_PROXY_RESPONSE: usize = 8192;
+
+/// The request metadata key reporting the gateway crate version to routers
+pub const VERSION_METADATA: &str = "x-gateway-version";
+/// The request metadata key reporting the gateway platform to routers
+pub const PLATFORM_METADATA: &str = "x-gateway-platform";
+/// The request metadata key carrying the configured bearer token of a router
+pub const AUTHORIZATION_METADATA: &str = "authorization";
+
+/// Bearer tokens for routers that require authentication, by router public
+/// key. Clones share the same tokens, so tokens replaced from the router
+/// reload file apply to the next request of every router service.
+///
+/// Token values are marked sensitive and never show in debug output.
+#[derive(Clone, Default)]
+pub struct AuthTokens(Arc<RwLock<HashMap<PublicKey, MetadataValue<Ascii>>>>);
+
+impl AuthTokens {
+    pub fn new(settings: &RouterSettings) -> Self {
+        let tokens = Self::default();
+        tokens.reload(&settings.auth_tokens);
+        tokens
+    }
+
+    /// Replaces the tokens with the given ones. Tokens that are not valid in
+    /// request metadata are ignored.
+    pub fn reload(&self, tokens: &[RouterAuthToken]) {
+        let tokens = tokens
+            .iter()
+            .filter_map(|auth| {
+                let token = format!("Bearer {}", auth.token.expose());
+                let mut value = MetadataValue::try_from(token.as_str()).ok()?;
+                value.set_sensitive(true);
+                Some((auth.pubkey.as_ref().clone(), value))
+            })
+            .collect();
+        *self.0.write().expect("auth tokens lock") = tokens;
+    }
+
+    fn get(&self, pubkey: &PublicKey) -> Option<MetadataValue<Ascii>> {
+        self.0
+            .read()
+            .expect("auth tokens lock")
+            .get(pubkey)
+            .cloned()
+    }
+}
+
+impl fmt::Debug for AuthTokens {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let tokens = self.0.read().expect("auth tokens lock");
+        f.debug_set()
+            .entries(tokens.keys().map(|pubkey| pubkey.to_string()))
+            .finish()
+    }
+}
+
+/// Caps the number of router services, and with that router connections,
+/// that can exist at the same time. Clones share the same limit.
+#[derive(Debug, Clone, Default)]
+pub struct ConnectionLimit(Option<(usize, Arc<Semaphore>)>);
+
+impl ConnectionLimit {
+    /// Construct a limit of at most `max` connections, or no limit if `None`
+    pub fn new(max: Option<usize>) -> Self {
+        Self(max.map(|max| (max, Arc::new(Semaphore::new(max)))))
+    }
+
+    fn acquire(&self) -> Result<Option<OwnedSemaphorePermit>> {
+        match &self.0 {
+            None => Ok(None),
+            Some((max, slots)) => slots
+                .clone()
+                .try_acquire_owned()
+                .map(Some)
+                .map_err(|_| Error::connection_limit(*max)),
+        }
+    }
+}
+
+/// Spaces out the connection attempts of a router service by a minimum
+/// interval, so a router that drops connections right after accepting them
+/// is not reconnected to in a hot loop. Clones share the last attempt.
+#[derive(Debug, Clone, Default)]
+struct ReconnectThrottle {
+    min_interval: Duration,
+    next_connect: Arc<Mutex<Option<Instant>>>,
+}
+
+impl ReconnectThrottle {
+    fn new(min_interval: Duration) -> Self {
+        Self {
+            min_interval,
+            next_connect: Arc::default(),
+        }
+    }
+
+    /// Reserves the next connection attempt at the given time, returning how
+    /// long to wait for it
+    fn reserve(&self, now: Instant) -> Duration {
+        let mut next_connect = self.next_connect.lock().expect("reconnect throttle lock");
+        let connect = next_connect.map_or(now, |next| next.max(now));
+        *next_connect = Some(connect + self.min_interval);
+        connect - now
+    }
+
+    async fn wait(&self) {
+        let wait = self.reserve(Instant::now());
+        if !wait.is_zero() {
+            time::sleep(wait).await;
+        }
+    }
+}
+
+/// Transport configuration shared by all router connections
+#[derive(Debug, Clone)]
+pub struct RouterTransport {
+    limit: ConnectionLimit,
+    reconnect_min_interval: Duration,
+    nodelay: bool,
+    keepalive: Option<Duration>,
+    proxy: Option<Uri>,
+    timeout: Duration,
+    platform: Option<MetadataValue<Ascii>>,
+    auth_tokens: AuthTokens,
+}
+
+impl Default for RouterTransport {
+    fn default() -> Self {
+        Self {
+            limit: ConnectionLimit::default(),
+            reconnect_min_interval: Duration::ZERO,
+            nodelay: false,
+            keepalive: None,
+            proxy: None,
+            timeout: RPC_TIMEOUT,
+            platform: None,
+            auth_tokens: AuthTokens::default(),
+        }
+    }
+}
+
+impl RouterTransport {
+    pub fn new(settings: &RouterSettings) -> Self {
+        Self {
+            limit: ConnectionLimit::new(settings.max_connections),
+            reconnect_min_interval: settings.reconnect_min_interval(),
+            nodelay: settings.tcp_nodelay,
+            keepalive: settings.tcp_keepalive(),
+            proxy: settings.proxy.clone(),
+            timeout: settings.route_timeout(),
+            platform: None,
+            auth_tokens: AuthTokens::new(settings),
+        }
+    }
+
+    /// The router auth tokens, shared with all router services created from
+    /// this transport
+    pub fn auth_tokens(&self) -> &AuthTokens {
+        &self.auth_tokens
+    }
+
+    /// Reports the given platform on router requests. Not reported if the
+    /// platform is not valid in request metadata.
+    pub fn with_platform(self, platform: &str) -> Self {
+        Self {
+            platform: MetadataValue::try_from(platform).ok(),
+            ..self
+        }
+    }
+
+    /// The tcp connector for router connections with the configured socket
+    /// options applied
+    fn http_connector(&self) -> HttpConnector {
+        let mut connector = HttpConnector::new();
+        connector.enforce_http(false);
+        connector.set_nodelay(self.nodelay);
+        connector.set_keepalive(self.keepalive);
+        connector.set_connect_timeout(Some(CONNECT_TIMEOUT));
+        connector
+    }
+}
 
 #[derive(Debug)]
 pub struct RouterService {
     pub uri: KeyedUri,
     router_client: RouterClient,
+    timeout: Duration,
+    platform: Option<MetadataValue<Ascii>>,
+    auth_tokens: AuthTokens,
+    // Held for the lifetime of the service to count against the limit
+    _permit: Option<OwnedSemaphorePermit>,
 }
 
 impl RouterService {
-    pub fn new(keyed_uri: KeyedUri) -> Result<Self> {
-        let router_channel = Endpoint::from(keyed_uri.uri.clone())
-            .timeout(RPC_TIMEOUT)
-            .connect_timeout(CONNECT_TIMEOUT)
-            .connect_lazy();
+    /// Construct a router service for the given uri. Fails if the connection
+    /// limit has been reached. The service connects lazily and reconnects
+    /// on demand, at most once every reconnect minimum interval. Tcp
+    /// connections are tunneled through the proxy if one is configured.
+    pub fn new(keyed_uri: KeyedUri, transport: &RouterTransport) -> Result<Self> {
+        let permit = transport.limit.acquire()?;
+        let throttle = ReconnectThrottle::new(transport.reconnect_min_interval);
+        let router_channel = match unix_socket_path(&keyed_uri.uri) {
+            // The endpoint uri is not used to connect over a unix socket but
+            // still needs to be a valid http uri for requests
+            Some(path) => Endpoint::from_static("http://localhost")
+                .connect_timeout(CONNECT_TIMEOUT)
+                .connect_with_connector_lazy(service_fn(move |_: Uri| {
+                    let (path, throttle) = (path.clone(), throttle.clone());
+                    async move {
+                        throttle.wait().await;
+                        UnixStream::connect(path).await
+                    }
+                })),
+            None => {
+                let (connector, proxy) = (transport.http_connector(), transport.proxy.clone());
+                endpoint(&keyed_uri.uri)?
+                    .connect_timeout(CONNECT_TIMEOUT)
+                    .connect_with_connector_lazy(service_fn(move |uri: Uri| {
+                        let (connector, throttle) = (connector.clone(), throttle.clone());
+                        let proxy = proxy.clone();
+                        async move {
+                            throttle.wait().await;
+                            match proxy {
+                                Some(proxy) => connect_proxy(connector, proxy, uri).await,
+                                None => connector.oneshot(uri).await.map_err(BoxError::from),
+                            }
+                        }
+                    }))
+            }
+        };
         Ok(Self {
             uri: keyed_uri,
             router_client: RouterClient::new(router_channel),
+            timeout: transport.timeout,
+            platform: transport.platform.clone(),
+            auth_tokens: transport.auth_tokens.clone(),
+            _permit: permit,
         })
     }
 
+    /// Routes a message to the router. Fails with a timeout error if the
+    /// router does not respond within the configured route timeout. A state
+    /// channel offer and its accept or purchase are a single route call, so
+    /// the timeout covers the whole state channel handshake.
     pub async fn route(
         &mut self,
         msg: BlockchainStateChannelMessageV1,
     ) -> Result<BlockchainStateChannelMessageV1> {
-        Ok(self.router_client.route(msg).await?.into_inner())
+        let request = self.request(msg);
+        let route = self
+            .router_client
+            .route(request)
+            .map_ok(|response| response.into_inner())
+            .map_err(Error::from);
+        time::timeout(self.timeout, route)
+            .await
+            .map_err(|_| Error::timeout(self.timeout))?
+    }
+
+    /// Wraps a message in a request carrying the gateway version metadata,
+    /// and the auth token of the router if one is configured
+    fn request<T>(&self, msg: T) -> tonic::Request<T> {
+        let mut request = tonic::Request::new(msg);
+        let metadata = request.metadata_mut();
+        metadata.insert(
+            VERSION_METADATA,
+            MetadataValue::from_static(env!("CARGO_PKG_VERSION")),
+        );
+        if let Some(platform) = &self.platform {
+            metadata.insert(PLATFORM_METADATA, platform.clone());
+        }
+        if let Some(token) = self.auth_tokens.get(&self.uri.pubkey) {
+            metadata.insert(AUTHORIZATION_METADATA, token);
+        }
+        request
+    }
+}
+
+/// Opens a tunnel to the given uri through an HTTP proxy with a CONNECT
+/// request
+async fn connect_proxy(
+    connector: HttpConnector,
+    proxy: Uri,
+    uri: Uri,
+) -> std::result::Result<TcpStream, BoxError> {
+    let authority = uri.authority().ok_or("router uri without host")?;
+    let default_port = if uri.scheme() == Some(&http::uri::Scheme::HTTPS) {
+        443
+    } else {
+        80
+    };
+    let target = format!(
+        "{}:{}",
+        authority.host(),
+        authority.port_u16().unwrap_or(default_port)
+    );
+    let mut stream = connector.oneshot(proxy).await?;
+    stream
+        .write_all(format!("CONNECT {target} HTTP/1.1\r\nHost: {target}\r\n\r\n").as_bytes())
+        .await?;
+    // Read the response header a byte at a time so nothing sent through the
+    // tunnel is consumed
+    let mut response = Vec::new();
+    while !response.ends_with(b"\r\n\r\n") {
+        if response.len() >= MAX_PROXY_RESPONSE {
+            return Err("proxy response header too long".into());
+        }
+        response.push(stream.read_u8().await?);
+    }
+    if response.split(|byte| *byte == b' ').nth(1) != Some(&b"200"[..]) {
+        let status = String::from_utf8_lossy(&response);
+        let status = status.lines().next().unwrap_or_default();
+        return Err(format!("proxy refused tunnel to {target}: {status}").into());
+    }
+    Ok(stream)
+}
+
+/// Returns the socket path for a `unix` scheme uri
+fn unix_socket_path(uri: &Uri) -> Option<PathBuf> {
+    match uri.scheme_str() {
+        Some(UNIX_SCHEME) => Some(PathBuf::from(uri.path())),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keyed_uri::mk_test_uri;
+
+    #[test]
+    fn unix_uri() {
+        let uri = Uri::from_static("unix://localhost/var/run/router.sock");
+        assert_eq!(
+            Some(PathBuf::from("/var/run/router.sock")),
+            unix_socket_path(&uri)
+        );
+        let uri = Uri::from_static("http://127.0.0.1:8080");
+        assert_eq!(None, unix_socket_path(&uri));
+    }
+
+    #[test]
+    fn reconnect_throttle() {
+        let throttle = ReconnectThrottle::new(Duration::from_millis(500));
+        let start = Instant::now();
+        // First connect is never throttled
+        assert_eq!(Duration::ZERO, throttle.reserve(start));
+
+        // A flapping router that drops right after connecting has to wait
+        // out the remainder of the interval, and attempts queued behind it
+        // are spaced out too
+        let dropped = start + Duration::from_millis(100);
+        assert_eq!(Duration::from_millis(400), throttle.reserve(dropped));
+        assert_eq!(Duration::from_millis(900), throttle.reserve(dropped));
+
+        // Once the interval has passed reconnects are allowed again
+        let later = start + Duration::from_millis(1_600);
+        assert_eq!(Duration::ZERO, throttle.reserve(later));
+
+        // Without an interval nothing waits
+        let unthrottled = ReconnectThrottle::default();
+        assert_eq!(Duration::ZERO, unthrottled.reserve(start));
+        assert_eq!(Duration::ZERO, unthrottled.reserve(start));
+    }
+
+    #[tokio::test]
+    async fn flapping_router_throttled() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        // A router that drops every connection right after accepting it
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("listener");
+        let addr = listener.local_addr().expect("listener address");
+        let accepted = Arc::new(AtomicUsize::new(0));
+        let accepts = accepted.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                accepts.fetch_add(1, Ordering::SeqCst);
+                drop(stream);
+            }
+        });
+
+        let transport = RouterTransport::new(&RouterSettings {
+            reconnect_min_interval: 200,
+            ..Default::default()
+        });
+        let uri = KeyedUri {
+            uri: format!("http://{addr}").parse().expect("router uri"),
+            ..mk_uri()
+        };
+        let mut service = RouterService::new(uri, &transport).expect("router");
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_millis(500) {
+            assert!(service
+                .route(BlockchainStateChannelMessageV1::default())
+                .await
+                .is_err());
+        }
+        // Connects at 0, 200 and 400ms, and one more may be waiting
+        let accepted = accepted.load(Ordering::SeqCst);
+        assert!((1..=4).contains(&accepted), "{accepted}");
+    }
+
+    #[tokio::test]
+    async fn route_over_unix_socket() {
+        use crate::router::loopback::LoopbackRouter;
+        use futures::stream;
+        use helium_proto::{
+            blockchain_state_channel_message_v1::Msg, BlockchainStateChannelPacketV1,
+        };
+
+        let path =
+            std::env::temp_dir().join(format!("gateway-rs-router-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = tokio::net::UnixListener::bind(&path).expect("unix listener");
+        let incoming = stream::unfold(listener, |listener| async move {
+            let stream = listener.accept().await.map(|(stream, _)| stream);
+            Some((stream, listener))
+        });
+        let (trigger, shutdown) = triggered::trigger();
+        let router = tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(services::router::Server::new(LoopbackRouter))
+                .serve_with_incoming_shutdown(incoming, shutdown),
+        );
+
+        let uri = KeyedUri {
+            uri: format!("{UNIX_SCHEME}://localhost{}", path.display())
+                .parse()
+                .expect("router uri"),
+            ..mk_uri()
+        };
+        let mut service = RouterService::new(uri, &RouterTransport::default()).expect("router");
+        let packet = helium_proto::Packet {
+            payload: vec![1, 2, 3],
+            timestamp: 1_000,
+            ..Default::default()
+        };
+        let response = service
+            .route(BlockchainStateChannelMessageV1 {
+                msg: Some(Msg::Packet(BlockchainStateChannelPacketV1 {
+                    packet: Some(packet),
+                    ..Default::default()
+                })),
+            })
+            .await
+            .expect("routed over unix socket");
+        let downlink = match response.msg {
+            Some(Msg::Response(response)) => response.downlink.expect("downlink"),
+            other => panic!("unexpected response {other:?}"),
+        };
+        assert_eq!(vec![1, 2, 3], downlink.payload);
+
+        trigger.trigger();
+        router.await.expect("router task").expect("router shutdown");
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn route_through_proxy() {
+        use crate::router::loopback::LoopbackRouter;
+        use futures::stream;
+        use helium_proto::{
+            blockchain_state_channel_message_v1::Msg, BlockchainStateChannelPacketV1,
+        };
+        use tokio::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("router");
+        let router_addr = listener.local_addr().expect("router address");
+        let incoming = stream::unfold(listener, |listener| async move {
+            let stream = listener.accept().await.map(|(stream, _)| stream);
+            Some((stream, listener))
+        });
+        let (trigger, shutdown) = triggered::trigger();
+        let router = tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(services::router::Server::new(LoopbackRouter))
+                .serve_with_incoming_shutdown(incoming, shutdown),
+        );
+
+        // A proxy that tunnels every CONNECT request to the router and
+        // refuses once asked to
+        let proxy = TcpListener::bind("127.0.0.1:0").await.expect("proxy");
+        let proxy_addr = proxy.local_addr().expect("proxy address");
+        let (targets, mut targets_rx) = tokio::sync::mpsc::unbounded_channel();
+        let refuse = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let refused = refuse.clone();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = proxy.accept().await {
+                let mut request = Vec::new();
+                while !request.ends_with(b"\r\n\r\n") {
+                    request.push(stream.read_u8().await.expect("proxy request"));
+                }
+                let request = String::from_utf8(request).expect("proxy request");
+                let _ = targets.send(request.lines().next().unwrap_or_default().to_string());
+                if refused.load(std::sync::atomic::Ordering::SeqCst) {
+                    let _ = stream.write_all(b"HTTP/1.1 403 Forbidden\r\n\r\n").await;
+                    continue;
+                }
+                let mut upstream = TcpStream::connect(router_addr).await.expect("upstream");
+                stream
+                    .write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")
+                    .await
+                    .expect("proxy response");
+                tokio::spawn(async move {
+                    let _ = tokio::io::copy_bidirectional(&mut stream, &mut upstream).await;
+                });
+            }
+        });
+
+        let transport = RouterTransport::new(&RouterSettings {
+            proxy: Some(format!("http://{proxy_addr}").parse().expect("proxy uri")),
+            ..Default::default()
+        });
+        // The router host only resolves at the proxy
+        let uri = KeyedUri {
+            uri: format!("http://router.invalid:{}", router_addr.port())
+                .parse()
+                .expect("router uri"),
+            ..mk_uri()
+        };
+        let msg = BlockchainStateChannelMessageV1 {
+            msg: Some(Msg::Packet(BlockchainStateChannelPacketV1 {
+                packet: Some(helium_proto::Packet {
+                    payload: vec![1, 2, 3],
+                    timestamp: 1_000,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            })),
+        };
+        let mut service = RouterService::new(uri.clone(), &transport).expect("router");
+        let response = service
+            .route(msg.clone())
+            .await
+            .expect("routed through proxy");
+        assert!(matches!(response.msg, Some(Msg::Response(_))));
+        assert_eq!(
+            Some(format!(
+                "CONNECT router.invalid:{} HTTP/1.1",
+                router_addr.port()
+            )),
+            targets_rx.recv().await
+        );
+
+        refuse.store(true, std::sync::atomic::Ordering::SeqCst);
+        let mut service = RouterService::new(uri, &transport).expect("router");
+        assert!(service.route(msg).await.is_err());
+
+        trigger.trigger();
+        router.await.expect("router task").expect("router shutdown");
+    }
+
+    fn mk_uri() -> KeyedUri {
+        mk_test_uri("http://127.0.0.1:8080")
+    }
+
+    #[tokio::test]
+    async fn version_metadata() {
+        let transport = RouterTransport::default().with_platform("raspi01");
+        let service = RouterService::new(mk_uri(), &transport).expect("router");
+        let request = service.request(BlockchainStateChannelMessageV1::default());
+        let metadata = request.metadata();
+        assert_eq!(
+            Some(env!("CARGO_PKG_VERSION")),
+            metadata.get(VERSION_METADATA).and_then(|v| v.to_str().ok())
+        );
+        assert_eq!(
+            Some("raspi01"),
+            metadata
+                .get(PLATFORM_METADATA)
+                .and_then(|v| v.to_str().ok())
+        );
+
+        // Without a platform only the version is reported
+        let service = RouterService::new(mk_uri(), &RouterTransport::default()).expect("router");
+        let request = service.request(BlockchainStateChannelMessageV1::default());
+        assert!(request.metadata().get(VERSION_METADATA).is_some());
+        assert!(request.metadata().get(PLATFORM_METADATA).is_none());
+    }
+
+    #[tokio::test]
+    async fn auth_token() {
+        let uri = mk_uri();
+        let mut settings = RouterSettings::default();
+        settings.auth_tokens.push(RouterAuthToken {
+            pubkey: uri.pubkey.clone(),
+            token: "s3cr3t-token".into(),
+        });
+        let transport = RouterTransport::new(&settings);
+        let service = RouterService::new(uri.clone(), &transport).expect("router");
+        let request = service.request(BlockchainStateChannelMessageV1::default());
+        assert_eq!(
+            Some("Bearer s3cr3t-token"),
+            request
+                .metadata()
+                .get(AUTHORIZATION_METADATA)
+                .and_then(|v| v.to_str().ok())
+        );
+
+        // The token does not show in logged values
+        let logged = [
+            format!("{service:?}"),
+            format!("{transport:?}"),
+            format!("{request:?}"),
+            format!("{settings:?}"),
+            serde_json::to_string(&settings).expect("export settings"),
+        ];
+        for logged in logged {
+            assert!(!logged.contains("s3cr3t-token"), "{logged}");
+        }
+
+        // Reloaded tokens apply to existing services
+        settings.auth_tokens[0].token = "rotated".into();
+        transport.auth_tokens().reload(&settings.auth_tokens);
+        let request = service.request(BlockchainStateChannelMessageV1::default());
+        assert_eq!(
+            Some("Bearer rotated"),
+            request
+                .metadata()
+                .get(AUTHORIZATION_METADATA)
+                .and_then(|v| v.to_str().ok())
+        );
+
+        // Routers without a token get no authorization metadata
+        let service = RouterService::new(mk_uri(), &transport).expect("router");
+        let request = service.request(BlockchainStateChannelMessageV1::default());
+        assert!(request.metadata().get(AUTHORIZATION_METADATA).is_none());
+    }
+
+    #[tokio::test]
+    async fn connection_limit() {
+        let uri = mk_uri();
+        let limit = RouterTransport {
+            limit: ConnectionLimit::new(Some(2)),
+            ..Default::default()
+        };
+        let first = RouterService::new(uri.clone(), &limit).expect("first router");
+        let _second = RouterService::new(uri.clone(), &limit).expect("second router");
+        assert!(RouterService::new(uri.clone(), &limit).is_err());
+
+        // Dropping a service frees its slot
+        drop(first);
+        RouterService::new(uri.clone(), &limit).expect("replacement router");
+
+        // No limit allows any number of services
+        let unlimited = RouterTransport::default();
+        let _services: Vec<RouterService> = (0..5)
+            .map(|_| RouterService::new(uri.clone(), &unlimited).expect("router"))
+            .collect();
+    }
+
+    #[tokio::test]
+    async fn tcp_socket_options() {
+        use tower::ServiceExt;
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("listener");
+        let addr = listener.local_addr().expect("listener address");
+        let transport = RouterTransport {
+            nodelay: true,
+            keepalive: Some(Duration::from_secs(30)),
+            ..Default::default()
+        };
+        let uri: Uri = format!("http://{addr}").parse().expect("uri");
+        let stream = transport
+            .http_connector()
+            .oneshot(uri)
+            .await
+            .expect("connected socket");
+        assert!(stream.nodelay().expect("nodelay"));
+        assert!(socket2::SockRef::from(&stream)
+            .keepalive()
+            .expect("keepalive"));
     }
 }
@@ -1,30 +1,71 @@
 use crate::{
-    api::GatewayStakingMode, releases, Error, KeyedUri, Keypair, PublicKey, Region, Result,
+    api::GatewayStakingMode,
+    gateway::{CollisionStrategy, DecodeStrictness, NoWindowPolicy},
+    releases,
+    router::{SelectionPolicy, UnknownRegionPolicy},
+    Error, KeyedUri, Keypair, PacketField, PublicKey, Region, Result, RetryPolicy, TimestampSource,
 };
 use config::{Config, Environment, File};
 use http::uri::Uri;
 pub use log_method::LogMethod;
-use serde::Deserialize;
-use std::{fmt, path::Path, str::FromStr, sync::Arc};
+use rust_decimal::Decimal;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::{
+    collections::HashMap,
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
 
 pub fn version() -> semver::Version {
     semver::Version::parse(env!("CARGO_PKG_VERSION")).expect("unable to parse version")
 }
 
 /// Settings are all the configuration parameters the service needs to operate.
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct Settings {
     /// The listen address to use for listening for the semtech UDP packet forwarder.
     /// Default "127.0.0.1:1680"
     #[serde(default = "default_listen")]
     pub listen: String,
+    /// Backoff between attempts to bind the listen address. Binding fails
+    /// once the attempts are exhausted (default 10 attempts from 1s up to
+    /// 30s)
+    #[serde(default = "default_listen_retry")]
+    pub listen_retry: RetryPolicy,
+    /// The time sources to stamp uplinks with, in order of preference. The
+    /// first available one of gps, counter and system is used. Default
+    /// ["gps", "counter", "system"]
+    #[serde(default = "default_uplink_time_sources")]
+    pub uplink_time_sources: Vec<TimestampSource>,
+    /// How decode errors of packet forwarder traffic are surfaced. Strict
+    /// logs them as warnings and raises alerts, lenient only counts them and
+    /// logs at debug level. Alerts are counted in the decode error metrics.
+    /// Default strict
+    #[serde(default)]
+    pub decode_strictness: DecodeStrictness,
+    /// Clock skew in milliseconds to tolerate in timing checks. The tolerance
+    /// is added as slack to the downlink lookahead, the expiry of queued
+    /// packets and the chain tip age, so small skews between the gateway,
+    /// packet forwarder and gateway service clocks do not cause spurious
+    /// drops (default 0)
+    #[serde(default)]
+    pub clock_skew: u64,
     /// The listening network port for the grpc / jsonrpc API.
     /// Default 4467
     #[serde(default = "default_api")]
     pub api: u16,
     /// The location of the keypair binary file for the gateway. If the keyfile
     /// is not found there a new one is generated and saved in that location.
+    /// Exported as the public key only.
+    #[serde(serialize_with = "serialize_redacted_keypair")]
     pub keypair: Arc<Keypair>,
+    /// Optional expected gateway address. Loading settings fails if the
+    /// public key of the keypair does not match it.
+    #[serde(default)]
+    pub address: Option<String>,
     /// The location of the onboarding keypair binary file for the gateway. If
     /// the keyfile is not found there a new one is generated and saved in that
     /// location.
@@ -32,6 +73,30 @@ pub struct Settings {
     /// The lorawan region to use. This value should line up with the configured
     /// region of the semtech packet forwarder. Defaults to "US915"
     pub region: Region,
+    /// Optionally infer the region from the frequencies of this many uplinks
+    /// on known channels and pin it as the region to request region params
+    /// for. Disabled if not set.
+    #[serde(default)]
+    pub infer_region: Option<u32>,
+    /// Regions to route the uplinks of specific packet forwarders with
+    /// instead of the gateway region, by packet forwarder MAC address.
+    /// Intended for testbeds where one gateway simulates several regions.
+    /// Overridden uplinks skip region inference. Empty by default.
+    #[serde(default)]
+    pub region_overrides: Vec<RegionOverride>,
+    /// Optional file to load static region params from instead of fetching
+    /// them from the gateway service. The file holds a protobuf encoded
+    /// region params response. Intended for offline and lab setups.
+    pub region_params: Option<PathBuf>,
+    /// Maximum number of region params fetches from the gateway service, or
+    /// from the static region params file, running at the same time.
+    /// Concurrent fetches of the same region share one fetch (default 1)
+    #[serde(default = "default_region_params_fetches")]
+    pub region_params_fetches: usize,
+    /// Optional maximum number of dispatcher messages to buffer beyond the
+    /// message channel size. When set, buffering grows up to this maximum
+    /// while the channel stays full. Disabled by default.
+    pub channel_overflow: Option<usize>,
     /// Log settings
     pub log: LogSettings,
     /// Update settings
@@ -44,12 +109,23 @@ pub struct Settings {
     pub gateways: Vec<KeyedUri>,
     /// Cache settings
     pub cache: CacheSettings,
+    /// Router and dispatcher settings
+    #[serde(default)]
+    pub router: RouterSettings,
     /// Proof-of-coverage (PoC) settings.
     pub poc: PocSettings,
+    /// Downlink settings
+    #[serde(default)]
+    pub downlink: DownlinkSettings,
+    /// Optional configured location of the gateway. Coordinates are validated
+    /// when settings are loaded. Uplinks are tagged with the location and it
+    /// is logged with each uplink. The state channel packets sent to routers
+    /// have no location field, so routers do not receive it.
+    pub location: Option<LocationSettings>,
 }
 
 /// Settings for log method and level to be used by the running service.
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct LogSettings {
     /// Log level to show (default info)
     pub level: log_level::Level,
@@ -59,10 +135,15 @@ pub struct LogSettings {
 
     /// Whehter to show timestamps in the stdio output stream (default false)
     pub timestamp: bool,
+
+    /// Whether to redact packet payloads in log output, keeping only their
+    /// size and hash (default false)
+    #[serde(default)]
+    pub redact_payload: bool,
 }
 
 /// Settings for log method and level to be used by the running service.
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct UpdateSettings {
     /// Whether the auto-update system is enabled (default: true)
     pub enabled: bool,
@@ -83,14 +164,502 @@ pub struct UpdateSettings {
 }
 
 /// Settings for cache storage
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct CacheSettings {
     // Maximum number of packets to queue up per router client
     pub max_packets: u16,
+    /// Optional folder to persist queued packets in across restarts. Each
+    /// router client stores its waiting packets in its own file, with the
+    /// keys of recently routed packets next to it to drop replays.
+    pub store: Option<PathBuf>,
+    /// Whether to zstd compress the persisted queue files (default false)
+    #[serde(default)]
+    pub compress: bool,
+    /// Upper bounds in milliseconds of the histogram buckets used to track how
+    /// long packets wait in the queue before being sent to a router.
+    #[serde(default = "default_queue_time_buckets")]
+    pub queue_time_buckets: Vec<u64>,
+    /// Maximum number of signed packets to keep per router client so that
+    /// retried packets do not need to be signed again. Zero disables the
+    /// cache (default 32)
+    #[serde(default = "default_signature_cache")]
+    pub signature_cache: usize,
+    /// Whether to not queue packets the same as an already queued one, as
+    /// compared by the packet hash (default false)
+    #[serde(default)]
+    pub filter_duplicates: bool,
+    /// Packet fields left out of the packet hash used to recognize other
+    /// receptions of an already queued packet when filtering duplicates, to
+    /// match the dedup hash of the routers. Replays and cached signatures are
+    /// always keyed by the full packet. One or more of signal_strength, snr,
+    /// timestamp, frequency and datarate (default none)
+    #[serde(default)]
+    pub hash_exclude: Vec<PacketField>,
+    /// Maximum number of packets per second to queue per router client.
+    /// Packets above the rate are not queued. Unlimited if not set
+    #[serde(default)]
+    pub max_rate: Option<u32>,
+    /// Seconds between writes of a changed persisted queue. Bounds flash
+    /// wear from writing on every change. Queues are also written on
+    /// shutdown
+    #[serde(default)]
+    pub flush_interval: Option<u64>,
+    /// Number of queue changes after which the persisted queue is written
+    /// without waiting for the flush interval. Written on every change if
+    /// neither this nor a flush interval is set
+    #[serde(default)]
+    pub flush_changes: Option<u32>,
+}
+
+impl CacheSettings {
+    pub fn flush_interval(&self) -> Option<Duration> {
+        self.flush_interval.map(Duration::from_secs)
+    }
+}
+
+/// Settings for the packet router dispatcher and router clients
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct RouterSettings {
+    /// Minimum time in milliseconds between connection attempts to a
+    /// router, so a router that drops connections right after accepting
+    /// them is not reconnected to in a hot loop (default 500)
+    #[serde(default = "default_reconnect_min_interval")]
+    pub reconnect_min_interval: u64,
+    /// Backoff between attempts to connect to a gateway service. Waits the
+    /// maximum delay between attempts once the attempts are exhausted
+    /// (default 10 attempts from 5s up to 30 minutes)
+    #[serde(default = "default_reconnect_retry")]
+    pub reconnect_retry: RetryPolicy,
+    /// Backoff between attempts to route queued packets to a router that
+    /// failed to take them. Waits the maximum delay between attempts once
+    /// the attempts are exhausted (default 10 attempts from 1s up to 60s)
+    #[serde(default = "default_route_retry")]
+    pub route_retry: RetryPolicy,
+    /// Backoff between attempts to sign a queued packet after a transient
+    /// signing failure. The packet is dropped once the attempts are
+    /// exhausted (default 3 attempts from 100ms up to 1s)
+    #[serde(default = "default_sign_retry")]
+    pub sign_retry: RetryPolicy,
+    /// Uplink rate in packets per second above which a warning is logged.
+    /// Disabled if not set.
+    #[serde(default)]
+    pub uplink_rate_alert: Option<u32>,
+    /// How uplinks are distributed over the routers that match them: fan_out,
+    /// failover, round_robin, consistent_hash or weighted (default fan_out)
+    #[serde(default)]
+    pub selection: SelectionPolicy,
+    /// Maximum number of router connections to keep at the same time. Routers
+    /// beyond the limit are refused until a slot frees up. Unlimited if not
+    /// set
+    #[serde(default)]
+    pub max_connections: Option<usize>,
+    /// Whether to set TCP_NODELAY on router connections (default true)
+    #[serde(default = "default_tcp_nodelay")]
+    pub tcp_nodelay: bool,
+    /// TCP keepalive idle time in seconds for router connections. Keepalive is
+    /// disabled if not set
+    #[serde(default)]
+    pub tcp_keepalive: Option<u64>,
+    /// Optional HTTP proxy to tunnel router connections through with CONNECT
+    /// requests, for example `http://proxy.local:3128`. Routers are
+    /// connected to directly if not set
+    #[serde(default, with = "http_serde::option::uri")]
+    pub proxy: Option<Uri>,
+    /// Time in milliseconds to wait for a router to respond to a routed
+    /// packet before the packet is requeued for a retry (default 5000). The
+    /// state channel offer and the router's accept or purchase are a single
+    /// route call in this client, so this also bounds the state channel
+    /// handshake. An expired handshake is retried like any failed route.
+    #[serde(default = "default_route_timeout")]
+    pub route_timeout: u64,
+    /// Maximum data credits to spend on packets to any one router within the
+    /// dc cap window. Routers at their cap are skipped while others remain.
+    /// Uncapped if not set
+    #[serde(default)]
+    pub dc_cap: Option<u64>,
+    /// Length in seconds of the window the per router dc cap applies to
+    /// (default 3600)
+    #[serde(default = "default_dc_cap_window")]
+    pub dc_cap_window: u64,
+    /// Maximum age in seconds of the chain tip, as last checked with the
+    /// gateway service, to route packets at. Router clients queue packets
+    /// while the tip is older and resume once it is fresh again. The tip is
+    /// checked every quarter of this age, between every 30 seconds and every
+    /// 15 minutes. Disabled if not set
+    #[serde(default)]
+    pub max_block_age: Option<u64>,
+    /// Optional folder to capture routed uplinks and received downlinks in.
+    /// Each router client appends to its own capture file. Disabled if not
+    /// set
+    #[serde(default)]
+    pub capture: Option<PathBuf>,
+    /// Size in bytes at which a capture file is rotated (default 10485760)
+    #[serde(default = "default_capture_max_size")]
+    pub capture_max_size: u64,
+    /// Number of consecutive empty gateway stream messages within the empty
+    /// message window that force a gateway reconnect. Disabled if not set
+    #[serde(default)]
+    pub empty_message_limit: Option<u32>,
+    /// Window in seconds for counting empty gateway stream messages (default
+    /// 10)
+    #[serde(default = "default_empty_message_window")]
+    pub empty_message_window: u64,
+    /// Interval in milliseconds to space out sends of queued packets to a
+    /// router by, jittered by up to half the interval either way. Disabled
+    /// if not set
+    #[serde(default)]
+    pub send_pacing: Option<u64>,
+    /// Time in milliseconds a packet may wait in a router queue before it is
+    /// attempted even while routing is paused by a stale chain tip or route
+    /// backoff, and without send pacing. Should be below the queue expiry of
+    /// a minute to take effect before packets are discarded. Disabled if not
+    /// set
+    #[serde(default)]
+    pub max_residence: Option<u64>,
+    /// Number of queued packets each router client signs concurrently in the
+    /// background while sending in queue order. Signs one packet at a time,
+    /// inline with sending, if not set
+    #[serde(default)]
+    pub signing_tasks: Option<usize>,
+    /// What to do with uplinks that arrive before region params are known:
+    /// route them with the configured region, hold them until region params
+    /// arrive, or drop them (default route)
+    #[serde(default)]
+    pub unknown_region: UnknownRegionPolicy,
+    /// Maximum number of uplinks held while the region is unknown. The oldest
+    /// held uplink is dropped beyond this (default 20)
+    #[serde(default = "default_unknown_region_hold")]
+    pub unknown_region_hold: usize,
+    /// Maximum time in milliseconds router clients hold routing of queued
+    /// packets while region params are refreshed after the region changed.
+    /// Routing resumes once new region params arrive (default 5000)
+    #[serde(default = "default_region_refresh_pause")]
+    pub region_refresh_pause: u64,
+    /// Bearer tokens to authenticate with routers that require one. Sent in
+    /// the authorization metadata of every request to the router with the
+    /// given public key. Redacted in logs and exported settings, and replaced
+    /// by the tokens in the reload file when it has any
+    #[serde(default)]
+    pub auth_tokens: Vec<RouterAuthToken>,
+    /// Relative weights of routers for the weighted selection policy. Routers
+    /// without a weight have weight 1. Replaced by the weights in the reload
+    /// file when it has any
+    #[serde(default)]
+    pub weights: Vec<RouterWeight>,
+    /// Path of a TOML file with `[[weights]]` and `[[auth_tokens]]` entries
+    /// like the router settings. Re-read when it changes, replacing the
+    /// weights and tokens in use with the sections it has, so they can be
+    /// changed without a restart (default none)
+    #[serde(default)]
+    pub reload_file: Option<PathBuf>,
+    /// Interval in seconds over which repeats of the same router client
+    /// warning are counted instead of logged, and then logged as one summary
+    /// with the count. Every warning is logged if not set
+    #[serde(default)]
+    pub log_repeat_interval: Option<u64>,
+}
+
+impl Default for RouterSettings {
+    fn default() -> Self {
+        Self {
+            reconnect_min_interval: default_reconnect_min_interval(),
+            reconnect_retry: default_reconnect_retry(),
+            route_retry: default_route_retry(),
+            sign_retry: default_sign_retry(),
+            uplink_rate_alert: None,
+            selection: SelectionPolicy::default(),
+            max_connections: None,
+            tcp_nodelay: default_tcp_nodelay(),
+            tcp_keepalive: None,
+            route_timeout: default_route_timeout(),
+            dc_cap: None,
+            dc_cap_window: default_dc_cap_window(),
+            max_block_age: None,
+            capture: None,
+            capture_max_size: default_capture_max_size(),
+            empty_message_limit: None,
+            empty_message_window: default_empty_message_window(),
+            send_pacing: None,
+            max_residence: None,
+            signing_tasks: None,
+            unknown_region: UnknownRegionPolicy::default(),
+            unknown_region_hold: default_unknown_region_hold(),
+            region_refresh_pause: default_region_refresh_pause(),
+            auth_tokens: vec![],
+            weights: vec![],
+            reload_file: None,
+            log_repeat_interval: None,
+        }
+    }
+}
+
+impl RouterSettings {
+    pub fn reconnect_min_interval(&self) -> Duration {
+        Duration::from_millis(self.reconnect_min_interval)
+    }
+
+    pub fn tcp_keepalive(&self) -> Option<Duration> {
+        self.tcp_keepalive.map(Duration::from_secs)
+    }
+
+    pub fn route_timeout(&self) -> Duration {
+        Duration::from_millis(self.route_timeout)
+    }
+
+    pub fn dc_cap_window(&self) -> Duration {
+        Duration::from_secs(self.dc_cap_window)
+    }
+
+    pub fn empty_message_window(&self) -> Duration {
+        Duration::from_secs(self.empty_message_window)
+    }
+
+    pub fn send_pacing(&self) -> Option<Duration> {
+        self.send_pacing.map(Duration::from_millis)
+    }
+
+    pub fn max_residence(&self) -> Option<Duration> {
+        self.max_residence.map(Duration::from_millis)
+    }
+
+    pub fn max_block_age(&self) -> Option<Duration> {
+        self.max_block_age.map(Duration::from_secs)
+    }
+
+    pub fn region_refresh_pause(&self) -> Duration {
+        Duration::from_millis(self.region_refresh_pause)
+    }
+
+    pub fn log_repeat_interval(&self) -> Option<Duration> {
+        self.log_repeat_interval.map(Duration::from_secs)
+    }
+}
+
+/// The auth token of the router with the given public key
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RouterAuthToken {
+    pub pubkey: Arc<PublicKey>,
+    pub token: AuthToken,
+}
+
+/// The selection weight of the router with the given public key
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RouterWeight {
+    pub pubkey: Arc<PublicKey>,
+    pub weight: u32,
+}
+
+/// The region to route the uplinks of the packet forwarder with the given MAC
+/// address with
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RegionOverride {
+    /// MAC address as shown in the gateway logs, compared case insensitively
+    pub mac: String,
+    pub region: Region,
+}
+
+/// A router auth token. Shows redacted in debug output and exported settings
+/// so the token never ends up in logs. The redacted placeholder is rejected
+/// when loading, so exported settings are not used with it as the token.
+#[derive(Clone, PartialEq, Eq)]
+pub struct AuthToken(String);
+
+impl AuthToken {
+    const REDACTED: &'static str = "<redacted>";
+
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<&str> for AuthToken {
+    fn from(token: &str) -> Self {
+        Self(token.to_string())
+    }
+}
+
+impl fmt::Debug for AuthToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(Self::REDACTED)
+    }
+}
+
+impl Serialize for AuthToken {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(Self::REDACTED)
+    }
+}
+
+impl<'de> Deserialize<'de> for AuthToken {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let token = String::deserialize(deserializer)?;
+        if token == Self::REDACTED {
+            return Err(de::Error::custom(
+                "auth token is redacted, set the actual token",
+            ));
+        }
+        Ok(Self(token))
+    }
+}
+
+/// Settings for downlink scheduling
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct DownlinkSettings {
+    /// Per region rx1 delay overrides in seconds, keyed by region name. Regions
+    /// without an override use the timing requested by the router.
+    #[serde(default)]
+    pub rx1_delay: HashMap<String, u64>,
+    /// Fixed transmit latency of the radio hardware in microseconds. Downlink
+    /// transmit times are moved earlier by this amount (default 0)
+    #[serde(default)]
+    pub tx_compensation: u64,
+    /// Maximum number of downlinks per second to pass to the packet
+    /// forwarder. Excess downlinks are dropped. Unlimited if not set
+    #[serde(default)]
+    pub max_rate: Option<u32>,
+    /// Maximum number of downlinks awaiting a transmit confirmation from the
+    /// packet forwarder at the same time. Further downlinks are dropped, since
+    /// waiting for a slot would miss their window. Unlimited if not set or
+    /// zero
+    #[serde(default)]
+    pub max_pending_confirmations: Option<usize>,
+    /// Minimum time in microseconds from the end of one scheduled downlink
+    /// transmission to the start of the next. A downlink too close to
+    /// another one moves to its rx2 window if that fits, and is dropped
+    /// otherwise. Disabled if not set
+    #[serde(default)]
+    pub min_gap: Option<u64>,
+    /// Which downlink to keep when a downlink collides with pending ones
+    /// within the minimum gap in both windows: keep_first,
+    /// keep_highest_priority or keep_earliest_window (default
+    /// keep_highest_priority). Pending downlinks already handed to the
+    /// packet forwarder are still transmitted, only their rx2 retry is
+    /// cancelled.
+    #[serde(default)]
+    pub collision: CollisionStrategy,
+    /// What to do with a downlink when neither of its windows has a usable
+    /// transmit time, frequency and datarate: drop or immediate (default
+    /// drop)
+    #[serde(default)]
+    pub no_window: NoWindowPolicy,
+    /// Maximum time in milliseconds a downlink may be scheduled ahead of the
+    /// latest uplink. Downlinks further out are dropped as implausible.
+    /// Disabled if not set
+    #[serde(default)]
+    pub max_lookahead: Option<u64>,
+    /// Maximum EIRP in dBm to clamp downlink transmit power to, overriding
+    /// the built-in regulatory limit of the region. Uses the regional limit
+    /// if not set
+    #[serde(default)]
+    pub max_eirp: Option<Decimal>,
+    /// RF chains of the concentrator that can transmit. Antenna mappings to
+    /// other RF chains are ignored (default [0])
+    #[serde(default = "default_tx_rf_chains")]
+    pub tx_rf_chains: Vec<u64>,
+    /// Optional mapping of receive antennas to transmit RF chains. When set,
+    /// a downlink to a device transmits on the RF chain mapped to the antenna
+    /// that received the latest uplink of the device the strongest. Other
+    /// downlinks, and all downlinks when not set, transmit on RF chain 0
+    #[serde(default)]
+    pub antenna_chains: Vec<AntennaChain>,
+}
+
+/// The RF chain to transmit downlinks on for devices heard the strongest on
+/// the given receive antenna
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+pub struct AntennaChain {
+    pub antenna: u64,
+    pub rfch: u64,
+}
+
+impl Default for DownlinkSettings {
+    fn default() -> Self {
+        Self {
+            rx1_delay: HashMap::new(),
+            tx_compensation: 0,
+            max_rate: None,
+            max_pending_confirmations: None,
+            min_gap: None,
+            collision: CollisionStrategy::default(),
+            no_window: NoWindowPolicy::default(),
+            max_lookahead: None,
+            max_eirp: None,
+            tx_rf_chains: default_tx_rf_chains(),
+            antenna_chains: vec![],
+        }
+    }
+}
+
+impl DownlinkSettings {
+    /// Returns the rx1 delay override for the given region if one is
+    /// configured.
+    pub fn rx1_delay(&self, region: &Region) -> Option<Duration> {
+        let region = region.to_string();
+        self.rx1_delay
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(&region))
+            .map(|(_, delay)| Duration::from_secs(*delay))
+    }
+
+    pub fn tx_compensation(&self) -> Duration {
+        Duration::from_micros(self.tx_compensation)
+    }
+
+    pub fn max_lookahead(&self) -> Option<Duration> {
+        self.max_lookahead.map(Duration::from_millis)
+    }
+}
+
+/// The configured location of the gateway.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(try_from = "UncheckedLocation")]
+pub struct LocationSettings {
+    /// Latitude in degrees, -90 to 90
+    pub lat: f64,
+    /// Longitude in degrees, -180 to 180
+    pub lon: f64,
+    /// Optional elevation in meters
+    pub elevation: Option<i32>,
+}
+
+#[derive(Deserialize)]
+struct UncheckedLocation {
+    lat: f64,
+    lon: f64,
+    #[serde(default)]
+    elevation: Option<i32>,
+}
+
+impl TryFrom<UncheckedLocation> for LocationSettings {
+    type Error = String;
+
+    fn try_from(v: UncheckedLocation) -> std::result::Result<Self, Self::Error> {
+        if !(-90.0..=90.0).contains(&v.lat) {
+            return Err(format!("invalid latitude: {}", v.lat));
+        }
+        if !(-180.0..=180.0).contains(&v.lon) {
+            return Err(format!("invalid longitude: {}", v.lon));
+        }
+        Ok(Self {
+            lat: v.lat,
+            lon: v.lon,
+            elevation: v.elevation,
+        })
+    }
+}
+
+impl fmt::Display for LocationSettings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{},{}", self.lat, self.lon)?;
+        if let Some(elevation) = self.elevation {
+            write!(f, ",{elevation}m")?;
+        }
+        Ok(())
+    }
 }
 
 /// Settings for proof-of-coverage (PoC).
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct PocSettings {
     /// Entropy URL.
     #[serde(with = "http_serde::uri")]
@@ -126,7 +695,82 @@ impl Settings {
             .add_source(Environment::with_prefix("gw").separator("_"))
             .build()
             .and_then(|config| config.try_deserialize())
-            .map_err(|e| e.into())
+            .map_err(Error::from)
+            .and_then(|settings: Self| settings.check_address().map(|_| settings))
+    }
+
+    /// A summary of the effective settings to log at startup
+    pub fn summary(&self) -> StartupSummary {
+        let uris = |uris: &[KeyedUri]| uris.iter().map(|uri| uri.uri.to_string()).collect();
+        let features = [
+            ("persistence", self.cache.store.is_some()),
+            ("compress", self.cache.compress),
+            ("capture", self.router.capture.is_some()),
+            ("dc_cap", self.router.dc_cap.is_some()),
+            ("max_block_age", self.router.max_block_age.is_some()),
+            ("send_pacing", self.router.send_pacing.is_some()),
+            ("infer_region", self.infer_region.is_some()),
+            ("location", self.location.is_some()),
+        ];
+        StartupSummary {
+            key: self.keypair.public_key().to_string(),
+            region: self.region,
+            region_params: self.region_params.clone(),
+            routers: self.routers.as_deref().map_or_else(Vec::new, uris),
+            gateways: uris(&self.gateways),
+            max_packets: self.cache.max_packets,
+            selection: self.router.selection,
+            features: features
+                .into_iter()
+                .filter(|(_, enabled)| *enabled)
+                .map(|(feature, _)| feature)
+                .collect(),
+        }
+    }
+
+    /// The optional features this build and configuration have enabled
+    pub fn capabilities(&self) -> Capabilities {
+        // pkcs11 signing is always built in
+        let mut hsm = vec!["pkcs11"];
+        if cfg!(feature = "ecc608") {
+            hsm.push("ecc608");
+        }
+        if cfg!(feature = "tpm") {
+            hsm.push("tpm");
+        }
+        Capabilities {
+            tls: cfg!(feature = "tls"),
+            proxy: self.router.proxy.is_some(),
+            persistence: self.cache.store.is_some(),
+            capture: self.router.capture.is_some(),
+            // Metrics are logged at debug level
+            metrics: slog::Level::Debug.is_at_least(self.log.level.into()),
+            fan_out: self.router.selection == SelectionPolicy::FanOut,
+            hsm,
+        }
+    }
+
+    pub fn clock_skew(&self) -> Duration {
+        Duration::from_millis(self.clock_skew)
+    }
+
+    /// Checks that the public key of the keypair matches the configured
+    /// gateway address, if one is configured. Routers reject packets signed
+    /// with an unexpected key, so a mismatch is reported up front.
+    pub fn check_address(&self) -> Result {
+        let address = match &self.address {
+            Some(address) => address,
+            None => return Ok(()),
+        };
+        let expected = PublicKey::from_str(address)
+            .map_err(|_| Error::custom(format!("invalid configured address: {address}")))?;
+        let actual = self.keypair.public_key();
+        if &expected != actual {
+            return Err(Error::custom(format!(
+                "keypair public key {actual} does not match configured address {expected}"
+            )));
+        }
+        Ok(())
     }
 
     /// Returns the onboarding key for this gateway. The onboarding key is
@@ -145,14 +789,174 @@ impl Settings {
     }
 }
 
+/// A summary of the effective settings, logged once at startup. The keypair
+/// is reduced to its public key.
+#[derive(Debug, Serialize)]
+pub struct StartupSummary {
+    pub key: String,
+    pub region: Region,
+    /// The static region params file overriding fetched region params
+    pub region_params: Option<PathBuf>,
+    /// Default router URIs
+    pub routers: Vec<String>,
+    /// Seed gateway service URIs
+    pub gateways: Vec<String>,
+    pub max_packets: u16,
+    pub selection: SelectionPolicy,
+    /// Names of the enabled optional features
+    pub features: Vec<&'static str>,
+}
+
+impl slog::KV for StartupSummary {
+    fn serialize(
+        &self,
+        _record: &slog::Record,
+        serializer: &mut dyn slog::Serializer,
+    ) -> slog::Result {
+        serializer.emit_str("key", &self.key)?;
+        serializer.emit_str("region", &self.region.to_string())?;
+        if let Some(region_params) = &self.region_params {
+            serializer.emit_str("region_params", &region_params.to_string_lossy())?;
+        }
+        serializer.emit_str("routers", &self.routers.join(","))?;
+        serializer.emit_str("gateways", &self.gateways.join(","))?;
+        serializer.emit_u16("max_packets", self.max_packets)?;
+        serializer.emit_str("selection", &format!("{:?}", self.selection))?;
+        serializer.emit_str("features", &self.features.join(","))
+    }
+}
+
+/// The optional features of this build and configuration, for tooling to
+/// adapt to
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Capabilities {
+    /// TLS router and gateway connections for https uris, built with the
+    /// tls feature
+    pub tls: bool,
+    /// Router connections tunneled through an HTTP proxy
+    pub proxy: bool,
+    /// Queued packets persisted across restarts
+    pub persistence: bool,
+    /// Routed packets captured to files
+    pub capture: bool,
+    /// In-process metrics, exported by logging them periodically at debug
+    /// level
+    pub metrics: bool,
+    /// Uplinks sent to every matching router
+    pub fan_out: bool,
+    /// The hardware keypair backends compiled in, pkcs11 included
+    pub hsm: Vec<&'static str>,
+}
+
+/// Serializes the keypair as its public key so exported settings never
+/// contain key material.
+fn serialize_redacted_keypair<S: Serializer>(
+    keypair: &Arc<Keypair>,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    serializer.collect_str(keypair.public_key())
+}
+
+fn default_region_params_fetches() -> usize {
+    1
+}
+
 fn default_listen() -> String {
     "127.0.0.1:1680".to_string()
 }
 
+fn default_listen_retry() -> RetryPolicy {
+    RetryPolicy {
+        max_attempts: 10,
+        base_delay: 1_000,
+        max_delay: 30_000,
+        jitter: 0.3,
+    }
+}
+
+fn default_uplink_time_sources() -> Vec<TimestampSource> {
+    vec![
+        TimestampSource::Gps,
+        TimestampSource::Counter,
+        TimestampSource::System,
+    ]
+}
+
 fn default_api() -> u16 {
     4467
 }
 
+fn default_reconnect_min_interval() -> u64 {
+    500
+}
+
+fn default_reconnect_retry() -> RetryPolicy {
+    RetryPolicy {
+        max_attempts: 10,
+        base_delay: 5_000,
+        max_delay: 1_800_000,
+        jitter: 0.3,
+    }
+}
+
+fn default_route_retry() -> RetryPolicy {
+    RetryPolicy {
+        max_attempts: 10,
+        base_delay: 1_000,
+        max_delay: 60_000,
+        jitter: 0.3,
+    }
+}
+
+fn default_sign_retry() -> RetryPolicy {
+    RetryPolicy {
+        max_attempts: 3,
+        base_delay: 100,
+        max_delay: 1_000,
+        jitter: 0.3,
+    }
+}
+
+fn default_dc_cap_window() -> u64 {
+    3600
+}
+
+fn default_capture_max_size() -> u64 {
+    10 * 1024 * 1024
+}
+
+fn default_empty_message_window() -> u64 {
+    10
+}
+
+fn default_unknown_region_hold() -> usize {
+    20
+}
+
+fn default_region_refresh_pause() -> u64 {
+    5000
+}
+
+fn default_route_timeout() -> u64 {
+    5000
+}
+
+fn default_tcp_nodelay() -> bool {
+    true
+}
+
+fn default_signature_cache() -> usize {
+    32
+}
+
+fn default_queue_time_buckets() -> Vec<u64> {
+    vec![10, 50, 100, 500, 1000, 5000, 30000]
+}
+
+fn default_tx_rf_chains() -> Vec<u64> {
+    vec![0]
+}
+
 fn default_poc_interval() -> u64 {
     // every 6 hours
     6 * 3600
@@ -209,7 +1013,10 @@ impl FromStr for StakingMode {
 }
 
 pub mod log_level {
-    use serde::de::{self, Deserialize, Deserializer, Visitor};
+    use serde::{
+        de::{self, Deserialize, Deserializer, Visitor},
+        Serialize, Serializer,
+    };
     use std::fmt;
 
     #[derive(Debug, Clone, Copy)]
@@ -227,6 +1034,12 @@ pub mod log_level {
         }
     }
 
+    impl Serialize for Level {
+        fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+            serializer.serialize_str(&self.0.as_str().to_lowercase())
+        }
+    }
+
     impl<'de> Deserialize<'de> for Level {
         fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
         where
@@ -265,7 +1078,10 @@ pub mod log_level {
 }
 
 pub mod log_method {
-    use serde::de::{self, Deserialize, Deserializer, Visitor};
+    use serde::{
+        de::{self, Deserialize, Deserializer, Visitor},
+        Serialize, Serializer,
+    };
     use std::fmt;
 
     /// The method to use for logging.
@@ -277,6 +1093,15 @@ pub mod log_method {
         Syslog,
     }
 
+    impl Serialize for LogMethod {
+        fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+            serializer.serialize_str(match self {
+                LogMethod::Stdio => "stdio",
+                LogMethod::Syslog => "syslog",
+            })
+        }
+    }
+
     impl<'de> Deserialize<'de> for LogMethod {
         fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
         where
@@ -310,3 +1135,173 @@ pub mod log_method {
         }
     }
 }
+
+/// Settings from the default configuration with a new keypair, for tests
+#[cfg(test)]
+pub(crate) fn mk_test_settings() -> Settings {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    static KEYS: AtomicUsize = AtomicUsize::new(0);
+    let key_path = test_key_path(&format!("test-{}", KEYS.fetch_add(1, Ordering::SeqCst)));
+    let settings = load_test_settings(&key_path, None);
+    let _ = std::fs::remove_file(&key_path);
+    settings
+}
+
+/// A key file path in the temp folder unique to the given test and process
+#[cfg(test)]
+fn test_key_path(test: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("gateway-rs-{test}-{}.key", std::process::id()))
+}
+
+/// Settings from the default configuration with the keypair at the given
+/// path, created if missing, and the given address, for tests
+#[cfg(test)]
+fn load_test_settings(key_path: &Path, address: Option<&str>) -> Settings {
+    Config::builder()
+        .add_source(File::from_str(
+            include_str!("../config/default.toml"),
+            config::FileFormat::Toml,
+        ))
+        .set_override("keypair", key_path.to_str().expect("key path"))
+        .and_then(|builder| builder.set_override_option("address", address))
+        .and_then(|builder| builder.build())
+        .and_then(|config| config.try_deserialize())
+        .expect("settings")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn location_valid() {
+        let location: LocationSettings =
+            serde_json::from_str(r#"{"lat": 37.77, "lon": -122.42, "elevation": 16}"#)
+                .expect("valid location");
+        assert_eq!(37.77, location.lat);
+        assert_eq!(-122.42, location.lon);
+        assert_eq!(Some(16), location.elevation);
+        assert_eq!("37.77,-122.42,16m", location.to_string());
+
+        let location: LocationSettings =
+            serde_json::from_str(r#"{"lat": -90, "lon": 180}"#).expect("valid location");
+        assert_eq!(None, location.elevation);
+    }
+
+    #[test]
+    fn location_invalid() {
+        assert!(serde_json::from_str::<LocationSettings>(r#"{"lat": 90.5, "lon": 0}"#).is_err());
+        assert!(serde_json::from_str::<LocationSettings>(r#"{"lat": 0, "lon": -181}"#).is_err());
+        assert!(serde_json::from_str::<LocationSettings>(r#"{"lat": "nan", "lon": 0}"#).is_err());
+    }
+
+    #[test]
+    fn check_address() {
+        let key_path = test_key_path("check_address");
+        let settings = load_test_settings(&key_path, None);
+        assert!(settings.check_address().is_ok());
+
+        let address = settings.keypair.public_key().to_string();
+        let settings = load_test_settings(&key_path, Some(&address));
+        assert!(settings.check_address().is_ok());
+
+        // A different key file no longer matches the configured address
+        let _ = std::fs::remove_file(&key_path);
+        let settings = load_test_settings(&key_path, Some(&address));
+        let _ = std::fs::remove_file(&key_path);
+        assert!(settings.check_address().is_err());
+
+        let settings = load_test_settings(&key_path, Some("not an address"));
+        let _ = std::fs::remove_file(&key_path);
+        assert!(settings.check_address().is_err());
+    }
+
+    #[test]
+    fn startup_summary() {
+        let key_path = test_key_path("startup_summary");
+        let mut settings = load_test_settings(&key_path, None);
+        let _ = std::fs::remove_file(&key_path);
+        settings.router.capture = Some(PathBuf::from("/tmp/capture"));
+
+        let summary = serde_json::to_value(settings.summary()).expect("summary");
+        assert_eq!(
+            serde_json::json!(settings.keypair.public_key().to_string()),
+            summary["key"]
+        );
+        assert_eq!(
+            serde_json::json!(settings.region.to_string()),
+            summary["region"]
+        );
+        assert_eq!(
+            settings.gateways.len(),
+            summary["gateways"].as_array().expect("gateways").len()
+        );
+        assert_eq!(serde_json::json!(["capture"]), summary["features"]);
+        // Nothing but the public key of the keypair is included
+        let exported = summary.to_string();
+        assert!(!exported.contains(key_path.to_str().expect("key path")));
+        assert!(!exported.contains("keypair"));
+    }
+
+    #[test]
+    fn capabilities() {
+        let mut settings = mk_test_settings();
+        let capabilities = settings.capabilities();
+        assert!(!capabilities.persistence);
+        assert!(!capabilities.capture);
+        assert!(capabilities.fan_out);
+        assert!(!capabilities.proxy);
+        assert_eq!(cfg!(feature = "tls"), capabilities.tls);
+        // Metrics are logged at debug level, below the default info level
+        assert!(!capabilities.metrics);
+        assert!(capabilities.hsm.contains(&"pkcs11"));
+        assert_eq!(
+            cfg!(feature = "ecc608"),
+            capabilities.hsm.contains(&"ecc608")
+        );
+        assert_eq!(cfg!(feature = "tpm"), capabilities.hsm.contains(&"tpm"));
+
+        settings.cache.store = Some(PathBuf::from("/tmp/store"));
+        settings.router.selection = SelectionPolicy::Failover;
+        settings.router.proxy = Some(Uri::from_static("http://proxy.local:3128"));
+        settings.log.level = serde_json::from_str("\"debug\"").expect("log level");
+        let capabilities = settings.capabilities();
+        assert!(capabilities.persistence);
+        assert!(!capabilities.fan_out);
+        assert!(capabilities.proxy);
+        assert!(capabilities.metrics);
+    }
+
+    #[test]
+    fn export_redacted() {
+        let key_path = test_key_path("export_redacted");
+        let mut settings = load_test_settings(&key_path, None);
+        settings.router.auth_tokens.push(RouterAuthToken {
+            pubkey: Arc::new(settings.keypair.public_key().clone()),
+            token: "s3cr3t-token".into(),
+        });
+        let mut exported = serde_json::to_value(&settings).expect("export settings");
+        assert_eq!(
+            serde_json::json!(settings.keypair.public_key().to_string()),
+            exported["keypair"]
+        );
+        assert_eq!(
+            serde_json::json!("<redacted>"),
+            exported["router"]["auth_tokens"][0]["token"]
+        );
+
+        // A redacted token is not loaded as the token itself
+        exported["keypair"] = serde_json::json!(key_path.to_str().expect("key path"));
+        assert!(serde_json::from_value::<Settings>(exported.clone()).is_err());
+        exported["router"]["auth_tokens"][0]["token"] = serde_json::json!("s3cr3t-token");
+
+        // Exported settings load back to the same settings once the redacted
+        // keypair and tokens are restored
+        let reloaded: Settings = serde_json::from_value(exported).expect("reload settings");
+        let _ = std::fs::remove_file(&key_path);
+        assert_eq!(
+            serde_json::to_value(&settings).expect("export settings"),
+            serde_json::to_value(&reloaded).expect("export reloaded")
+        );
+    }
+}
@@ -1,7 +1,7 @@
-use crate::{Error, Keypair, MsgSign, Packet, Region, Result};
+use crate::{Error, Keypair, MsgSign, Packet, PublicKey, Region, Result};
 use helium_proto::{
     blockchain_state_channel_message_v1::Msg, BlockchainStateChannelMessageV1,
-    BlockchainStateChannelPacketV1,
+    BlockchainStateChannelPacketV1, BlockchainStateChannelSummaryV1,
 };
 use std::sync::Arc;
 
@@ -30,6 +30,30 @@ impl StateChannelMessage {
         &self.0
     }
 
+    /// Returns the id of the state channel carried by a purchase or banner
+    /// message, if any.
+    pub fn sc_id(&self) -> Option<&[u8]> {
+        let sc = match &self.0 {
+            Msg::Purchase(purchase) => purchase.sc.as_ref(),
+            Msg::Banner(banner) => banner.sc.as_ref(),
+            _ => None,
+        };
+        sc.map(|sc| &sc.id[..])
+    }
+
+    /// Returns the summary for the given client in the state channel of a
+    /// purchase message, if any.
+    pub fn summary(&self, client: &PublicKey) -> Option<&BlockchainStateChannelSummaryV1> {
+        let sc = match &self.0 {
+            Msg::Purchase(purchase) => purchase.sc.as_ref()?,
+            _ => return None,
+        };
+        let client = client.to_vec();
+        sc.summaries
+            .iter()
+            .find(|summary| summary.client_pubkeybin == client)
+    }
+
     pub fn to_message(self) -> BlockchainStateChannelMessageV1 {
         BlockchainStateChannelMessageV1 { msg: Some(self.0) }
     }
@@ -67,3 +91,28 @@ impl From<StateChannelMessage> for BlockchainStateChannelPacketV1 {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use helium_proto::{
+        BlockchainStateChannelPurchaseV1, BlockchainStateChannelResponseV1,
+        BlockchainStateChannelV1,
+    };
+
+    #[test]
+    fn purchase_sc_id() {
+        let message = StateChannelMessage::from(Msg::Purchase(BlockchainStateChannelPurchaseV1 {
+            sc: Some(BlockchainStateChannelV1 {
+                id: b"channel_a".to_vec(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }));
+        assert_eq!(Some(&b"channel_a"[..]), message.sc_id());
+
+        let message =
+            StateChannelMessage::from(Msg::Response(BlockchainStateChannelResponseV1::default()));
+        assert_eq!(None, message.sc_id());
+    }
+}
@@ -0,0 +1,180 @@
+use super::StateChannelMessage;
+use crate::metrics::Counters;
+use helium_proto::{blockchain_state_channel_message_v1::Msg, BlockchainStateChannelSummaryV1};
+use serde::Serialize;
+
+/// Counts state channel outcomes of the packets sent to a router.
+///
+/// Exported like the other in-process metrics by logging their values
+/// periodically.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct StateChannelMetrics {
+    /// Packets offered to the router
+    pub offered: u64,
+    /// Packets the router accepted or purchased
+    pub accepted: u64,
+    /// Packets the router did not respond to
+    pub no_response: u64,
+    /// Packets the router responded to without accepting them
+    pub not_accepted: u64,
+    /// Packets the router rejected
+    pub rejected: u64,
+    /// Summaries of this gateway that failed validation
+    pub invalid_summary: u64,
+    /// Summaries of this gateway that count fewer packets than an earlier
+    /// summary in the same state channel
+    pub conflicts: u64,
+    /// Changes of the state channel the router reports packets in
+    pub rotations: u64,
+    #[serde(skip)]
+    sc_id: Option<Vec<u8>>,
+    #[serde(skip)]
+    summary: Option<(Vec<u8>, u64)>,
+}
+
+impl StateChannelMetrics {
+    /// Records the router response to an offered packet
+    pub fn record_response(&mut self, response: Option<&StateChannelMessage>) {
+        self.offered += 1;
+        let response = match response {
+            Some(response) => response,
+            None => {
+                self.no_response += 1;
+                return;
+            }
+        };
+        match response.msg() {
+            Msg::Response(response) if response.accepted => self.accepted += 1,
+            Msg::Response(_) => self.not_accepted += 1,
+            Msg::Purchase(_) => self.accepted += 1,
+            Msg::Reject(_) => self.rejected += 1,
+            _ => (),
+        }
+        if let Some(sc_id) = response.sc_id() {
+            match &self.sc_id {
+                Some(current) if current == sc_id => return,
+                Some(_) => self.rotations += 1,
+                None => (),
+            }
+            self.sc_id = Some(sc_id.to_vec());
+        }
+    }
+
+    /// Records a summary of this gateway that failed validation
+    pub fn record_invalid_summary(&mut self) {
+        self.invalid_summary += 1;
+    }
+
+    /// Records a valid summary of this gateway in the given state channel.
+    /// Returns whether it conflicts with an earlier summary in the same state
+    /// channel by counting fewer packets.
+    pub fn record_summary(
+        &mut self,
+        sc_id: &[u8],
+        summary: &BlockchainStateChannelSummaryV1,
+    ) -> bool {
+        let conflict = matches!(
+            &self.summary,
+            Some((id, num_packets)) if id == sc_id && summary.num_packets < *num_packets
+        );
+        if conflict {
+            self.conflicts += 1;
+        } else {
+            self.summary = Some((sc_id.to_vec(), summary.num_packets));
+        }
+        conflict
+    }
+
+    /// The current counter values, for logging
+    pub fn counters(&self) -> Counters {
+        Counters(vec![
+            ("offered", self.offered),
+            ("accepted", self.accepted),
+            ("no_response", self.no_response),
+            ("not_accepted", self.not_accepted),
+            ("rejected", self.rejected),
+            ("invalid_summary", self.invalid_summary),
+            ("conflicts", self.conflicts),
+            ("rotations", self.rotations),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use helium_proto::{
+        BlockchainStateChannelBannerV1, BlockchainStateChannelPurchaseV1,
+        BlockchainStateChannelRejectionV1, BlockchainStateChannelResponseV1,
+        BlockchainStateChannelV1,
+    };
+
+    fn mk_sc(id: &[u8]) -> Option<BlockchainStateChannelV1> {
+        Some(BlockchainStateChannelV1 {
+            id: id.to_vec(),
+            ..Default::default()
+        })
+    }
+
+    fn mk_summary(num_packets: u64) -> BlockchainStateChannelSummaryV1 {
+        BlockchainStateChannelSummaryV1 {
+            num_packets,
+            num_dcs: num_packets,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn lifecycle_counters() {
+        let mut metrics = StateChannelMetrics::default();
+        let responses = [
+            Some(Msg::Response(BlockchainStateChannelResponseV1 {
+                accepted: true,
+                ..Default::default()
+            })),
+            Some(Msg::Response(BlockchainStateChannelResponseV1::default())),
+            Some(Msg::Reject(BlockchainStateChannelRejectionV1::default())),
+            Some(Msg::Banner(BlockchainStateChannelBannerV1 {
+                sc: mk_sc(b"channel_a"),
+            })),
+            Some(Msg::Purchase(BlockchainStateChannelPurchaseV1 {
+                sc: mk_sc(b"channel_a"),
+                ..Default::default()
+            })),
+            Some(Msg::Purchase(BlockchainStateChannelPurchaseV1 {
+                sc: mk_sc(b"channel_b"),
+                ..Default::default()
+            })),
+            None,
+        ];
+        for response in responses {
+            let response = response.map(StateChannelMessage::from);
+            metrics.record_response(response.as_ref());
+        }
+        assert_eq!(7, metrics.offered);
+        assert_eq!(3, metrics.accepted);
+        assert_eq!(1, metrics.not_accepted);
+        assert_eq!(1, metrics.rejected);
+        assert_eq!(1, metrics.no_response);
+        // Moving from channel a to b is the only rotation
+        assert_eq!(1, metrics.rotations);
+
+        // A summary going backwards in the same channel conflicts
+        assert!(!metrics.record_summary(b"channel_b", &mk_summary(5)));
+        assert!(!metrics.record_summary(b"channel_b", &mk_summary(6)));
+        assert!(metrics.record_summary(b"channel_b", &mk_summary(4)));
+        assert!(!metrics.record_summary(b"channel_c", &mk_summary(1)));
+        assert_eq!(1, metrics.conflicts);
+
+        metrics.record_invalid_summary();
+        assert_eq!(1, metrics.invalid_summary);
+        assert_eq!(
+            Some(&("conflicts", 1)),
+            metrics
+                .counters()
+                .0
+                .iter()
+                .find(|(name, _)| *name == "conflicts")
+        );
+    }
+}
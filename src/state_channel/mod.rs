@@ -1,3 +1,11 @@
 mod message;
+mod metrics;
+mod signature_cache;
+mod signing_pool;
+mod summary;
 
 pub use message::StateChannelMessage;
+pub use metrics::StateChannelMetrics;
+pub use signature_cache::SignatureCache;
+pub use signing_pool::SigningPool;
+pub use summary::validate_summary;
@@ -0,0 +1,132 @@
+use crate::Result;
+use helium_proto::BlockchainStateChannelPacketV1;
+use std::{collections::VecDeque, future::Future};
+
+/// A bounded least recently used cache of signed state channel packets, keyed
+/// by a hash of the packet they carry.
+///
+/// Signing is slow on hardware keypairs. Caching the signed packet lets retries
+/// of the same packet be resent without signing it again. A resent packet
+/// carries the hold time of its first signing.
+#[derive(Debug)]
+pub struct SignatureCache {
+    capacity: usize,
+    entries: VecDeque<(Vec<u8>, BlockchainStateChannelPacketV1)>,
+}
+
+impl SignatureCache {
+    /// Construct a cache holding at most `capacity` signed packets. A capacity
+    /// of zero disables caching.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// Returns the cached signed packet for the given hash, or signs and
+    /// caches a new one using the given signing function.
+    pub async fn get_or_sign<F, Fut>(
+        &mut self,
+        hash: &[u8],
+        sign: F,
+    ) -> Result<BlockchainStateChannelPacketV1>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<BlockchainStateChannelPacketV1>>,
+    {
+        if let Some(position) = self.entries.iter().position(|(key, _)| key == hash) {
+            // Move to the most recently used position
+            let entry = self.entries.remove(position).expect("cache entry");
+            let packet = entry.1.clone();
+            self.entries.push_back(entry);
+            return Ok(packet);
+        }
+        let packet = sign().await?;
+        if self.capacity > 0 {
+            if self.entries.len() >= self.capacity {
+                self.entries.pop_front();
+            }
+            self.entries.push_back((hash.to_vec(), packet.clone()));
+        }
+        Ok(packet)
+    }
+
+    /// Whether a signed packet is cached for the given hash
+    pub fn contains(&self, hash: &[u8]) -> bool {
+        self.entries.iter().any(|(key, _)| key == hash)
+    }
+
+    /// Removes the signed packet for the given hash, for example once it has
+    /// been delivered.
+    pub fn remove(&mut self, hash: &[u8]) {
+        self.entries.retain(|(key, _)| key != hash);
+    }
+
+    /// Removes all signed packets, for example when the region they were
+    /// signed for changes.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mk_signed(signature: &[u8]) -> BlockchainStateChannelPacketV1 {
+        BlockchainStateChannelPacketV1 {
+            signature: signature.to_vec(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn retry_reuses_signature() {
+        let mut cache = SignatureCache::new(2);
+        let mut signs = 0;
+        let mut sends = vec![];
+        for _ in 0..2 {
+            let packet = cache
+                .get_or_sign(b"packet", || {
+                    signs += 1;
+                    async { Ok(mk_signed(b"signature")) }
+                })
+                .await
+                .expect("signed packet");
+            sends.push(packet);
+        }
+        assert_eq!(1, signs);
+        assert_eq!(2, sends.len());
+        assert_eq!(sends[0], sends[1]);
+
+        cache.remove(b"packet");
+        assert!(cache.is_empty());
+    }
+
+    #[tokio::test]
+    async fn evicts_least_recently_used() {
+        let mut cache = SignatureCache::new(2);
+        for key in [b"a", b"b", b"a", b"c"] {
+            cache
+                .get_or_sign(key, || async { Ok(mk_signed(key)) })
+                .await
+                .expect("signed packet");
+        }
+        assert_eq!(2, cache.len());
+        // "b" was least recently used and evicted when "c" was inserted
+        let packet = cache
+            .get_or_sign(b"b", || async { Ok(mk_signed(b"resigned")) })
+            .await
+            .expect("signed packet");
+        assert_eq!(b"resigned".to_vec(), packet.signature);
+    }
+}
@@ -0,0 +1,159 @@
+use crate::Result;
+use futures::TryFutureExt;
+use helium_proto::BlockchainStateChannelPacketV1;
+use std::{collections::VecDeque, future::Future};
+use tokio::task::JoinHandle;
+
+type SignTask = JoinHandle<Result<BlockchainStateChannelPacketV1>>;
+
+/// A bounded pool of background signing tasks.
+///
+/// Signing is slow on hardware keypairs. The pool lets up to `size` packets
+/// sign concurrently while handing out the signed packets in the order they
+/// were added, so the send order of the queue is preserved.
+pub struct SigningPool<T> {
+    size: usize,
+    pending: VecDeque<(T, Option<SignTask>)>,
+}
+
+impl<T> SigningPool<T> {
+    /// Construct a pool signing at most `size` packets at a time. A size of
+    /// zero is treated as one, which signs inline with sending.
+    pub fn new(size: usize) -> Self {
+        let size = size.max(1);
+        Self {
+            size,
+            pending: VecDeque::with_capacity(size),
+        }
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.pending.len() >= self.size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Adds an item and starts signing it in the background
+    pub fn push<F>(&mut self, item: T, sign: F)
+    where
+        F: Future<Output = Result<BlockchainStateChannelPacketV1>> + Send + 'static,
+    {
+        self.pending.push_back((item, Some(tokio::spawn(sign))));
+    }
+
+    /// Adds an item that needs no signing, for example because its signed
+    /// packet is already cached. It is handed out in order with the others.
+    pub fn push_unsigned(&mut self, item: T) {
+        self.pending.push_back((item, None));
+    }
+
+    /// Waits for the oldest item to finish signing and returns it with its
+    /// signed packet, if it was signed by the pool.
+    pub async fn next(&mut self) -> Option<(T, Option<Result<BlockchainStateChannelPacketV1>>)> {
+        let (item, task) = self.pending.pop_front()?;
+        let signed = match task {
+            Some(task) => Some(
+                task.map_err(|err| helium_crypto::Error::from(signature::Error::from_source(err)))
+                    .await
+                    .map_err(crate::Error::from)
+                    .and_then(|signed| signed),
+            ),
+            None => None,
+        };
+        Some((item, signed))
+    }
+
+    /// Cancels all signing tasks and returns their items in the order they
+    /// were added
+    pub fn cancel(&mut self) -> Vec<T> {
+        self.pending
+            .drain(..)
+            .map(|(item, task)| {
+                if let Some(task) = task {
+                    task.abort();
+                }
+                item
+            })
+            .collect()
+    }
+}
+
+impl<T> Drop for SigningPool<T> {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+    use tokio::time;
+
+    const SIGN_TIME: Duration = Duration::from_millis(50);
+
+    async fn slow_sign(id: u8) -> Result<BlockchainStateChannelPacketV1> {
+        time::sleep(SIGN_TIME).await;
+        Ok(BlockchainStateChannelPacketV1 {
+            signature: vec![id],
+            ..Default::default()
+        })
+    }
+
+    /// Signs and "sends" the given items through a pool of the given size,
+    /// returning the send order and the elapsed time
+    async fn sign_all(size: usize, items: &[u8]) -> (Vec<u8>, Duration) {
+        let start = Instant::now();
+        let mut pool = SigningPool::new(size);
+        let mut items = items.iter().copied();
+        let mut sent = vec![];
+        loop {
+            while !pool.is_full() {
+                match items.next() {
+                    Some(id) => pool.push(id, slow_sign(id)),
+                    None => break,
+                }
+            }
+            match pool.next().await {
+                Some((id, signed)) => {
+                    let signed = signed.expect("pool signed").expect("signed packet");
+                    assert_eq!(vec![id], signed.signature);
+                    sent.push(id);
+                }
+                None => break,
+            }
+        }
+        (sent, start.elapsed())
+    }
+
+    #[tokio::test]
+    async fn concurrent_signing() {
+        let items = [1, 2, 3, 4, 5, 6, 7, 8];
+        let (inline_order, inline_time) = sign_all(1, &items).await;
+        let (pool_order, pool_time) = sign_all(4, &items).await;
+        // Send order is preserved either way
+        assert_eq!(items.to_vec(), inline_order);
+        assert_eq!(items.to_vec(), pool_order);
+        assert!(inline_time >= SIGN_TIME * items.len() as u32);
+        // Four at a time takes about two signing times, with plenty of
+        // headroom for a slow test machine
+        assert!(pool_time < inline_time / 2);
+    }
+
+    #[tokio::test]
+    async fn cancel_returns_items_in_order() {
+        let mut pool = SigningPool::new(3);
+        pool.push(1, slow_sign(1));
+        pool.push_unsigned(2);
+        pool.push(3, slow_sign(3));
+        assert!(pool.is_full());
+        assert_eq!(vec![1, 2, 3], pool.cancel());
+        assert!(pool.is_empty());
+    }
+}
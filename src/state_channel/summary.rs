@@ -0,0 +1,70 @@
+use crate::{Error, PublicKey, Result};
+use helium_proto::BlockchainStateChannelSummaryV1;
+
+/// Validates a state channel summary before it is accepted.
+///
+/// A summary must cover at least one packet, must not account fewer data
+/// credits than packets since every packet costs at least one, and must name a
+/// valid client address.
+pub fn validate_summary(summary: &BlockchainStateChannelSummaryV1) -> Result {
+    if summary.num_packets == 0 {
+        return Err(Error::custom("summary has zero packets"));
+    }
+    if summary.num_dcs < summary.num_packets {
+        return Err(Error::custom(format!(
+            "summary dcs {} less than packets {}",
+            summary.num_dcs, summary.num_packets
+        )));
+    }
+    PublicKey::from_bytes(&summary.client_pubkeybin)
+        .map_err(|_| Error::custom("summary has invalid client address"))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keypair::mk_test_keypair;
+
+    fn mk_summary(num_packets: u64, num_dcs: u64) -> BlockchainStateChannelSummaryV1 {
+        BlockchainStateChannelSummaryV1 {
+            client_pubkeybin: mk_test_keypair().public_key().to_vec(),
+            num_packets,
+            num_dcs,
+        }
+    }
+
+    #[test]
+    fn valid_summary() {
+        validate_summary(&mk_summary(1, 1)).expect("valid summary");
+        validate_summary(&mk_summary(2, 5)).expect("valid summary");
+    }
+
+    #[test]
+    fn zero_packet_summary() {
+        assert!(matches!(
+            validate_summary(&mk_summary(0, 0)),
+            Err(Error::Custom(msg)) if msg == "summary has zero packets"
+        ));
+    }
+
+    #[test]
+    fn dc_mismatch_summary() {
+        assert!(matches!(
+            validate_summary(&mk_summary(3, 2)),
+            Err(Error::Custom(msg)) if msg == "summary dcs 2 less than packets 3"
+        ));
+    }
+
+    #[test]
+    fn invalid_address_summary() {
+        let summary = BlockchainStateChannelSummaryV1 {
+            client_pubkeybin: vec![0, 1, 2],
+            ..mk_summary(1, 1)
+        };
+        assert!(matches!(
+            validate_summary(&summary),
+            Err(Error::Custom(msg)) if msg == "summary has invalid client address"
+        ));
+    }
+}
@@ -1,20 +1,169 @@
-use crate::{Error, Result};
+use crate::{metrics::Counters, Error, Result};
 use slog::{warn, Logger};
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
 use tokio::sync::{mpsc, oneshot};
 
+/// Number of consecutive receives that find senders were blocked on a full
+/// channel before the overflow buffer grows
+const SUSTAINED_FULL: u32 = 3;
+
+#[derive(Debug)]
+pub struct MessageSender<T>(pub(crate) ChannelSender<T>);
 #[derive(Debug)]
-pub struct MessageSender<T>(pub(crate) mpsc::Sender<T>);
+pub struct MessageReceiver<T> {
+    rx: mpsc::Receiver<T>,
+    full_sends: Arc<AtomicU64>,
+    overflow: Option<Overflow<T>>,
+}
+
+/// The sending half of a message channel. Counts sends that find the channel
+/// full, so the receiver can tell sustained fullness.
 #[derive(Debug)]
-pub struct MessageReceiver<T>(mpsc::Receiver<T>);
+pub struct ChannelSender<T> {
+    tx: mpsc::Sender<T>,
+    full_sends: Arc<AtomicU64>,
+}
 
 pub fn message_channel<T>(size: usize) -> (MessageSender<T>, MessageReceiver<T>) {
     let (tx, rx) = mpsc::channel(size);
-    (MessageSender(tx), MessageReceiver(rx))
+    let full_sends = Arc::new(AtomicU64::new(0));
+    (
+        MessageSender(ChannelSender {
+            tx,
+            full_sends: full_sends.clone(),
+        }),
+        MessageReceiver {
+            rx,
+            full_sends,
+            overflow: None,
+        },
+    )
+}
+
+impl<T> ChannelSender<T> {
+    pub async fn send(&self, msg: T) -> std::result::Result<(), mpsc::error::SendError<T>> {
+        if self.tx.capacity() == 0 {
+            self.full_sends.fetch_add(1, Ordering::Relaxed);
+        }
+        self.tx.send(msg).await
+    }
+}
+
+impl<T> Clone for ChannelSender<T> {
+    fn clone(&self) -> Self {
+        Self {
+            tx: self.tx.clone(),
+            full_sends: self.full_sends.clone(),
+        }
+    }
+}
+
+/// Buffering statistics of an auto-tuned message channel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OverflowStats {
+    /// Number of messages currently buffered beyond the channel
+    pub buffered: usize,
+    /// Number of messages that may currently be buffered beyond the channel
+    pub limit: usize,
+    /// Maximum the limit may grow to
+    pub max: usize,
+    /// Number of times the limit grew
+    pub grown: u64,
+}
+
+impl OverflowStats {
+    /// The current values, for logging
+    pub fn counters(&self) -> Counters {
+        Counters(vec![
+            ("buffered", self.buffered as u64),
+            ("limit", self.limit as u64),
+            ("max", self.max as u64),
+            ("grown", self.grown),
+        ])
+    }
+}
+
+/// An overflow buffer the receiver moves waiting messages into, freeing
+/// channel slots for senders. The buffer starts out empty and grows by the
+/// channel size, up to a maximum, each time senders keep finding the channel
+/// full.
+#[derive(Debug)]
+struct Overflow<T> {
+    queue: VecDeque<T>,
+    step: usize,
+    limit: usize,
+    max: usize,
+    full_streak: u32,
+    grown: u64,
+}
+
+impl<T> Overflow<T> {
+    /// Records whether senders found the channel full since the last receive
+    /// and grows the limit once that is sustained
+    fn observe(&mut self, full: bool) {
+        if !full {
+            self.full_streak = 0;
+            return;
+        }
+        self.full_streak += 1;
+        if self.full_streak >= SUSTAINED_FULL && self.limit < self.max {
+            self.limit = (self.limit + self.step).min(self.max);
+            self.full_streak = 0;
+            self.grown += 1;
+        }
+    }
 }
 
 impl<T> MessageReceiver<T> {
+    /// Enables auto-tuning of the channel buffering. Once senders keep
+    /// finding the channel full, up to `max` more messages are buffered
+    /// beyond the channel size. Disabled if `max` is not set.
+    pub fn with_overflow(self, max: Option<usize>) -> Self {
+        let step = self.rx.max_capacity().max(1);
+        Self {
+            overflow: max.map(|max| Overflow {
+                queue: VecDeque::new(),
+                step,
+                limit: 0,
+                max,
+                full_streak: 0,
+                grown: 0,
+            }),
+            ..self
+        }
+    }
+
+    /// The buffering statistics if auto-tuning is enabled
+    pub fn overflow_stats(&self) -> Option<OverflowStats> {
+        self.overflow.as_ref().map(|overflow| OverflowStats {
+            buffered: overflow.queue.len(),
+            limit: overflow.limit,
+            max: overflow.max,
+            grown: overflow.grown,
+        })
+    }
+
     pub async fn recv(&mut self) -> Option<T> {
-        self.0.recv().await
+        if let Some(overflow) = self.overflow.as_mut() {
+            overflow.observe(self.full_sends.swap(0, Ordering::Relaxed) > 0);
+            // Buffered messages are older than the ones left in the channel
+            while overflow.queue.len() < overflow.limit {
+                match self.rx.try_recv() {
+                    Ok(msg) => overflow.queue.push_back(msg),
+                    Err(_) => break,
+                }
+            }
+            if let Some(msg) = overflow.queue.pop_front() {
+                return Some(msg);
+            }
+        }
+        self.rx.recv().await
     }
 }
 
@@ -47,3 +196,51 @@ impl<T> ResponseReceiver<T> {
         self.0.await.map_err(|_| Error::channel())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn overflow_grows_to_max() {
+        let (tx, rx) = message_channel::<u32>(2);
+        let mut rx = rx.with_overflow(Some(5));
+        let mut sent = 0;
+        let mut received = vec![];
+        // Keep the channel full, receiving one message per round
+        for _ in 0..12 {
+            while tx.0.tx.capacity() > 0 {
+                tx.0.send(sent).await.expect("send");
+                sent += 1;
+            }
+            // A sender finding the channel full
+            let blocked = tx.clone();
+            let msg = sent;
+            sent += 1;
+            let send = tokio::spawn(async move { blocked.0.send(msg).await });
+            tokio::task::yield_now().await;
+            received.push(rx.recv().await.expect("message"));
+            send.await.expect("send task").expect("send");
+        }
+        let stats = rx.overflow_stats().expect("overflow stats");
+        // Grows by the channel size every three full receives, capped at 5
+        assert_eq!(5, stats.limit);
+        assert_eq!(3, stats.grown);
+        assert!(stats.buffered <= stats.limit);
+
+        // Messages stay in send order
+        drop(tx);
+        while let Some(msg) = rx.recv().await {
+            received.push(msg);
+        }
+        assert_eq!((0..sent).collect::<Vec<u32>>(), received);
+    }
+
+    #[tokio::test]
+    async fn no_overflow_by_default() {
+        let (tx, mut rx) = message_channel::<u32>(1);
+        tx.0.send(1).await.expect("send");
+        assert_eq!(None, rx.overflow_stats());
+        assert_eq!(Some(1), rx.recv().await);
+    }
+}
@@ -1,7 +1,7 @@
 use crate::{curl, releases, settings, Error, Future, Result, Stream};
 use futures::{future, stream, FutureExt, StreamExt, TryFutureExt};
 use semver::{Identifier, Version};
-use serde::{de, Deserialize, Deserializer};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 use std::{fmt, path::Path, str::FromStr};
 use tokio::process;
 
@@ -106,6 +106,12 @@ impl<'de> Deserialize<'de> for Channel {
     }
 }
 
+impl Serialize for Channel {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
 impl fmt::Display for Channel {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let s = match self {